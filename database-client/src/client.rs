@@ -0,0 +1,561 @@
+use crate::error::{Error, Result};
+use crate::retry::RetryPolicy;
+use reqwest::{RequestBuilder, Response, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+
+const SCAN_PAGE_SIZE: usize = 500;
+const WATCH_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Entry<T = Value> {
+    pub key: String,
+    pub value: T,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Set,
+    Delete,
+    Expire,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Change {
+    pub offset: u64,
+    pub timestamp_ms: u64,
+    pub kind: ChangeKind,
+    pub key: String,
+    pub value: Option<Value>,
+}
+
+impl Change {
+    // The value of a set change decoded as `T`, `None` for deletes and expiries.
+    pub fn value_as<T: DeserializeOwned>(&self) -> Result<Option<T>> {
+        match &self.value {
+            Some(value) => Ok(Some(T::deserialize(value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ScanPage<T> {
+    version: u64,
+    entries: Vec<Entry<T>>,
+    next: Option<String>,
+}
+
+// The application/problem+json body the server sends with every error.
+#[derive(Deserialize)]
+struct Problem {
+    code: String,
+    detail: String,
+}
+
+#[derive(Deserialize)]
+struct ChangesBatch {
+    changes: Vec<Change>,
+    next: u64,
+}
+
+pub struct ClientBuilder {
+    url: String,
+    retry: RetryPolicy,
+    timeout: Duration,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Duration,
+}
+
+impl ClientBuilder {
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Connections are kept open and reused between requests; these bound how
+    // many stay idle and for how long.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    pub fn build(self) -> Result<Client> {
+        let base = Url::parse(&self.url).map_err(|_| Error::InvalidUrl(self.url.clone()))?;
+        if base.cannot_be_a_base() {
+            return Err(Error::InvalidUrl(self.url));
+        }
+
+        let http = reqwest::Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .build()?;
+
+        Ok(Client {
+            http,
+            base,
+            retry: self.retry,
+            timeout: self.timeout,
+        })
+    }
+}
+
+// Cloning is cheap and clones share one connection pool.
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    base: Url,
+    retry: RetryPolicy,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(url: &str) -> Result<Client> {
+        Self::builder(url).build()
+    }
+
+    pub fn builder(url: &str) -> ClientBuilder {
+        ClientBuilder {
+            url: url.to_string(),
+            retry: RetryPolicy::default(),
+            timeout: Duration::from_secs(10),
+            pool_max_idle_per_host: 16,
+            pool_idle_timeout: Duration::from_secs(90),
+        }
+    }
+
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("base url was checked in build")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    async fn send(request: RequestBuilder) -> Result<Response> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        let (code, message) = match serde_json::from_str::<Problem>(&body) {
+            Ok(problem) => (Some(problem.code), problem.detail),
+            Err(_) => (None, body),
+        };
+        Err(Error::Server {
+            status: status.as_u16(),
+            code,
+            message,
+        })
+    }
+
+    async fn send_with_retry(&self, request: impl Fn() -> RequestBuilder) -> Result<Response> {
+        self.retry
+            .run(|| Self::send(request().timeout(self.timeout)))
+            .await
+    }
+
+    // `None` when the key has no value.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        self.get_with(key, &[]).await
+    }
+
+    // Reads the part of a JSON document at a path such as `$.user.email`.
+    pub async fn get_path<T: DeserializeOwned>(&self, key: &str, path: &str) -> Result<Option<T>> {
+        self.get_with(key, &[("path", path)]).await
+    }
+
+    async fn get_with<T: DeserializeOwned>(
+        &self,
+        key: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<T>> {
+        let url = self.url(&["keys", key]);
+        let result = self
+            .send_with_retry(|| self.http.get(url.clone()).query(query))
+            .await;
+
+        match result {
+            Ok(response) => Ok(Some(serde_json::from_slice(&response.bytes().await?)?)),
+            Err(Error::Server { status: 404, .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Returns the version the write created.
+    pub async fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<u64> {
+        self.set_with(key, value, None).await
+    }
+
+    pub async fn set_with_ttl<T: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<u64> {
+        self.set_with(key, value, Some(ttl_secs(ttl))).await
+    }
+
+    async fn set_with<T: Serialize>(&self, key: &str, value: &T, ttl: Option<u64>) -> Result<u64> {
+        let url = self.url(&["keys", key]);
+        let body = serde_json::to_vec(value)?;
+        let query: Vec<(&str, u64)> = ttl.map(|ttl| ("ttl", ttl)).into_iter().collect();
+
+        let response = self
+            .send_with_retry(|| {
+                self.http
+                    .put(url.clone())
+                    .query(&query)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+            })
+            .await?;
+
+        Ok(version_of(&response))
+    }
+
+    // Returns the version of the delete, or `None` when there was no value.
+    // A retried delete whose first attempt did reach the server also reports
+    // `None`.
+    pub async fn delete(&self, key: &str) -> Result<Option<u64>> {
+        let url = self.url(&["keys", key]);
+        let result = self.send_with_retry(|| self.http.delete(url.clone())).await;
+
+        match result {
+            Ok(response) => Ok(Some(version_of(&response))),
+            Err(Error::Server { status: 404, .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // All keys starting with `prefix`, read page by page from one snapshot.
+    pub async fn scan<T: DeserializeOwned>(&self, prefix: &str) -> Result<Vec<Entry<T>>> {
+        let url = self.url(&["keys"]);
+        let mut entries = vec![];
+        let mut after: Option<String> = None;
+        let mut as_of: Option<u64> = None;
+
+        loop {
+            let mut query = vec![
+                ("prefix", prefix.to_string()),
+                ("limit", SCAN_PAGE_SIZE.to_string()),
+            ];
+            if let Some(after) = &after {
+                query.push(("after", after.clone()));
+            }
+            if let Some(as_of) = as_of {
+                query.push(("as_of", as_of.to_string()));
+            }
+
+            let response = self
+                .send_with_retry(|| self.http.get(url.clone()).query(&query))
+                .await?;
+            let page: ScanPage<T> = serde_json::from_slice(&response.bytes().await?)?;
+
+            entries.extend(page.entries);
+            as_of = Some(page.version);
+            after = page.next;
+            if after.is_none() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    // Follows the change feed starting after offset `since`; use 0 for the
+    // start of the retained log.
+    pub fn watch(&self, since: u64) -> Watch {
+        Watch {
+            client: self.clone(),
+            since,
+            buffered: Vec::new(),
+        }
+    }
+
+    async fn changes(&self, since: u64) -> Result<ChangesBatch> {
+        let url = self.url(&["changes"]);
+        let query = [("since", since), ("wait", WATCH_WAIT.as_secs())];
+
+        let response = self
+            .send_with_retry(|| {
+                self.http
+                    .get(url.clone())
+                    .query(&query)
+                    .timeout(WATCH_WAIT + self.timeout)
+            })
+            .await?;
+
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+}
+
+fn version_of(response: &Response) -> u64 {
+    response
+        .headers()
+        .get("x-version")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default()
+}
+
+// The server takes whole seconds. Rounding up keeps a sub-second TTL from
+// being sent as 0.
+fn ttl_secs(ttl: Duration) -> u64 {
+    ttl.as_secs()
+        .saturating_add(u64::from(ttl.subsec_nanos() > 0))
+}
+
+// A position in the change feed. `offset` can be saved and passed to
+// `Client::watch` later to resume exactly after the last change seen.
+pub struct Watch {
+    client: Client,
+    since: u64,
+    buffered: Vec<Change>,
+}
+
+impl Watch {
+    pub fn offset(&self) -> u64 {
+        self.since
+    }
+
+    // Waits for the next change. A `410` server error means the log no longer
+    // reaches back to `offset` and the consumer has to resynchronise.
+    pub async fn next(&mut self) -> Result<Change> {
+        loop {
+            if let Some(change) = self.buffered.pop() {
+                self.since = change.offset;
+                return Ok(change);
+            }
+
+            let batch = self.client.changes(self.since).await?;
+            self.buffered = batch.changes.into_iter().rev().collect();
+            if self.buffered.is_empty() {
+                self.since = batch.next;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Json, Router,
+        extract::{Path, Query},
+        http::StatusCode,
+        routing::get,
+    };
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::net::TcpListener;
+
+    async fn serve(app: Router) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    fn fast_retries() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct User {
+        name: String,
+    }
+
+    #[test]
+    fn urls_escape_keys() -> Result<()> {
+        let client = Client::new("http://localhost:4000/db/")?;
+
+        assert_eq!(
+            client.url(&["keys", "a/b c"]).as_str(),
+            "http://localhost:4000/db/keys/a%2Fb%20c"
+        );
+        assert!(matches!(
+            Client::new("not a url"),
+            Err(Error::InvalidUrl(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ttls_round_up_to_whole_seconds() {
+        assert_eq!(ttl_secs(Duration::from_millis(1)), 1);
+        assert_eq!(ttl_secs(Duration::from_millis(1500)), 2);
+        assert_eq!(ttl_secs(Duration::from_secs(3)), 3);
+        assert_eq!(ttl_secs(Duration::ZERO), 0);
+    }
+
+    #[tokio::test]
+    async fn get_decodes_typed_values() -> Result<()> {
+        let app = Router::new().route(
+            "/keys/{key}",
+            get(|Path(key): Path<String>| async move {
+                match key.as_str() {
+                    "user" => Ok(Json(json!({"name": "ann"}))),
+                    _ => Err((StatusCode::NOT_FOUND, "no value set")),
+                }
+            }),
+        );
+        let client = Client::new(&serve(app).await)?;
+
+        assert_eq!(
+            client.get::<User>("user").await?,
+            Some(User {
+                name: "ann".to_string()
+            })
+        );
+        assert_eq!(client.get::<User>("missing").await?, None);
+        assert!(matches!(
+            client.get::<u64>("user").await,
+            Err(Error::Decode(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unavailable_server_is_retried() -> Result<()> {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        let app = Router::new().route(
+            "/keys/{key}",
+            get(move || {
+                let counter = counter.clone();
+                async move {
+                    match counter.fetch_add(1, Ordering::SeqCst) {
+                        0 | 1 => Err((StatusCode::SERVICE_UNAVAILABLE, "busy")),
+                        _ => Ok(Json(json!(7))),
+                    }
+                }
+            }),
+        );
+        let client = Client::builder(&serve(app).await)
+            .retry(fast_retries())
+            .build()?;
+
+        assert_eq!(client.get::<u64>("a").await?, Some(7));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() -> Result<()> {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        let app = Router::new().route(
+            "/keys/{key}",
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { (StatusCode::BAD_REQUEST, "bad path") }
+            }),
+        );
+        let client = Client::builder(&serve(app).await)
+            .retry(fast_retries())
+            .build()?;
+
+        let result = client.get::<Value>("a").await;
+
+        assert!(matches!(result, Err(Error::Server { status: 400, .. })));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn problem_codes_are_parsed() -> Result<()> {
+        let app = Router::new().route(
+            "/keys/{key}",
+            get(|| async {
+                let problem = json!({
+                    "type": "urn:database-server:problem:invalid_path",
+                    "title": "Invalid JSON path",
+                    "status": 400,
+                    "detail": "path must start with '$'",
+                    "code": "invalid_path",
+                });
+                (StatusCode::BAD_REQUEST, Json(problem))
+            }),
+        );
+        let client = Client::new(&serve(app).await)?;
+
+        let result = client.get_path::<Value>("a", "a.b").await;
+
+        match result {
+            Err(Error::Server {
+                status: 400,
+                code: Some(code),
+                message,
+            }) => {
+                assert_eq!(code, "invalid_path");
+                assert_eq!(message, "path must start with '$'");
+            }
+            other => panic!("expected a problem, got {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn watch_resumes_from_offset() -> Result<()> {
+        let app = Router::new().route(
+            "/changes",
+            get(
+                |Query(q): Query<HashMap<String, u64>>| async move {
+                    let since = q.get("since").copied().unwrap_or(0);
+                    let changes: Vec<Value> = (since + 1..=since + 2)
+                        .map(|offset| {
+                            json!({"offset": offset, "timestamp_ms": 0, "kind": "set", "key": "k", "value": offset})
+                        })
+                        .collect();
+                    Json(json!({"changes": changes, "next": since + 2}))
+                },
+            ),
+        );
+        let client = Client::new(&serve(app).await)?;
+        let mut watch = client.watch(10);
+
+        let offsets = [
+            watch.next().await?.offset,
+            watch.next().await?.offset,
+            watch.next().await?.offset,
+        ];
+
+        assert_eq!(offsets, [11, 12, 13]);
+        assert_eq!(watch.offset(), 13);
+        assert_eq!(
+            client
+                .watch(watch.offset())
+                .next()
+                .await?
+                .value_as::<u64>()?,
+            Some(14)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidUrl(String),
+    // The request never got an answer: connection refused, reset or timed out.
+    Transport(reqwest::Error),
+    // The server answered with a status the client does not handle itself.
+    // `code` is the stable error code from the problem document, when the
+    // response had one.
+    Server {
+        status: u16,
+        code: Option<String>,
+        message: String,
+    },
+    // The response or the stored value did not match the expected type.
+    Decode(serde_json::Error),
+}
+
+impl Error {
+    // Whether sending the same request again might succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Transport(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            Error::Server { status, .. } => matches!(status, 502..=504),
+            Error::InvalidUrl(_) | Error::Decode(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidUrl(url) => write!(f, "invalid server url {}", url),
+            Error::Transport(e) => write!(f, "request failed: {}", e),
+            Error::Server {
+                status,
+                code: Some(code),
+                message,
+            } => write!(f, "server responded {} {}: {}", status, code, message),
+            Error::Server {
+                status,
+                code: None,
+                message,
+            } => write!(f, "server responded {}: {}", status, message),
+            Error::Decode(e) => write!(f, "could not decode response: {}", e),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::InvalidUrl(_) | Error::Server { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Transport(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Decode(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
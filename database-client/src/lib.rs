@@ -0,0 +1,22 @@
+//! Async client for database-server.
+//!
+//! ```no_run
+//! # async fn example() -> database_client::Result<()> {
+//! let client = database_client::Client::new("http://localhost:4000")?;
+//!
+//! client.set("user", &serde_json::json!({"name": "ann"})).await?;
+//! let name: Option<String> = client.get_path("user", "$.name").await?;
+//!
+//! let mut watch = client.watch(0);
+//! let change = watch.next().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod client;
+mod error;
+mod retry;
+
+pub use crate::client::{Change, ChangeKind, Client, ClientBuilder, Entry, Watch};
+pub use crate::error::{Error, Result};
+pub use crate::retry::RetryPolicy;
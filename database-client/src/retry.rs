@@ -0,0 +1,112 @@
+use crate::error::Result;
+use std::future::Future;
+use std::time::Duration;
+
+// Retries use exponential backoff: `initial_backoff`, then twice that, and so
+// on, never waiting longer than `max_backoff` between attempts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    pub async fn run<T, F, Fut>(&self, mut attempt: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_retryable() && retry < self.max_retries => {
+                    tokio::time::sleep(self.backoff(retry)).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::cell::Cell;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+
+        let waits: Vec<u128> = (0..5).map(|r| policy.backoff(r).as_millis()).collect();
+
+        assert_eq!(waits, vec![100, 200, 400, 500, 500]);
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn retries_only_retryable_errors() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+
+        let attempts = Cell::new(0);
+        let result: Result<()> = policy
+            .run(|| async {
+                attempts.set(attempts.get() + 1);
+                Err(Error::Server {
+                    status: 503,
+                    code: None,
+                    message: "unavailable".to_string(),
+                })
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 4);
+
+        attempts.set(0);
+        let result: Result<()> = policy
+            .run(|| async {
+                attempts.set(attempts.get() + 1);
+                Err(Error::Server {
+                    status: 400,
+                    code: None,
+                    message: "bad request".to_string(),
+                })
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
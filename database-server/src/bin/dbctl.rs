@@ -0,0 +1,727 @@
+use anyhow::{Context as _, bail};
+use clap::{Parser, Subcommand, ValueEnum};
+use reqwest::blocking::{Client as HttpClient, RequestBuilder, Response};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// dbctl set user '{"name":"ann"}'
+// dbctl get user --path '$.name'
+// dbctl --output json scan --prefix user
+// dbctl export > backup.jsonl && dbctl import backup.jsonl
+// dbctl          (no subcommand starts the interactive shell)
+
+const IMPORT_BATCH_SIZE: usize = 1_000;
+const SCAN_PAGE_SIZE: usize = 500;
+const COMPLETION_LIMIT: usize = 50;
+const WATCH_WAIT_SECS: u64 = 30;
+const HISTORY_FILE: &str = ".dbctl_history";
+
+#[derive(Parser, Debug)]
+#[command(name = "dbctl", about = "Command-line client for database-server")]
+struct Cli {
+    #[arg(long, env = "DBCTL_URL", default_value = "http://localhost:4000")]
+    url: String,
+
+    #[arg(long, value_enum, default_value_t = Output::Table)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+enum Command {
+    /// Read a key, or part of a JSON document with --path
+    Get {
+        key: String,
+        #[arg(long)]
+        path: Option<String>,
+        #[arg(long)]
+        as_of: Option<u64>,
+    },
+    /// Write a key. The value is stored as JSON when it parses as JSON
+    Set {
+        key: String,
+        value: String,
+        #[arg(long)]
+        ttl: Option<u64>,
+        /// Store the value as a string even if it parses as JSON
+        #[arg(long)]
+        string: bool,
+    },
+    /// Delete a key
+    Del { key: String },
+    /// List keys and values
+    Scan {
+        #[arg(long, default_value = "")]
+        prefix: String,
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Follow the change feed
+    Watch {
+        #[arg(long, default_value_t = 0)]
+        since: u64,
+    },
+    /// Write keys and values as JSON lines
+    Export {
+        #[arg(long, default_value = "")]
+        prefix: String,
+        file: Option<PathBuf>,
+    },
+    /// Read JSON lines written by export
+    Import { file: Option<PathBuf> },
+    /// Start the interactive shell
+    Shell,
+}
+
+// The shell reuses the subcommand parser for each line it reads.
+#[derive(Parser, Debug)]
+#[command(no_binary_name = true, name = "", disable_help_flag = true)]
+struct ShellLine {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Entry {
+    key: String,
+    value: Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScanPage {
+    version: u64,
+    entries: Vec<Entry>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChangesBatch {
+    changes: Vec<Change>,
+    next: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Change {
+    offset: u64,
+    kind: String,
+    key: String,
+    value: Option<Value>,
+}
+
+// The application/problem+json body the server sends with every error.
+#[derive(Deserialize, Debug)]
+struct Problem {
+    detail: String,
+    code: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct MsetResponse {
+    results: Vec<MsetResult>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MsetResult {
+    key: String,
+    error: Option<String>,
+}
+
+struct Client {
+    http: HttpClient,
+    url: String,
+    base: reqwest::Url,
+}
+
+impl Client {
+    fn new(url: &str) -> anyhow::Result<Self> {
+        let base = reqwest::Url::parse(url).with_context(|| format!("invalid url {}", url))?;
+        // Key urls are built by appending path segments, see `key_url`.
+        if base.cannot_be_a_base() {
+            bail!("invalid url {}: not an http url", url);
+        }
+
+        let http = HttpClient::builder()
+            .timeout(Duration::from_secs(WATCH_WAIT_SECS + 10))
+            .build()?;
+
+        Ok(Client {
+            http,
+            url: url.trim_end_matches('/').to_string(),
+            base,
+        })
+    }
+
+    fn key_url(&self, key: &str) -> reqwest::Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("base url was checked in new")
+            .pop_if_empty()
+            .extend(["keys", key]);
+        url
+    }
+
+    fn send(request: RequestBuilder) -> anyhow::Result<Response> {
+        let response = request.send()?;
+        match response.status().is_success() {
+            true => Ok(response),
+            false => {
+                let status = response.status();
+                let body = response.text().unwrap_or_default();
+                match serde_json::from_str::<Problem>(&body) {
+                    Ok(problem) => bail!("{} ({})", problem.detail, problem.code),
+                    Err(_) => bail!("{}: {}", status, body.trim()),
+                }
+            }
+        }
+    }
+
+    fn get(&self, key: &str, path: Option<&str>, as_of: Option<u64>) -> anyhow::Result<Value> {
+        let mut query = vec![];
+        if let Some(path) = path {
+            query.push(("path", path.to_string()));
+        }
+        if let Some(as_of) = as_of {
+            query.push(("as_of", as_of.to_string()));
+        }
+
+        let response = Self::send(self.http.get(self.key_url(key)).query(&query))?;
+        Ok(response.json()?)
+    }
+
+    fn set(&self, key: &str, value: &Value, ttl: Option<u64>) -> anyhow::Result<u64> {
+        let query: Vec<(&str, u64)> = ttl.map(|ttl| ("ttl", ttl)).into_iter().collect();
+        let response = Self::send(self.http.put(self.key_url(key)).query(&query).json(value))?;
+        Ok(version_of(&response))
+    }
+
+    fn delete(&self, key: &str) -> anyhow::Result<u64> {
+        let response = Self::send(self.http.delete(self.key_url(key)))?;
+        Ok(version_of(&response))
+    }
+
+    fn scan_page(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+        as_of: Option<u64>,
+    ) -> anyhow::Result<ScanPage> {
+        let mut query = vec![("prefix", prefix.to_string()), ("limit", limit.to_string())];
+        if let Some(after) = after {
+            query.push(("after", after.to_string()));
+        }
+        if let Some(as_of) = as_of {
+            query.push(("as_of", as_of.to_string()));
+        }
+
+        let url = format!("{}/keys", self.url);
+        Ok(Self::send(self.http.get(url).query(&query))?.json()?)
+    }
+
+    // Follows `next` until the scan is exhausted, pinning every page to the
+    // snapshot of the first one.
+    fn scan(&self, prefix: &str, limit: Option<usize>) -> anyhow::Result<Vec<Entry>> {
+        let mut entries = vec![];
+        let mut after = None;
+        let mut as_of = None;
+
+        loop {
+            let remaining = limit.map_or(SCAN_PAGE_SIZE, |l| l - entries.len());
+            let page = self.scan_page(
+                prefix,
+                after.as_deref(),
+                remaining.min(SCAN_PAGE_SIZE),
+                as_of,
+            )?;
+            entries.extend(page.entries);
+            as_of = Some(page.version);
+            after = page.next;
+
+            if after.is_none() || limit.is_some_and(|l| entries.len() >= l) {
+                return Ok(entries);
+            }
+        }
+    }
+
+    fn changes(&self, since: u64) -> anyhow::Result<ChangesBatch> {
+        let url = format!("{}/changes", self.url);
+        let query = [("since", since), ("wait", WATCH_WAIT_SECS)];
+        Ok(Self::send(self.http.get(url).query(&query))?.json()?)
+    }
+
+    fn mset(&self, entries: &[Entry]) -> anyhow::Result<MsetResponse> {
+        let url = format!("{}/mset", self.url);
+        let body = json!({ "entries": entries });
+        Ok(Self::send(self.http.post(url).json(&body))?.json()?)
+    }
+}
+
+fn version_of(response: &Response) -> u64 {
+    response
+        .headers()
+        .get("x-version")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default()
+}
+
+fn parse_value(value: &str, string: bool) -> Value {
+    match string {
+        true => Value::String(value.to_string()),
+        false => serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())),
+    }
+}
+
+// Strings are shown without quotes in tables, everything else as compact JSON.
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn render_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+    let mut lines = vec![render_row(headers, &widths), render_row(&rule, &widths)];
+    for row in rows {
+        lines.push(render_row(row, &widths));
+    }
+    lines.join("\n")
+}
+
+fn render_row<S: AsRef<str>>(cells: &[S], widths: &[usize]) -> String {
+    let padded: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!("{:<width$}", cell.as_ref(), width = width))
+        .collect();
+    padded.join("  ").trim_end().to_string()
+}
+
+fn print_entries(entries: &[Entry], output: Output) -> anyhow::Result<()> {
+    match output {
+        Output::Json => println!("{}", serde_json::to_string_pretty(entries)?),
+        Output::Table => {
+            let rows: Vec<Vec<String>> = entries
+                .iter()
+                .map(|e| vec![e.key.clone(), display_value(&e.value)])
+                .collect();
+            println!("{}", render_table(&["KEY", "VALUE"], &rows));
+        }
+    }
+    Ok(())
+}
+
+fn print_version(key: &str, version: u64, output: Output) {
+    match output {
+        Output::Json => println!("{}", json!({ "key": key, "version": version })),
+        Output::Table => println!(
+            "{}",
+            render_table(
+                &["KEY", "VERSION"],
+                &[vec![key.to_string(), version.to_string()]]
+            )
+        ),
+    }
+}
+
+fn run(client: &Client, command: Command, output: Output) -> anyhow::Result<()> {
+    match command {
+        Command::Get { key, path, as_of } => {
+            let value = client.get(&key, path.as_deref(), as_of)?;
+            print_entries(&[Entry { key, value }], output)
+        }
+        Command::Set {
+            key,
+            value,
+            ttl,
+            string,
+        } => {
+            let version = client.set(&key, &parse_value(&value, string), ttl)?;
+            print_version(&key, version, output);
+            Ok(())
+        }
+        Command::Del { key } => {
+            let version = client.delete(&key)?;
+            print_version(&key, version, output);
+            Ok(())
+        }
+        Command::Scan { prefix, limit } => print_entries(&client.scan(&prefix, limit)?, output),
+        Command::Watch { since } => watch(client, since, output),
+        Command::Export { prefix, file } => export(client, &prefix, file),
+        Command::Import { file } => import(client, file),
+        Command::Shell => bail!("already in the shell"),
+    }
+}
+
+// Changes arrive one batch at a time, so table rows use fixed column widths
+// instead of being sized to their contents.
+const WATCH_WIDTHS: [usize; 4] = [8, 6, 24, 0];
+
+fn watch(client: &Client, mut since: u64, output: Output) -> anyhow::Result<()> {
+    if output == Output::Table {
+        println!(
+            "{}",
+            render_row(&["OFFSET", "KIND", "KEY", "VALUE"], &WATCH_WIDTHS)
+        );
+    }
+
+    loop {
+        let batch = client.changes(since)?;
+        for change in &batch.changes {
+            match output {
+                Output::Json => println!("{}", serde_json::to_string(change)?),
+                Output::Table => {
+                    let cells = [
+                        change.offset.to_string(),
+                        change.kind.clone(),
+                        change.key.clone(),
+                        change.value.as_ref().map(display_value).unwrap_or_default(),
+                    ];
+                    println!("{}", render_row(&cells, &WATCH_WIDTHS));
+                }
+            }
+        }
+        since = batch.next;
+    }
+}
+
+fn export(client: &Client, prefix: &str, file: Option<PathBuf>) -> anyhow::Result<()> {
+    let entries = client.scan(prefix, None)?;
+
+    let mut writer: Box<dyn Write> = match &file {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    for entry in &entries {
+        writeln!(writer, "{}", serde_json::to_string(entry)?)?;
+    }
+    writer.flush()?;
+
+    eprintln!("exported {} keys", entries.len());
+    Ok(())
+}
+
+fn import(client: &Client, file: Option<PathBuf>) -> anyhow::Result<()> {
+    let reader: Box<dyn BufRead> = match &file {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    let mut batch = vec![];
+    let mut imported = 0;
+    let mut failed = 0;
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line).with_context(|| {
+            format!("line {} is not a {{\"key\", \"value\"}} object", number + 1)
+        })?;
+        batch.push(entry);
+
+        if batch.len() == IMPORT_BATCH_SIZE {
+            (imported, failed) = import_batch(client, &mut batch, imported, failed)?;
+        }
+    }
+    (imported, failed) = import_batch(client, &mut batch, imported, failed)?;
+
+    eprintln!("imported {} keys, {} failed", imported, failed);
+    match failed {
+        0 => Ok(()),
+        _ => bail!("{} keys could not be imported", failed),
+    }
+}
+
+fn import_batch(
+    client: &Client,
+    batch: &mut Vec<Entry>,
+    imported: usize,
+    failed: usize,
+) -> anyhow::Result<(usize, usize)> {
+    if batch.is_empty() {
+        return Ok((imported, failed));
+    }
+
+    let response = client.mset(batch)?;
+    batch.clear();
+
+    let mut failed_now = 0;
+    for result in &response.results {
+        if let Some(error) = &result.error {
+            eprintln!("{}: {}", result.key, error);
+            failed_now += 1;
+        }
+    }
+    Ok((
+        imported + response.results.len() - failed_now,
+        failed + failed_now,
+    ))
+}
+
+// Splits a shell line on whitespace, keeping single or double quoted words
+// together so JSON values can contain spaces.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if let Some(q) = quote {
+        return Err(format!("unclosed {}", q));
+    }
+    if in_word {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+const SHELL_COMMANDS: [&str; 10] = [
+    "get", "set", "del", "scan", "watch", "export", "import", "help", "exit", "quit",
+];
+
+struct ShellHelper<'a> {
+    client: &'a Client,
+}
+
+impl Completer for ShellHelper<'_> {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..];
+
+        if start == 0 {
+            let commands = SHELL_COMMANDS
+                .iter()
+                .filter(|c| c.starts_with(word))
+                .map(|c| c.to_string())
+                .collect();
+            return Ok((start, commands));
+        }
+
+        // Only the first argument of the key commands is a key name.
+        let command = line.split_whitespace().next().unwrap_or_default();
+        let is_key_position = line[..start].split_whitespace().count() == 1;
+        if !is_key_position || !["get", "set", "del"].contains(&command) {
+            return Ok((start, vec![]));
+        }
+
+        let keys = match self.client.scan_page(word, None, COMPLETION_LIMIT, None) {
+            Ok(page) => page.entries.into_iter().map(|e| e.key).collect(),
+            Err(_) => vec![],
+        };
+        Ok((start, keys))
+    }
+}
+
+impl Hinter for ShellHelper<'_> {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper<'_> {}
+
+impl Validator for ShellHelper<'_> {}
+
+impl Helper for ShellHelper<'_> {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+fn shell(client: &Client, output: Output) -> anyhow::Result<()> {
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ShellHelper { client }));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session.
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let line = match editor.readline("dbctl> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+
+        let words = match split_words(&line) {
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                continue;
+            }
+        };
+
+        match words[0].as_str() {
+            "exit" | "quit" => break,
+            "help" => {
+                println!("commands: {}", SHELL_COMMANDS.join(", "));
+                println!("run `<command> --help` for the arguments of a command");
+                continue;
+            }
+            _ => {}
+        }
+
+        match ShellLine::try_parse_from(&words) {
+            Ok(ShellLine {
+                command: Command::Shell,
+            }) => eprintln!("error: already in the shell"),
+            Ok(ShellLine { command }) => {
+                if let Err(e) = run(client, command, output) {
+                    eprintln!("error: {:#}", e);
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let client = Client::new(&cli.url)?;
+
+    match cli.command {
+        None | Some(Command::Shell) => shell(&client, cli.output),
+        Some(command) => run(&client, command, cli.output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_words_keeps_quoted_values() -> Result<(), Box<dyn std::error::Error>> {
+        let words = split_words(r#"set user '{"name": "ann"}' --ttl 5"#)?;
+
+        assert_eq!(
+            words,
+            vec!["set", "user", r#"{"name": "ann"}"#, "--ttl", "5"]
+        );
+        assert_eq!(split_words("get \"a b\"")?, vec!["get", "a b"]);
+        assert_eq!(split_words("get ''")?, vec!["get", ""]);
+        assert!(split_words("get 'a").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn shell_line_parses_subcommands() -> Result<(), Box<dyn std::error::Error>> {
+        let line = ShellLine::try_parse_from(["set", "a", "1", "--ttl", "5"])?;
+
+        assert_eq!(
+            line.command,
+            Command::Set {
+                key: "a".to_string(),
+                value: "1".to_string(),
+                ttl: Some(5),
+                string: false,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn values_parse_as_json_when_possible() {
+        assert_eq!(parse_value("42", false), json!(42));
+        assert_eq!(parse_value(r#"{"a":1}"#, false), json!({"a": 1}));
+        assert_eq!(parse_value("hello", false), json!("hello"));
+        assert_eq!(parse_value("42", true), json!("42"));
+    }
+
+    #[test]
+    fn table_aligns_columns() {
+        let rows = vec![
+            vec!["a".to_string(), "1".to_string()],
+            vec!["longer".to_string(), "two".to_string()],
+        ];
+
+        let table = render_table(&["KEY", "VALUE"], &rows);
+
+        assert_eq!(
+            table,
+            "KEY     VALUE\n------  -----\na       1\nlonger  two"
+        );
+    }
+
+    #[test]
+    fn key_urls_are_escaped() -> Result<(), Box<dyn std::error::Error>> {
+        let client = Client::new("http://localhost:4000/")?;
+
+        assert_eq!(
+            client.key_url("a/b c").as_str(),
+            "http://localhost:4000/keys/a%2Fb%20c"
+        );
+        assert!(Client::new("mailto:x").is_err());
+        assert!(Client::new("not a url").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::durability::Syncer;
+
+// On-disk framing shared by the write-ahead log and snapshots. Every record
+// is written as
+//
+//   magic (4 bytes) | length (u32 LE) | crc32 of length and payload (u32 LE) | payload
+//
+// The magic lets a reader find the next intact frame after a damaged one, and
+// the checksum covers the length so a corrupted length is caught as well.
+
+pub const LOG_FILE: &str = "wal.log";
+pub const SNAPSHOT_FILE: &str = "snapshot.db";
+
+const MAGIC: [u8; 4] = *b"dbr1";
+const HEADER_LEN: usize = 12;
+const MAX_PAYLOAD: usize = 64 * 1024 * 1024;
+const RUN_FSCK: &str = "run `database-server fsck` on the data directory";
+
+#[derive(Debug)]
+pub enum DiskError {
+    Io(PathBuf, io::Error),
+    // Damage that cannot be explained by a write cut short at the end of a
+    // file, so nothing is dropped until `fsck` has been run.
+    Corrupt(PathBuf, Vec<Range<u64>>),
+    // A snapshot without its final block.
+    Incomplete(PathBuf),
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiskError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            DiskError::Corrupt(path, damaged) => {
+                write!(f, "{} is damaged at bytes", path.display())?;
+                for (i, range) in damaged.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}..{}", separator, range.start, range.end)?;
+                }
+                write!(f, "; {}", RUN_FSCK)
+            }
+            DiskError::Incomplete(path) => {
+                write!(
+                    f,
+                    "{} is missing its final block; {}",
+                    path.display(),
+                    RUN_FSCK
+                )
+            }
+        }
+    }
+}
+
+impl Error for DiskError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DiskError::Io(_, e) => Some(e),
+            DiskError::Corrupt(_, _) | DiskError::Incomplete(_) => None,
+        }
+    }
+}
+
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let len = (payload.len() as u32).to_le_bytes();
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&len);
+    hasher.update(payload);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&len);
+    bytes.extend_from_slice(&hasher.finalize().to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+// The payload of the frame starting at `bytes[0]`, or `None` when there is no
+// intact frame there.
+fn unframe(bytes: &[u8]) -> Option<&[u8]> {
+    let header = bytes.get(..HEADER_LEN)?;
+    if header[..4] != MAGIC {
+        return None;
+    }
+    let len = u32::from_le_bytes(header[4..8].try_into().ok()?);
+    let crc = u32::from_le_bytes(header[8..12].try_into().ok()?);
+    if len as usize > MAX_PAYLOAD {
+        return None;
+    }
+
+    let payload = bytes.get(HEADER_LEN..HEADER_LEN + len as usize)?;
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&len.to_le_bytes());
+    hasher.update(payload);
+    (hasher.finalize() == crc).then_some(payload)
+}
+
+// The result of reading a file frame by frame. `frames` holds the byte range
+// and payload of every intact frame, `damaged` the ranges in between that
+// could not be read.
+#[derive(Debug, Default, PartialEq)]
+pub struct Scan<'a> {
+    pub frames: Vec<(Range<u64>, &'a [u8])>,
+    pub damaged: Vec<Range<u64>>,
+}
+
+impl Scan<'_> {
+    // Where the file can be cut to drop a torn write, if the only damage is
+    // at the very end.
+    pub fn torn_tail(&self, len: u64) -> Option<u64> {
+        match self.damaged.as_slice() {
+            [tail] if tail.end == len => Some(tail.start),
+            _ => None,
+        }
+    }
+}
+
+pub fn scan(bytes: &[u8]) -> Scan<'_> {
+    let mut scan = Scan::default();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        if let Some(payload) = unframe(&bytes[pos..]) {
+            let end = pos + HEADER_LEN + payload.len();
+            scan.frames.push((pos as u64..end as u64, payload));
+            pos = end;
+            continue;
+        }
+
+        // Skip ahead to the next position holding an intact frame.
+        let resume = (pos + 1..bytes.len())
+            .find(|&at| bytes[at..].starts_with(&MAGIC) && unframe(&bytes[at..]).is_some())
+            .unwrap_or(bytes.len());
+        scan.damaged.push(pos as u64..resume as u64);
+        pos = resume;
+    }
+
+    scan
+}
+
+pub fn read(path: &Path) -> Result<Option<Vec<u8>>, DiskError> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(DiskError::Io(path.to_path_buf(), e)),
+    }
+}
+
+// Writes `payloads` as a new file in one go. The frames go to a temporary
+// file which is synced and then renamed over `path`, so readers only ever see
+// the old file or the complete new one.
+pub fn replace<'a>(
+    path: &Path,
+    payloads: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<(), DiskError> {
+    let io_error = |e| DiskError::Io(path.to_path_buf(), e);
+    let temporary = path.with_extension("tmp");
+
+    let mut file = File::create(&temporary).map_err(io_error)?;
+    for payload in payloads {
+        file.write_all(&frame(payload)).map_err(io_error)?;
+    }
+    file.sync_all().map_err(io_error)?;
+    fs::rename(&temporary, path).map_err(io_error)?;
+    sync_dir(path)
+}
+
+fn sync_dir(path: &Path) -> Result<(), DiskError> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| DiskError::Io(dir.to_path_buf(), e))
+}
+
+// The append-only write-ahead log. Appends only reach the OS; see `Syncer`
+// for getting them onto the disk.
+pub struct Log {
+    path: PathBuf,
+    file: Arc<File>,
+    len: u64,
+    syncer: Arc<Syncer>,
+}
+
+// The records found when opening the log, each with the bytes it was read
+// from, and the byte range of a torn tail that was cut off.
+pub struct LogContents {
+    pub records: Vec<(Range<u64>, Vec<u8>)>,
+    pub truncated: Option<Range<u64>>,
+}
+
+impl Log {
+    // Opens the log, creating it if needed. A write torn by a crash leaves
+    // damage only at the end of the file, and that tail is cut off. Damage
+    // anywhere else is an error.
+    pub fn open(path: &Path) -> Result<(Log, LogContents), DiskError> {
+        let io_error = |e| DiskError::Io(path.to_path_buf(), e);
+        let bytes = read(path)?.unwrap_or_default();
+        let len = bytes.len() as u64;
+        let scan = scan(&bytes);
+
+        let truncate_at = match scan.damaged.is_empty() {
+            true => None,
+            false => match scan.torn_tail(len) {
+                Some(at) => Some(at),
+                None => return Err(DiskError::Corrupt(path.to_path_buf(), scan.damaged)),
+            },
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+        if let Some(at) = truncate_at {
+            file.set_len(at).map_err(io_error)?;
+            file.sync_all().map_err(io_error)?;
+        }
+
+        let records = scan
+            .frames
+            .into_iter()
+            .map(|(range, payload)| (range, payload.to_vec()))
+            .collect();
+        let file = Arc::new(file);
+        let log = Log {
+            path: path.to_path_buf(),
+            file: file.clone(),
+            len: truncate_at.unwrap_or(len),
+            syncer: Arc::new(Syncer::new(path.to_path_buf(), file)),
+        };
+        let contents = LogContents {
+            records,
+            truncated: truncate_at.map(|at| at..len),
+        };
+        Ok((log, contents))
+    }
+
+    // Appends the record of the write that created `version`.
+    pub fn append(&mut self, payload: &[u8], version: u64) -> Result<(), DiskError> {
+        let bytes = frame(payload);
+        (&*self.file)
+            .write_all(&bytes)
+            .map_err(|e| DiskError::Io(self.path.clone(), e))?;
+        self.len += bytes.len() as u64;
+        self.syncer.written(version);
+        Ok(())
+    }
+
+    // Drops every record, once a snapshot covers them all up to `version`.
+    pub fn reset(&mut self, version: u64) -> Result<(), DiskError> {
+        let io_error = |e| DiskError::Io(self.path.clone(), e);
+        self.file.set_len(0).map_err(io_error)?;
+        self.file.sync_all().map_err(io_error)?;
+        self.len = 0;
+        self.syncer.synced_through(version);
+        Ok(())
+    }
+
+    pub fn syncer(&self) -> Arc<Syncer> {
+        self.syncer.clone()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(payloads: &[&[u8]]) -> Vec<u8> {
+        payloads.iter().flat_map(|p| frame(p)).collect()
+    }
+
+    #[test]
+    fn scan_reads_back_frames() {
+        let bytes = framed(&[b"one", b"", b"three"]);
+
+        let scan = scan(&bytes);
+
+        let payloads: Vec<&[u8]> = scan.frames.iter().map(|(_, p)| *p).collect();
+        assert_eq!(payloads, vec![&b"one"[..], b"", b"three"]);
+        assert!(scan.damaged.is_empty());
+    }
+
+    #[test]
+    fn scan_resumes_after_damage() {
+        let mut bytes = framed(&[b"one", b"two", b"three"]);
+        // Flip a bit in the payload of the second frame.
+        bytes[15 + HEADER_LEN] ^= 1;
+
+        let scan = scan(&bytes);
+
+        let payloads: Vec<&[u8]> = scan.frames.iter().map(|(_, p)| *p).collect();
+        assert_eq!(payloads, vec![&b"one"[..], b"three"]);
+        assert_eq!(scan.damaged, vec![15..30]);
+        assert_eq!(scan.torn_tail(bytes.len() as u64), None);
+    }
+
+    #[test]
+    fn torn_tail_is_cut_off() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(LOG_FILE);
+        let mut bytes = framed(&[b"one", b"two"]);
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&path, &bytes)?;
+
+        let (mut log, contents) = Log::open(&path)?;
+        assert_eq!(contents.records, vec![(0..15, b"one".to_vec())]);
+        assert_eq!(contents.truncated, Some(15..29));
+        assert_eq!(fs::metadata(&path)?.len(), 15);
+
+        log.append(b"three", 2)?;
+        let (_, contents) = Log::open(&path)?;
+        let payloads: Vec<Vec<u8>> = contents.records.into_iter().map(|(_, p)| p).collect();
+        assert_eq!(payloads, vec![b"one".to_vec(), b"three".to_vec()]);
+        assert_eq!(contents.truncated, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn damage_before_the_tail_is_refused() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(LOG_FILE);
+        let mut bytes = framed(&[b"one", b"two"]);
+        bytes[4] ^= 1;
+        fs::write(&path, &bytes)?;
+
+        assert!(matches!(Log::open(&path), Err(DiskError::Corrupt(_, _))));
+        assert_eq!(fs::read(&path)?, bytes);
+
+        Ok(())
+    }
+}
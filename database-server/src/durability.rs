@@ -0,0 +1,224 @@
+use crate::disk::DiskError;
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::watch;
+
+// How long a write waits before it is acknowledged:
+//
+// - `sync`: until the log has been fsynced past it.
+// - `group`: until the next group commit, which fsyncs every write made since
+//   the one before in one go.
+// - `buffered`: not at all; the write is in the OS page cache and reaches the
+//   disk whenever the OS or a later fsync gets to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    Sync,
+    #[default]
+    Group,
+    Buffered,
+}
+
+impl FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sync" => Ok(Durability::Sync),
+            "group" => Ok(Durability::Group),
+            "buffered" => Ok(Durability::Buffered),
+            _ => Err(format!(
+                "unknown durability {:?}, expected sync, group or buffered",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Durability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Durability::Sync => write!(f, "sync"),
+            Durability::Group => write!(f, "group"),
+            Durability::Buffered => write!(f, "buffered"),
+        }
+    }
+}
+
+struct State {
+    // The version of the last write appended to the log.
+    written: u64,
+    // Whether an fsync is in flight. Only one runs at a time; writers that
+    // arrive meanwhile wait for it and then share the next one.
+    syncing: bool,
+}
+
+// Tracks how far the log has been fsynced, in store versions, and runs the
+// fsyncs themselves outside the store lock.
+pub struct Syncer {
+    path: PathBuf,
+    file: Arc<File>,
+    state: Mutex<State>,
+    synced: watch::Sender<u64>,
+}
+
+impl Syncer {
+    pub fn new(path: PathBuf, file: Arc<File>) -> Self {
+        Syncer {
+            path,
+            file,
+            state: Mutex::new(State {
+                written: 0,
+                syncing: false,
+            }),
+            synced: watch::Sender::new(0),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| {
+            self.state.clear_poison();
+            poisoned.into_inner()
+        })
+    }
+
+    pub fn written(&self, version: u64) {
+        self.state().written = version;
+    }
+
+    // Everything up to `version` is on disk by other means, e.g. a snapshot.
+    pub fn synced_through(&self, version: u64) {
+        self.synced.send_if_modified(|synced| {
+            let advanced = version > *synced;
+            *synced = (*synced).max(version);
+            advanced
+        });
+    }
+
+    pub fn is_synced(&self, version: u64) -> bool {
+        *self.synced.borrow() >= version
+    }
+
+    pub fn pending(&self) -> Option<u64> {
+        let written = self.state().written;
+        (!self.is_synced(written)).then_some(written)
+    }
+
+    // Returns once the log is on disk up to `version`. Concurrent callers are
+    // coalesced: while one fsync runs the others wait, and the next fsync
+    // covers every write made before it started.
+    pub async fn sync(&self, version: u64) -> Result<(), DiskError> {
+        let mut synced = self.synced.subscribe();
+        loop {
+            let target = {
+                let mut state = self.state();
+                if *synced.borrow_and_update() >= version.min(state.written) {
+                    return Ok(());
+                }
+                match state.syncing {
+                    true => None,
+                    false => {
+                        state.syncing = true;
+                        Some(state.written)
+                    }
+                }
+            };
+
+            let Some(target) = target else {
+                // Woken when the fsync in flight finishes, successful or not.
+                let _ = synced.changed().await;
+                continue;
+            };
+
+            let file = self.file.clone();
+            let result = tokio::task::spawn_blocking(move || file.sync_data()).await;
+            self.state().syncing = false;
+            match result {
+                Ok(Ok(())) => {
+                    self.synced
+                        .send_modify(|synced| *synced = (*synced).max(target));
+                }
+                Ok(Err(e)) => {
+                    self.synced.send_modify(|_| {});
+                    return Err(DiskError::Io(self.path.clone(), e));
+                }
+                Err(e) => {
+                    self.synced.send_modify(|_| {});
+                    return Err(DiskError::Io(self.path.clone(), e.into()));
+                }
+            }
+        }
+    }
+
+    // Waits up to `timeout` for someone else, i.e. the group commit, to sync
+    // the log past `version`. Returns whether it did.
+    pub async fn wait(&self, version: u64, timeout: Duration) -> bool {
+        let mut synced = self.synced.subscribe();
+        let waited = tokio::time::timeout(timeout, synced.wait_for(|s| *s >= version)).await;
+        matches!(waited, Ok(Ok(_)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn syncer() -> Result<(tempfile::TempDir, Arc<Syncer>), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log");
+        let file = Arc::new(File::create(&path)?);
+        Ok((dir, Arc::new(Syncer::new(path, file))))
+    }
+
+    #[tokio::test]
+    async fn concurrent_writers_share_an_fsync() -> Result<(), Box<dyn std::error::Error>> {
+        let (_dir, syncer) = syncer()?;
+        for version in 1..=3 {
+            (&*syncer.file).write_all(b"x")?;
+            syncer.written(version);
+        }
+        assert_eq!(syncer.pending(), Some(3));
+
+        let waiters: Vec<_> = (1..=3)
+            .map(|version| {
+                let syncer = syncer.clone();
+                tokio::spawn(async move { syncer.sync(version).await })
+            })
+            .collect();
+        for waiter in waiters {
+            waiter.await??;
+        }
+
+        assert!(syncer.is_synced(3));
+        assert_eq!(syncer.pending(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn waiting_for_a_group_commit() -> Result<(), Box<dyn std::error::Error>> {
+        let (_dir, syncer) = syncer()?;
+        syncer.written(1);
+
+        assert!(!syncer.wait(1, Duration::from_millis(10)).await);
+
+        let committer = syncer.clone();
+        tokio::spawn(async move { committer.sync(1).await });
+        assert!(syncer.wait(1, Duration::from_secs(5)).await);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!("sync".parse(), Ok(Durability::Sync));
+        assert_eq!("buffered".parse(), Ok(Durability::Buffered));
+        assert!("eventually".parse::<Durability>().is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use crate::json_path::JsonPathError;
+use crate::lease::LeaseError;
+use crate::pubsub::PubSubError;
+use crate::store::StoreError;
+use axum::{
+    Json,
+    extract::rejection::{JsonRejection, QueryRejection},
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+// Every failed request is answered with an RFC 7807 problem document. `code`
+// is stable and meant for clients to match on; `detail` is for people.
+#[derive(Debug, PartialEq)]
+pub enum ApiError {
+    KeyNotFound(String),
+    PathNotFound(String, String),
+    IndexNotFound(String),
+    InvalidPath(JsonPathError),
+    InvalidQuery(String),
+    InvalidBody(String),
+    UnsupportedMediaType(String),
+    PatchConflict(String),
+    CompareFailed(String),
+    BatchTooLarge(usize, usize),
+    LockHeld(String),
+    Unauthorized(String),
+    Lease(LeaseError),
+    PubSub(PubSubError),
+    Store(StoreError),
+}
+
+#[derive(Serialize, Debug)]
+struct Problem {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    code: &'static str,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::KeyNotFound(_)
+            | ApiError::PathNotFound(_, _)
+            | ApiError::IndexNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::InvalidPath(_)
+            | ApiError::Store(StoreError::InvalidPath(_))
+            | ApiError::InvalidQuery(_)
+            | ApiError::InvalidBody(_)
+            | ApiError::PubSub(_) => StatusCode::BAD_REQUEST,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::PatchConflict(_)
+            | ApiError::CompareFailed(_)
+            | ApiError::LockHeld(_)
+            | ApiError::Lease(_) => StatusCode::CONFLICT,
+            ApiError::BatchTooLarge(_, _) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Store(StoreError::FutureVersion(_, _)) => StatusCode::BAD_REQUEST,
+            ApiError::Store(StoreError::Compacted(_) | StoreError::ChangesTruncated(_, _)) => {
+                StatusCode::GONE
+            }
+            ApiError::Store(StoreError::Disk(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::KeyNotFound(_) => "key_not_found",
+            ApiError::PathNotFound(_, _) => "path_not_found",
+            ApiError::IndexNotFound(_) => "index_not_found",
+            ApiError::InvalidPath(_) | ApiError::Store(StoreError::InvalidPath(_)) => {
+                "invalid_path"
+            }
+            ApiError::InvalidQuery(_) => "invalid_query",
+            ApiError::InvalidBody(_) => "invalid_body",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::PatchConflict(_) => "patch_conflict",
+            ApiError::CompareFailed(_) => "compare_failed",
+            ApiError::BatchTooLarge(_, _) => "batch_too_large",
+            ApiError::LockHeld(_) => "lock_held",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Lease(LeaseError::NotHeld(_, _)) => "lease_not_held",
+            ApiError::PubSub(PubSubError::InvalidChannel(_)) => "invalid_channel",
+            ApiError::PubSub(PubSubError::InvalidPattern(_)) => "invalid_pattern",
+            ApiError::Store(StoreError::Compacted(_)) => "version_compacted",
+            ApiError::Store(StoreError::FutureVersion(_, _)) => "future_version",
+            ApiError::Store(StoreError::ChangesTruncated(_, _)) => "changes_truncated",
+            ApiError::Store(StoreError::Disk(_)) => "storage_failed",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            ApiError::KeyNotFound(_) => "Key not found",
+            ApiError::PathNotFound(_, _) => "Path not found",
+            ApiError::IndexNotFound(_) => "Index not found",
+            ApiError::InvalidPath(_) | ApiError::Store(StoreError::InvalidPath(_)) => {
+                "Invalid JSON path"
+            }
+            ApiError::InvalidQuery(_) => "Invalid query parameters",
+            ApiError::InvalidBody(_) => "Invalid request body",
+            ApiError::UnsupportedMediaType(_) => "Unsupported media type",
+            ApiError::PatchConflict(_) => "Patch could not be applied",
+            ApiError::CompareFailed(_) => "Value has changed",
+            ApiError::BatchTooLarge(_, _) => "Batch too large",
+            ApiError::LockHeld(_) => "Lock held",
+            ApiError::Unauthorized(_) => "Unauthorized",
+            ApiError::Lease(LeaseError::NotHeld(_, _)) => "Lease not held",
+            ApiError::PubSub(PubSubError::InvalidChannel(_)) => "Invalid channel",
+            ApiError::PubSub(PubSubError::InvalidPattern(_)) => "Invalid channel pattern",
+            ApiError::Store(StoreError::Compacted(_)) => "Version no longer retained",
+            ApiError::Store(StoreError::FutureVersion(_, _)) => "Version not written yet",
+            ApiError::Store(StoreError::ChangesTruncated(_, _)) => "Changes no longer retained",
+            ApiError::Store(StoreError::Disk(_)) => "Storage failed",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::KeyNotFound(key) => write!(f, "no value set for key {}", key),
+            ApiError::PathNotFound(key, path) => {
+                write!(f, "path {} not found in key {}", path, key)
+            }
+            ApiError::IndexNotFound(name) => write!(f, "no index named {}", name),
+            ApiError::InvalidPath(e) => write!(f, "{}", e),
+            ApiError::InvalidQuery(e) => write!(f, "{}", e),
+            ApiError::InvalidBody(e) => write!(f, "{}", e),
+            ApiError::UnsupportedMediaType(e) => write!(f, "{}", e),
+            ApiError::PatchConflict(e) => write!(f, "{}", e),
+            ApiError::CompareFailed(key) => {
+                write!(f, "key {} does not hold the expected value", key)
+            }
+            ApiError::BatchTooLarge(len, max) => {
+                write!(f, "batch of {} exceeds the limit of {}", len, max)
+            }
+            ApiError::LockHeld(name) => write!(f, "lock {} is held by someone else", name),
+            ApiError::Unauthorized(e) => write!(f, "{}", e),
+            ApiError::Lease(e) => write!(f, "{}", e),
+            ApiError::PubSub(e) => write!(f, "{}", e),
+            ApiError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let problem = Problem {
+            problem_type: format!("urn:database-server:problem:{}", self.code()),
+            title: self.title(),
+            status: status.as_u16(),
+            detail: self.to_string(),
+            code: self.code(),
+        };
+
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, PROBLEM_JSON)],
+            Json(problem),
+        )
+            .into_response();
+        if let ApiError::Unauthorized(_) = self {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer realm=\"admin\""),
+            );
+        }
+        response
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> Self {
+        match err {
+            StoreError::InvalidPath(e) => ApiError::InvalidPath(e),
+            err => ApiError::Store(err),
+        }
+    }
+}
+
+impl From<LeaseError> for ApiError {
+    fn from(err: LeaseError) -> Self {
+        ApiError::Lease(err)
+    }
+}
+
+impl From<PubSubError> for ApiError {
+    fn from(err: PubSubError) -> Self {
+        ApiError::PubSub(err)
+    }
+}
+
+impl From<JsonPathError> for ApiError {
+    fn from(err: JsonPathError) -> Self {
+        ApiError::InvalidPath(err)
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::InvalidQuery(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::MissingJsonContentType(e) => {
+                ApiError::UnsupportedMediaType(e.body_text())
+            }
+            other => ApiError::InvalidBody(other.body_text()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use serde_json::{Value, json};
+
+    async fn problem(
+        err: ApiError,
+    ) -> Result<(StatusCode, String, Value), Box<dyn std::error::Error>> {
+        let response = err.into_response();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|v| v.to_str().unwrap_or_default().to_string())
+            .unwrap_or_default();
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok((status, content_type, serde_json::from_slice(&body)?))
+    }
+
+    #[tokio::test]
+    async fn renders_problem_json() -> Result<(), Box<dyn std::error::Error>> {
+        let (status, content_type, body) =
+            problem(ApiError::KeyNotFound("user".to_string())).await?;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, PROBLEM_JSON);
+        assert_eq!(
+            body,
+            json!({
+                "type": "urn:database-server:problem:key_not_found",
+                "title": "Key not found",
+                "status": 404,
+                "detail": "no value set for key user",
+                "code": "key_not_found",
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_errors_keep_their_own_codes() -> Result<(), Box<dyn std::error::Error>> {
+        let (status, _, body) = problem(StoreError::Compacted(3).into()).await?;
+        assert_eq!(status, StatusCode::GONE);
+        assert_eq!(body["code"], "version_compacted");
+
+        let (status, _, body) = problem(StoreError::FutureVersion(9, 2).into()).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "future_version");
+
+        let (status, _, body) = problem(StoreError::ChangesTruncated(1, 5).into()).await?;
+        assert_eq!(status, StatusCode::GONE);
+        assert_eq!(body["code"], "changes_truncated");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use crate::disk::{self, DiskError, LOG_FILE, SNAPSHOT_FILE};
+use crate::store::{LogEntry, SnapshotBlock};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// Offline check of a data directory, run as `database-server fsck <dir>`
+// while the server is stopped. With `--repair` every record that can still be
+// read is salvaged into a fresh file, and the damaged original is kept next
+// to it with a `.damaged` suffix.
+
+#[derive(Debug, PartialEq)]
+pub struct FileReport {
+    pub file: &'static str,
+    pub records: usize,
+    pub damaged: Vec<Range<u64>>,
+    // A snapshot whose final block is missing.
+    pub incomplete: bool,
+}
+
+impl FileReport {
+    pub fn is_clean(&self) -> bool {
+        self.damaged.is_empty() && !self.incomplete
+    }
+}
+
+impl fmt::Display for FileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} intact records", self.file, self.records)?;
+        if self.is_clean() {
+            return write!(f, ", ok");
+        }
+        for range in &self.damaged {
+            write!(
+                f,
+                "\n  damaged bytes {}..{} ({} bytes)",
+                range.start,
+                range.end,
+                range.end - range.start
+            )?;
+        }
+        if self.incomplete {
+            write!(f, "\n  final block missing, later entries are lost")?;
+        }
+        Ok(())
+    }
+}
+
+// Reports on the log and the snapshot, whichever exist, and salvages the
+// damaged ones when `repair` is set.
+pub fn fsck(dir: &Path, repair: bool) -> Result<Vec<FileReport>, DiskError> {
+    let mut reports = vec![];
+
+    if let Some((report, records)) = check::<LogEntry>(dir, LOG_FILE)? {
+        if repair && !report.is_clean() {
+            let path = set_aside(dir, LOG_FILE)?;
+            disk::replace(&path, records.iter().map(|(_, payload)| payload.as_slice()))?;
+        }
+        reports.push(report);
+    }
+
+    if let Some((mut report, blocks)) = check::<SnapshotBlock>(dir, SNAPSHOT_FILE)? {
+        report.incomplete = !blocks.last().is_some_and(|(block, _)| block.last);
+        if repair && !report.is_clean() {
+            let path = set_aside(dir, SNAPSHOT_FILE)?;
+            if !blocks.is_empty() {
+                let payloads = salvage_snapshot(&path, blocks)?;
+                disk::replace(&path, payloads.iter().map(Vec::as_slice))?;
+            }
+        }
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+// The report on a file, and every record in it that could be read together
+// with its payload.
+type Checked<T> = (FileReport, Vec<(T, Vec<u8>)>);
+
+// Reads every frame of `file` that passes its checksum and decodes. Frames
+// that fail either are reported as damaged.
+fn check<T: DeserializeOwned>(
+    dir: &Path,
+    file: &'static str,
+) -> Result<Option<Checked<T>>, DiskError> {
+    let Some(bytes) = disk::read(&dir.join(file))? else {
+        return Ok(None);
+    };
+
+    let scan = disk::scan(&bytes);
+    let mut damaged = scan.damaged;
+    let mut records = vec![];
+    for (range, payload) in scan.frames {
+        match serde_json::from_slice(payload) {
+            Ok(record) => records.push((record, payload.to_vec())),
+            Err(_) => damaged.push(range),
+        }
+    }
+
+    let report = FileReport {
+        file,
+        records: records.len(),
+        damaged: merge(damaged),
+        incomplete: false,
+    };
+    Ok(Some((report, records)))
+}
+
+fn merge(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<u64>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// Moves the damaged file out of the way, returning the path it was at.
+fn set_aside(dir: &Path, file: &str) -> Result<PathBuf, DiskError> {
+    let path = dir.join(file);
+    let aside = dir.join(format!("{}.damaged", file));
+    fs::rename(&path, &aside).map_err(|e| DiskError::Io(path.clone(), e))?;
+    Ok(path)
+}
+
+// The intact blocks, re-encoded as one complete snapshot at the newest
+// version any of them carries.
+fn salvage_snapshot(
+    path: &Path,
+    blocks: Vec<(SnapshotBlock, Vec<u8>)>,
+) -> Result<Vec<Vec<u8>>, DiskError> {
+    let version = blocks.iter().map(|(b, _)| b.version).max().unwrap_or(0);
+    let count = blocks.len();
+
+    blocks
+        .into_iter()
+        .enumerate()
+        .map(|(i, (block, _))| {
+            let block = SnapshotBlock {
+                version,
+                last: i + 1 == count,
+                ..block
+            };
+            serde_json::to_vec(&block).map_err(|e| DiskError::Io(path.to_path_buf(), e.into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{Retention, Store};
+    use serde_json::json;
+
+    // Flips a byte inside the `n`th frame of `file`.
+    fn damage(dir: &Path, file: &str, n: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let path = dir.join(file);
+        let mut bytes = fs::read(&path)?;
+        let (range, _) = disk::scan(&bytes).frames[n].clone();
+        bytes[range.end as usize - 1] ^= 0xff;
+        fs::write(&path, bytes)?;
+        Ok(())
+    }
+
+    #[test]
+    fn repairs_a_damaged_log() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        {
+            let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+            for n in 0..3 {
+                store.set(format!("k{}", n), json!(n))?;
+            }
+        }
+        damage(dir.path(), LOG_FILE, 1)?;
+        assert!(Store::open(dir.path(), Retention::default()).is_err());
+
+        let reports = fsck(dir.path(), false)?;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].records, 2);
+        assert_eq!(reports[0].damaged.len(), 1);
+
+        fsck(dir.path(), true)?;
+        assert!(dir.path().join("wal.log.damaged").exists());
+        assert!(fsck(dir.path(), false)?.iter().all(FileReport::is_clean));
+
+        let (store, _) = Store::open(dir.path(), Retention::default())?;
+        assert_eq!(store.get("k0"), Some(&json!(0)));
+        assert_eq!(store.get("k1"), None);
+        assert_eq!(store.get("k2"), Some(&json!(2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn repairs_a_snapshot_missing_its_final_block() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        {
+            let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+            for n in 0..2_500 {
+                store.set(format!("k{:04}", n), json!(n))?;
+            }
+            store.checkpoint()?;
+        }
+        damage(dir.path(), SNAPSHOT_FILE, 2)?;
+
+        let reports = fsck(dir.path(), true)?;
+        let snapshot = &reports[1];
+        assert_eq!(snapshot.records, 2);
+        assert!(snapshot.incomplete);
+
+        let (store, recovery) = Store::open(dir.path(), Retention::default())?;
+        assert_eq!(recovery.snapshot_version, 2_500);
+        assert_eq!(store.get("k1999"), Some(&json!(1999)));
+        assert_eq!(store.get("k2000"), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+
+// A small subset of JSONPath: `$`, `.field`, `['field']` and `[index]`.
+// $.user.emails[0]
+// $['first name']
+
+#[derive(Debug, PartialEq)]
+pub enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JsonPathError {
+    MissingRoot,
+    EmptyField(usize),
+    UnterminatedBracket(usize),
+    InvalidIndex(String),
+    UnexpectedChar(char, usize),
+}
+
+impl fmt::Display for JsonPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonPathError::MissingRoot => write!(f, "path must start with '$'"),
+            JsonPathError::EmptyField(pos) => write!(f, "empty field name at {}", pos),
+            JsonPathError::UnterminatedBracket(pos) => {
+                write!(f, "unterminated '[' at {}", pos)
+            }
+            JsonPathError::InvalidIndex(s) => write!(f, "invalid array index '{}'", s),
+            JsonPathError::UnexpectedChar(c, pos) => {
+                write!(f, "unexpected character '{}' at {}", c, pos)
+            }
+        }
+    }
+}
+
+impl Error for JsonPathError {}
+
+impl JsonPath {
+    pub fn parse(input: &str) -> Result<JsonPath, JsonPathError> {
+        let Some(rest) = input.strip_prefix('$') else {
+            return Err(JsonPathError::MissingRoot);
+        };
+
+        let bytes = rest.as_bytes();
+        let mut segments = vec![];
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            match bytes[pos] {
+                b'.' => {
+                    let start = pos + 1;
+                    let end = rest[start..]
+                        .find(['.', '['])
+                        .map_or(rest.len(), |i| start + i);
+                    if start == end {
+                        return Err(JsonPathError::EmptyField(start + 1));
+                    }
+                    segments.push(Segment::Field(rest[start..end].to_string()));
+                    pos = end;
+                }
+                b'[' => {
+                    let Some(close) = rest[pos..].find(']').map(|i| pos + i) else {
+                        return Err(JsonPathError::UnterminatedBracket(pos + 1));
+                    };
+                    let inner = &rest[pos + 1..close];
+                    let quoted = inner
+                        .strip_prefix('\'')
+                        .and_then(|s| s.strip_suffix('\''))
+                        .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+                    match quoted {
+                        Some(field) => segments.push(Segment::Field(field.to_string())),
+                        None => {
+                            let index = inner
+                                .parse::<usize>()
+                                .map_err(|_| JsonPathError::InvalidIndex(inner.to_string()))?;
+                            segments.push(Segment::Index(index));
+                        }
+                    }
+                    pos = close + 1;
+                }
+                _ => {
+                    let c = rest[pos..].chars().next().unwrap_or_default();
+                    return Err(JsonPathError::UnexpectedChar(c, pos + 1));
+                }
+            }
+        }
+
+        Ok(JsonPath { segments })
+    }
+
+    pub fn select<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(value, |current, segment| match segment {
+                Segment::Field(name) => current.get(name),
+                Segment::Index(index) => current.get(index),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_dotted() -> Result<(), Box<dyn std::error::Error>> {
+        let expected = JsonPath {
+            segments: vec![
+                Segment::Field("a".to_string()),
+                Segment::Field("b".to_string()),
+            ],
+        };
+
+        assert_eq!(JsonPath::parse("$.a.b")?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_brackets() -> Result<(), Box<dyn std::error::Error>> {
+        let expected = JsonPath {
+            segments: vec![
+                Segment::Field("first name".to_string()),
+                Segment::Index(2),
+                Segment::Field("x".to_string()),
+            ],
+        };
+
+        assert_eq!(JsonPath::parse("$['first name'][2].x")?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(JsonPath::parse("a.b"), Err(JsonPathError::MissingRoot));
+        assert_eq!(JsonPath::parse("$.a..b"), Err(JsonPathError::EmptyField(4)));
+        assert_eq!(
+            JsonPath::parse("$[0"),
+            Err(JsonPathError::UnterminatedBracket(1))
+        );
+        assert_eq!(
+            JsonPath::parse("$[x]"),
+            Err(JsonPathError::InvalidIndex("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn select_nested() -> Result<(), Box<dyn std::error::Error>> {
+        let doc = json!({"a": {"b": [10, {"c": true}]}});
+
+        assert_eq!(JsonPath::parse("$")?.select(&doc), Some(&doc));
+        assert_eq!(JsonPath::parse("$.a.b[0]")?.select(&doc), Some(&json!(10)));
+        assert_eq!(
+            JsonPath::parse("$.a.b[1].c")?.select(&doc),
+            Some(&json!(true))
+        );
+        assert_eq!(JsonPath::parse("$.a.missing")?.select(&doc), None);
+        assert_eq!(JsonPath::parse("$.a.b[5]")?.select(&doc), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+// Named locks held under a lease. Every grant carries a fencing token taken
+// from one increasing counter, so a resource guarded by the lock can reject
+// writes from a holder whose lease has since expired and passed on.
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Grant {
+    pub name: String,
+    pub token: u64,
+    pub ttl_ms: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LockStatus {
+    pub name: String,
+    pub token: Option<u64>,
+    pub ttl_ms: Option<u64>,
+    pub waiters: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LeaseError {
+    // The token does not belong to the current holder, either because the
+    // lease expired or because it never did.
+    NotHeld(String, u64),
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LeaseError::NotHeld(name, token) => {
+                write!(f, "token {} does not hold lock {}", token, name)
+            }
+        }
+    }
+}
+
+impl Error for LeaseError {}
+
+pub enum Acquire {
+    Granted(Grant),
+    // Queued behind the current holder; the grant arrives on `receiver`.
+    Waiting(u64, oneshot::Receiver<Grant>),
+    Busy,
+}
+
+struct Holder {
+    token: u64,
+    expires_at: Instant,
+}
+
+struct Waiter {
+    id: u64,
+    ttl: Duration,
+    sender: oneshot::Sender<Grant>,
+}
+
+#[derive(Default)]
+struct Lock {
+    holder: Option<Holder>,
+    waiters: VecDeque<Waiter>,
+}
+
+#[derive(Default)]
+pub struct Leases {
+    locks: HashMap<String, Lock>,
+    next_token: u64,
+    next_waiter: u64,
+}
+
+impl Leases {
+    // Grants the lock if it is free. Otherwise, when `queue` is set, the caller
+    // joins the back of the line and is granted the lock after everyone ahead.
+    pub fn acquire(&mut self, name: &str, ttl: Duration, queue: bool) -> Acquire {
+        self.expire(name, Instant::now());
+
+        let lock = self.locks.entry(name.to_string()).or_default();
+        if lock.holder.is_none() {
+            self.next_token += 1;
+            return Acquire::Granted(grant(lock, name, self.next_token, ttl));
+        }
+        if !queue {
+            return Acquire::Busy;
+        }
+
+        self.next_waiter += 1;
+        let (sender, receiver) = oneshot::channel();
+        lock.waiters.push_back(Waiter {
+            id: self.next_waiter,
+            ttl,
+            sender,
+        });
+        Acquire::Waiting(self.next_waiter, receiver)
+    }
+
+    // Leaves the line. Returns false when the waiter was already granted the
+    // lock, in which case the grant is waiting on its receiver.
+    pub fn cancel(&mut self, name: &str, waiter: u64) -> bool {
+        let Some(lock) = self.locks.get_mut(name) else {
+            return false;
+        };
+
+        let before = lock.waiters.len();
+        lock.waiters.retain(|w| w.id != waiter);
+        let removed = lock.waiters.len() < before;
+        self.forget_if_idle(name);
+        removed
+    }
+
+    pub fn renew(&mut self, name: &str, token: u64, ttl: Duration) -> Result<Grant, LeaseError> {
+        self.expire(name, Instant::now());
+
+        match self.locks.get_mut(name) {
+            Some(lock) if lock.holder.as_ref().is_some_and(|h| h.token == token) => {
+                Ok(grant(lock, name, token, ttl))
+            }
+            _ => Err(LeaseError::NotHeld(name.to_string(), token)),
+        }
+    }
+
+    pub fn release(&mut self, name: &str, token: u64) -> Result<(), LeaseError> {
+        self.expire(name, Instant::now());
+
+        match self.locks.get_mut(name) {
+            Some(lock) if lock.holder.as_ref().is_some_and(|h| h.token == token) => {
+                lock.holder = None;
+                self.hand_over(name);
+                Ok(())
+            }
+            _ => Err(LeaseError::NotHeld(name.to_string(), token)),
+        }
+    }
+
+    pub fn status(&mut self, name: &str) -> LockStatus {
+        let now = Instant::now();
+        self.expire(name, now);
+
+        let lock = self.locks.get(name);
+        let holder = lock.and_then(|l| l.holder.as_ref());
+        LockStatus {
+            name: name.to_string(),
+            token: holder.map(|h| h.token),
+            ttl_ms: holder.map(|h| h.expires_at.saturating_duration_since(now).as_millis() as u64),
+            waiters: lock.map_or(0, |l| l.waiters.len()),
+        }
+    }
+
+    pub fn expire_due(&mut self) {
+        let now = Instant::now();
+        let names: Vec<String> = self.locks.keys().cloned().collect();
+        for name in names {
+            self.expire(&name, now);
+        }
+    }
+
+    fn expire(&mut self, name: &str, now: Instant) {
+        let Some(lock) = self.locks.get_mut(name) else {
+            return;
+        };
+
+        if lock.holder.as_ref().is_some_and(|h| h.expires_at <= now) {
+            lock.holder = None;
+            self.hand_over(name);
+        }
+    }
+
+    // Passes a free lock to the longest waiting caller that is still listening.
+    fn hand_over(&mut self, name: &str) {
+        let Some(lock) = self.locks.get_mut(name) else {
+            return;
+        };
+
+        while lock.holder.is_none() {
+            let Some(waiter) = lock.waiters.pop_front() else {
+                break;
+            };
+            if waiter.sender.is_closed() {
+                continue;
+            }
+
+            self.next_token += 1;
+            let granted = grant(lock, name, self.next_token, waiter.ttl);
+            if waiter.sender.send(granted).is_err() {
+                lock.holder = None;
+            }
+        }
+
+        self.forget_if_idle(name);
+    }
+
+    fn forget_if_idle(&mut self, name: &str) {
+        if self
+            .locks
+            .get(name)
+            .is_some_and(|l| l.holder.is_none() && l.waiters.is_empty())
+        {
+            self.locks.remove(name);
+        }
+    }
+}
+
+fn grant(lock: &mut Lock, name: &str, token: u64, ttl: Duration) -> Grant {
+    lock.holder = Some(Holder {
+        token,
+        expires_at: Instant::now() + ttl,
+    });
+    Grant {
+        name: name.to_string(),
+        token,
+        ttl_ms: ttl.as_millis() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(10);
+
+    fn granted(acquire: Acquire) -> Grant {
+        match acquire {
+            Acquire::Granted(grant) => grant,
+            _ => panic!("expected the lock to be granted"),
+        }
+    }
+
+    fn waiting(acquire: Acquire) -> (u64, oneshot::Receiver<Grant>) {
+        match acquire {
+            Acquire::Waiting(id, receiver) => (id, receiver),
+            _ => panic!("expected to wait for the lock"),
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_increase_with_each_grant() -> Result<(), Box<dyn std::error::Error>> {
+        let mut leases = Leases::default();
+
+        let first = granted(leases.acquire("job", TTL, false));
+        assert!(matches!(leases.acquire("job", TTL, false), Acquire::Busy));
+        leases.release("job", first.token)?;
+        let second = granted(leases.acquire("job", TTL, false));
+
+        assert!(second.token > first.token);
+        assert_eq!(
+            leases.release("job", first.token),
+            Err(LeaseError::NotHeld("job".to_string(), first.token))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn waiters_are_served_in_order() -> Result<(), Box<dyn std::error::Error>> {
+        let mut leases = Leases::default();
+        let holder = granted(leases.acquire("job", TTL, true));
+        let (_, mut first) = waiting(leases.acquire("job", TTL, true));
+        let (_, mut second) = waiting(leases.acquire("job", TTL, true));
+
+        leases.release("job", holder.token)?;
+        let next = first.try_recv()?;
+        assert!(second.try_recv().is_err());
+
+        leases.release("job", next.token)?;
+        assert!(second.try_recv()?.token > next.token);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_are_skipped() -> Result<(), Box<dyn std::error::Error>> {
+        let mut leases = Leases::default();
+        let holder = granted(leases.acquire("job", TTL, true));
+        let (first_id, _first) = waiting(leases.acquire("job", TTL, true));
+        let (_, second) = waiting(leases.acquire("job", TTL, true));
+        let (_, mut third) = waiting(leases.acquire("job", TTL, true));
+
+        assert!(leases.cancel("job", first_id));
+        drop(second);
+        leases.release("job", holder.token)?;
+
+        assert!(third.try_recv().is_ok());
+        assert_eq!(leases.status("job").waiters, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_leases_pass_to_waiters() -> Result<(), Box<dyn std::error::Error>> {
+        let mut leases = Leases::default();
+        let holder = granted(leases.acquire("job", Duration::from_secs(1), true));
+        let (_, mut waiter) = waiting(leases.acquire("job", TTL, true));
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        leases.renew("job", holder.token, Duration::from_secs(1))?;
+        tokio::time::advance(Duration::from_millis(900)).await;
+        leases.expire_due();
+        assert!(waiter.try_recv().is_err());
+
+        tokio::time::advance(Duration::from_millis(200)).await;
+        leases.expire_due();
+        let next = waiter.try_recv()?;
+
+        assert_eq!(leases.status("job").token, Some(next.token));
+        assert!(leases.renew("job", holder.token, TTL).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn idle_locks_are_forgotten() -> Result<(), Box<dyn std::error::Error>> {
+        let mut leases = Leases::default();
+        let holder = granted(leases.acquire("job", TTL, false));
+
+        leases.release("job", holder.token)?;
+
+        assert!(leases.locks.is_empty());
+
+        Ok(())
+    }
+}
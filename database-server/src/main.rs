@@ -1,16 +1,155 @@
+use anyhow::bail;
 use axum::{
-    Router,
-    extract::{Query, State},
-    routing::get,
+    Json, Router,
+    body::Bytes,
+    extract::{
+        Form, Path, Query, Request, State,
+        rejection::{FormRejection, JsonRejection, QueryRejection},
+    },
+    http::{HeaderMap, StatusCode, header},
+    middleware::{self, Next},
+    response::{
+        Html, IntoResponse, Redirect, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{MethodRouter, get, post, put},
 };
+use clap::{Parser, Subcommand};
 use serde::*;
-use std::collections::HashMap;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::{Stream, StreamExt};
 
+mod disk;
+mod durability;
+mod error;
+mod fsck;
+mod json_path;
+mod lease;
+mod openapi;
+mod pubsub;
+mod session;
+mod stats;
+mod store;
+use crate::durability::{Durability, Syncer};
+use crate::error::ApiError;
+use crate::fsck::fsck;
+use crate::json_path::JsonPath;
+use crate::lease::{Acquire, Grant, Leases, LockStatus};
+use crate::pubsub::{Pattern, PubSub};
+use crate::session::Sessions;
+use crate::stats::Stats;
+use crate::store::{Change, Retention, Store, StoreError, Version};
+
+// The server listens on DATABASE_ADDR, 127.0.0.1:4000 by default; a port of 0
+// picks a free one, which is printed on startup.
+//
 // http://localhost:4000/set?somekey=somevalue
 // http://localhost:4000/get?key=somekey
+//
+// curl -X PUT localhost:4000/keys/user -H 'content-type: application/json' -d '{"a":{"b":1}}'
+// curl 'localhost:4000/keys/user?path=$.a.b'
+// curl -X PATCH localhost:4000/keys/user -H 'content-type: application/merge-patch+json' -d '{"a":{"c":2}}'
+// curl -X PATCH localhost:4000/keys/user -H 'content-type: application/json-patch+json' \
+//   -d '[{"op":"replace","path":"/a/b","value":3}]'
+//
+// curl -X PUT 'localhost:4000/indexes/email?path=$.email'
+// curl 'localhost:4000/query?index=email&eq=someone@example.com'
+//
+// Indexes are kept in DATABASE_DATA_DIR along with the data, and can also be declared
+// at startup, e.g. DATABASE_INDEXES='email=$.email,age=$.age'
+//
+// curl 'localhost:4000/keys/user?as_of=3'
+// curl 'localhost:4000/keys/user/history'
+// curl 'localhost:4000/keys?prefix=user&limit=100'
+// curl 'localhost:4000/keys?prefix=user&limit=100&after=user:99&as_of=42'
+//
+// Old versions are kept according to DATABASE_RETAIN_VERSIONS (per key) and
+// DATABASE_RETAIN_SECS (since being overwritten). Both are unlimited by default.
+//
+// curl -X PUT 'localhost:4000/keys/session?ttl=30' -H 'content-type: application/json' -d '{}'
+// curl -X DELETE localhost:4000/keys/session
+// curl -X POST localhost:4000/keys/counter/cas -H 'content-type: application/json' -d '{"expected":1,"value":2}'
+// curl 'localhost:4000/changes?since=0&wait=30'
+//
+// The change feed keeps the last DATABASE_CHANGELOG_SIZE mutations (10000 by default).
+//
+// curl -X POST 'localhost:4000/locks/nightly-job/acquire?ttl=30&wait=10'
+// curl -X POST 'localhost:4000/locks/nightly-job/renew?token=7&ttl=30'
+// curl -X POST 'localhost:4000/locks/nightly-job/release?token=7'
+// curl localhost:4000/locks/nightly-job
+//
+// curl -N 'localhost:4000/subscribe?channels=orders.*,users.created'
+// curl -X POST localhost:4000/publish/orders.created -H 'content-type: application/json' -d '{"id":1}'
+//
+// Published messages are not stored. A subscriber gets those sent while it is
+// connected, and a `lagged` event with the count of any it was too slow to take.
+//
+// Setting DATABASE_DATA_DIR keeps the store on disk: every write is appended
+// to a checksummed log there, with periodic snapshots. A write cut short by a
+// crash is dropped on startup; other damage stops the server until
+//
+//   database-server fsck <dir> [--repair]
+//
+// has checked the directory and, with --repair, salvaged what it could.
+//
+// DATABASE_DURABILITY picks when writes are acknowledged: `sync` fsyncs the
+// log for every write, `group` (the default) fsyncs every
+// DATABASE_GROUP_COMMIT_MS milliseconds (10 by default) on behalf of all the
+// writes since, and `buffered` leaves it to the OS. A write can ask for its
+// own policy, and every write response says in `x-durable` whether it is on disk:
+//
+// curl -X PUT 'localhost:4000/keys/order?durability=sync' -H 'content-type: application/json' -d '{}'
+//
+// Errors are RFC 7807 application/problem+json documents with a stable `code`.
+// The whole API is described at /openapi.json, and can be tried out from /docs.
+//
+// Setting DATABASE_ADMIN_TOKEN enables a dashboard at /admin for browsing and
+// editing keys and watching the server's load. It asks for the token once and
+// starts a session that lasts ADMIN_SESSION_TTL; /admin/stats and /admin/keys
+// also take the token as a bearer token:
+//
+// curl localhost:4000/admin/stats -H "authorization: Bearer $DATABASE_ADMIN_TOKEN"
+//
+// curl -X POST localhost:4000/mget -H 'content-type: application/json' -d '{"keys":["a","b"]}'
+// curl -X POST localhost:4000/mset -H 'content-type: application/json' \
+//   -d '{"entries":[{"key":"a","value":1},{"key":"b","value":{"x":2},"ttl":60}]}'
+
+const MERGE_PATCH: &str = "application/merge-patch+json";
+const JSON_PATCH: &str = "application/json-patch+json";
+
+// Responses that read or write a key carry the store version they saw.
+const VERSION_HEADER: &str = "x-version";
+// Write responses say whether the write has reached the disk.
+const DURABLE_HEADER: &str = "x-durable";
+const DEFAULT_SCAN_LIMIT: usize = 100;
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60);
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_CHANGELOG_SIZE: usize = 10_000;
+const DEFAULT_CHANGES_LIMIT: usize = 1_000;
+const MAX_CHANGES_WAIT: Duration = Duration::from_secs(60);
+const MAX_BATCH_SIZE: usize = 1_000;
+const DEFAULT_LEASE_TTL: Duration = Duration::from_secs(30);
+const MAX_LOCK_WAIT: Duration = Duration::from_secs(60);
+const LEASE_EXPIRY_INTERVAL: Duration = Duration::from_millis(100);
+const PUBSUB_BUFFER: usize = 1_024;
+// Once the log has grown past this, the next compaction writes a snapshot.
+const CHECKPOINT_LOG_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_GROUP_COMMIT: Duration = Duration::from_millis(10);
+// A group committed write not on disk by then is acknowledged as not durable.
+const GROUP_COMMIT_WAIT: Duration = Duration::from_secs(5);
+// Holds the dashboard session id once logged in, see `Sessions`.
+const ADMIN_COOKIE: &str = "admin_session";
+const ADMIN_SESSION_TTL: Duration = Duration::from_secs(12 * 60 * 60);
 
 #[derive(Deserialize, Debug)]
 pub struct GetQueryParams {
@@ -21,51 +160,2148 @@ pub struct GetQueryParams {
 pub struct SetQueryParams {
     pub key: String,
     pub value: String,
+    pub durability: Option<Durability>,
+}
+
+// Overrides the deployment's durability policy for one write.
+#[derive(Deserialize, Debug)]
+pub struct WriteParams {
+    pub durability: Option<Durability>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DocumentQueryParams {
+    pub path: Option<String>,
+    pub as_of: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PutParams {
+    pub ttl: Option<u64>,
+    pub durability: Option<Durability>,
+}
+
+// `wait` is how many seconds to hold the request open when there are no new
+// changes yet.
+#[derive(Deserialize, Debug)]
+pub struct ChangesParams {
+    pub since: Option<u64>,
+    pub limit: Option<usize>,
+    pub wait: Option<u64>,
+}
+
+// Pass `next` back as `since` to resume after the last change in this batch.
+#[derive(Serialize, Debug)]
+pub struct ChangesResponse {
+    pub changes: Vec<Change>,
+    pub next: u64,
+}
+
+impl ChangesResponse {
+    fn new(changes: Vec<Change>, since: u64) -> Self {
+        let next = changes.last().map_or(since, |c| c.offset);
+        ChangesResponse { changes, next }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ScanParams {
+    pub prefix: Option<String>,
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+    pub as_of: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScanEntry {
+    pub key: String,
+    pub value: Value,
+}
+
+// `next` is set when the page is full; pass it back as `after`, along with
+// `version` as `as_of`, to continue the scan on the same snapshot.
+#[derive(Serialize, Debug)]
+pub struct ScanResponse {
+    pub version: u64,
+    pub entries: Vec<ScanEntry>,
+    pub next: Option<String>,
+}
+
+// `expected: null` only matches a key without a value.
+#[derive(Deserialize, Debug)]
+pub struct CasRequest {
+    pub expected: Option<Value>,
+    pub value: Value,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MgetRequest {
+    pub keys: Vec<String>,
+    pub as_of: Option<u64>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MgetResult {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MgetResponse {
+    pub version: u64,
+    pub results: Vec<MgetResult>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MsetEntry {
+    pub key: String,
+    pub value: Value,
+    pub ttl: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MsetRequest {
+    pub entries: Vec<MsetEntry>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MsetResult {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MsetResponse {
+    pub results: Vec<MsetResult>,
+}
+
+// `ttl` is the lease length and `wait` how long to queue for a held lock, both
+// in seconds. Without `wait` a held lock is reported straight away.
+#[derive(Deserialize, Debug)]
+pub struct AcquireParams {
+    pub ttl: Option<u64>,
+    pub wait: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RenewParams {
+    pub token: u64,
+    pub ttl: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReleaseParams {
+    pub token: u64,
+}
+
+// A comma separated list of channel patterns, e.g. `orders.*,users.created`.
+#[derive(Deserialize, Debug)]
+pub struct SubscribeParams {
+    pub channels: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PublishResponse {
+    pub subscribers: usize,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IndexParams {
+    pub path: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QueryParams {
+    pub index: String,
+    pub eq: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoginForm {
+    pub token: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AdminStats {
+    pub keys: usize,
+    pub version: u64,
+    pub requests: u64,
+    pub requests_per_sec: f64,
+    pub memory_bytes: Option<u64>,
+    pub log_bytes: Option<u64>,
+    pub uptime_secs: u64,
 }
 
 #[derive(Clone)]
 struct AppState {
-    store: Arc<Mutex<HashMap<String, String>>>,
+    store: Arc<Mutex<Store>>,
+    leases: Arc<Mutex<Leases>>,
+    pubsub: Arc<PubSub>,
+    // Present when the store is kept on disk.
+    syncer: Option<Arc<Syncer>>,
+    durability: Durability,
+    // The dashboard is disabled without one.
+    admin_token: Option<Arc<str>>,
+    sessions: Arc<Sessions>,
+    stats: Arc<Stats>,
+}
+
+impl AppState {
+    fn store(&self) -> MutexGuard<'_, Store> {
+        lock_store(&self.store)
+    }
+
+    fn leases(&self) -> MutexGuard<'_, Leases> {
+        lock_leases(&self.leases)
+    }
+}
+
+// A handler that panics while holding the lock poisons it. Rather than failing
+// every later request, the lock is taken over and the indexes, the only state
+// derived from other state, are rebuilt in case the panic interrupted a write.
+fn lock_store(store: &Mutex<Store>) -> MutexGuard<'_, Store> {
+    store.lock().unwrap_or_else(|poisoned| {
+        eprintln!("store lock was poisoned by a panic, recovering");
+        let mut guard = poisoned.into_inner();
+        guard.rebuild_indexes();
+        store.clear_poison();
+        guard
+    })
+}
+
+// Leases have no derived state, so a poisoned lock is simply taken over.
+fn lock_leases(leases: &Mutex<Leases>) -> MutexGuard<'_, Leases> {
+    leases.lock().unwrap_or_else(|poisoned| {
+        leases.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "database-server", about = "A key/value store served over HTTP")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Verify a data directory while the server is stopped
+    Fsck {
+        dir: PathBuf,
+        /// Salvage readable records, keeping damaged files as *.damaged
+        #[arg(long)]
+        repair: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Some(Command::Fsck { dir, repair }) => check_data_dir(&dir, repair),
+        None => serve().await,
+    }
+}
+
+fn check_data_dir(dir: &std::path::Path, repair: bool) -> anyhow::Result<()> {
+    let reports = fsck(dir, repair)?;
+    if reports.is_empty() {
+        bail!("no data files found in {}", dir.display());
+    }
+    for report in &reports {
+        println!("{}", report);
+    }
+
+    let damaged = reports.iter().any(|r| !r.is_clean());
+    match (damaged, repair) {
+        (false, _) => Ok(()),
+        (true, true) => {
+            println!("salvaged what could be read, damaged files were kept as *.damaged");
+            Ok(())
+        }
+        (true, false) => bail!("data directory is damaged, run again with --repair to salvage it"),
+    }
+}
+
+async fn serve() -> anyhow::Result<()> {
+    let retention = retention_from_env()?;
+    let mut store = match std::env::var("DATABASE_DATA_DIR") {
+        Ok(dir) => {
+            let (store, recovery) = Store::open(dir.as_ref(), retention)?;
+            if let Some(range) = recovery.truncated {
+                eprintln!(
+                    "dropped a torn write at bytes {}..{} of the log",
+                    range.start, range.end
+                );
+            }
+            store
+        }
+        Err(_) => Store::with_retention(retention),
+    };
+    if let Ok(declared) = std::env::var("DATABASE_INDEXES") {
+        declare_indexes(&mut store, &declared)?;
+    }
+    store.rebuild_indexes();
+
+    let (durability, group_commit) = durability_from_env()?;
+    let syncer = store.syncer();
     let state = AppState {
-        store: Arc::new(Mutex::new(HashMap::new())),
+        store: Arc::new(Mutex::new(store)),
+        leases: Arc::new(Mutex::new(Leases::default())),
+        pubsub: Arc::new(PubSub::new(PUBSUB_BUFFER)),
+        syncer: syncer.clone(),
+        durability,
+        admin_token: std::env::var("DATABASE_ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            .map(Arc::from),
+        sessions: Arc::new(Sessions::new(ADMIN_SESSION_TTL)),
+        stats: Arc::new(Stats::default()),
     };
 
-    let app = Router::new()
-        .route("/get", get(get_value))
-        .route("/set", get(set_value))
-        .with_state(state);
+    if let Some(syncer) = syncer {
+        tokio::spawn(group_commit_periodically(syncer, group_commit));
+    }
+    tokio::spawn(compact_periodically(state.store.clone()));
+    tokio::spawn(expire_periodically(state.store.clone()));
+    tokio::spawn(expire_leases_periodically(state.leases.clone()));
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let addr = match std::env::var("DATABASE_ADDR") {
+        Ok(addr) => addr.parse()?,
+        Err(_) => SocketAddr::from(([127, 0, 0, 1], 4000)),
+    };
     let listener = TcpListener::bind(addr).await?;
+    println!("listening on {}", listener.local_addr()?);
+
+    axum::serve(listener, app(state).into_make_service()).await?;
+
+    Ok(())
+}
+
+// Every route the server answers, listed once so the OpenAPI document can be
+// checked against it.
+fn routes() -> Vec<(&'static str, MethodRouter<AppState>)> {
+    vec![
+        ("/get", get(get_value)),
+        ("/set", get(set_value)),
+        ("/keys", get(scan_keys)),
+        ("/keys/{key}/history", get(key_history)),
+        ("/keys/{key}/cas", post(compare_and_set)),
+        (
+            "/keys/{key}",
+            get(get_document)
+                .put(put_document)
+                .patch(patch_document)
+                .delete(delete_document),
+        ),
+        ("/changes", get(list_changes)),
+        ("/mget", post(mget)),
+        ("/mset", post(mset)),
+        ("/indexes", get(list_indexes)),
+        ("/indexes/{name}", put(create_index).delete(drop_index)),
+        ("/query", get(query_index)),
+        ("/locks/{name}", get(lock_status)),
+        ("/locks/{name}/acquire", post(acquire_lock)),
+        ("/locks/{name}/renew", post(renew_lock)),
+        ("/locks/{name}/release", post(release_lock)),
+        ("/publish/{channel}", post(publish)),
+        ("/subscribe", get(subscribe)),
+        ("/openapi.json", get(openapi_document)),
+        ("/docs", get(api_explorer)),
+        ("/admin", get(admin_dashboard)),
+        ("/admin/login", post(admin_login)),
+        ("/admin/logout", post(admin_logout)),
+        ("/admin/stats", get(admin_stats)),
+        (
+            "/admin/keys/{key}",
+            put(admin_put_document).delete(admin_delete_document),
+        ),
+    ]
+}
+
+fn app(state: AppState) -> Router {
+    routes()
+        .into_iter()
+        .fold(Router::new(), |router, (path, route)| {
+            router.route(path, route)
+        })
+        .layer(middleware::from_fn_with_state(
+            state.stats.clone(),
+            count_requests,
+        ))
+        .with_state(state)
+}
 
-    axum::serve(listener, app.into_make_service()).await?;
+// Parses a comma separated list of `name=path` pairs.
+fn declare_indexes(store: &mut Store, declared: &str) -> anyhow::Result<()> {
+    for declaration in declared.split(',').filter(|d| !d.trim().is_empty()) {
+        let Some((name, path)) = declaration.split_once('=') else {
+            anyhow::bail!("index declaration '{}' is not name=path", declaration);
+        };
+        store.create_index(name.trim(), path.trim())?;
+    }
 
     Ok(())
 }
 
-async fn get_value(params: Query<GetQueryParams>, State(state): State<AppState>) -> String {
+fn retention_from_env() -> anyhow::Result<Retention> {
+    let max_versions = match std::env::var("DATABASE_RETAIN_VERSIONS") {
+        Ok(n) => Some(n.parse()?),
+        Err(_) => None,
+    };
+    let max_age = match std::env::var("DATABASE_RETAIN_SECS") {
+        Ok(secs) => Some(Duration::from_secs(secs.parse()?)),
+        Err(_) => None,
+    };
+    let max_changes = match std::env::var("DATABASE_CHANGELOG_SIZE") {
+        Ok(n) => n.parse()?,
+        Err(_) => DEFAULT_CHANGELOG_SIZE,
+    };
+
+    Ok(Retention {
+        max_versions,
+        max_age,
+        max_changes: Some(max_changes),
+    })
+}
+
+fn durability_from_env() -> anyhow::Result<(Durability, Duration)> {
+    let durability = match std::env::var("DATABASE_DURABILITY") {
+        Ok(policy) => policy.parse().map_err(anyhow::Error::msg)?,
+        Err(_) => Durability::default(),
+    };
+    let group_commit = match std::env::var("DATABASE_GROUP_COMMIT_MS") {
+        Ok(ms) => Duration::from_millis(ms.parse()?),
+        Err(_) => DEFAULT_GROUP_COMMIT,
+    };
+
+    Ok((durability, group_commit))
+}
+
+async fn group_commit_periodically(syncer: Arc<Syncer>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        if let Some(written) = syncer.pending()
+            && let Err(e) = syncer.sync(written).await
+        {
+            eprintln!("group commit failed: {}", e);
+        }
+    }
+}
+
+// Waits for the write that created `version` according to the durability
+// policy, the request's own or else the deployment's, and reports whether it
+// is on disk. The store lock must not be held, so other writers can share the
+// same fsync.
+async fn settle(
+    state: &AppState,
+    version: u64,
+    requested: Option<Durability>,
+) -> Result<bool, ApiError> {
+    let Some(syncer) = &state.syncer else {
+        return Ok(false);
+    };
+
+    match requested.unwrap_or(state.durability) {
+        Durability::Sync => {
+            syncer.sync(version).await.map_err(StoreError::from)?;
+            Ok(true)
+        }
+        Durability::Group => Ok(syncer.wait(version, GROUP_COMMIT_WAIT).await),
+        Durability::Buffered => Ok(syncer.is_synced(version)),
+    }
+}
+
+async fn compact_periodically(store: Arc<Mutex<Store>>) {
+    let mut interval = tokio::time::interval(COMPACTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        let snapshot = {
+            let mut store = lock_store(&store);
+            store.compact();
+            match store
+                .log_len()
+                .is_some_and(|len| len > CHECKPOINT_LOG_BYTES)
+            {
+                true => store.snapshot(),
+                false => None,
+            }
+        };
+        let Some(snapshot) = snapshot else {
+            continue;
+        };
+
+        // Writing the snapshot out blocks on the disk, so it runs on its own
+        // thread and without the store lock. If writes land meanwhile the log
+        // is left for a later pass to cut.
+        let store = store.clone();
+        let checkpoint = tokio::task::spawn_blocking(move || {
+            snapshot.write()?;
+            lock_store(&store).truncate_log(&snapshot)
+        });
+        match checkpoint.await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => eprintln!("checkpoint failed: {}", e),
+            Err(e) => eprintln!("checkpoint failed: {}", e),
+        }
+    }
+}
+
+async fn expire_periodically(store: Arc<Mutex<Store>>) {
+    let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = lock_store(&store).expire_due() {
+            eprintln!("expiring keys failed: {}", e);
+        }
+    }
+}
+
+async fn expire_leases_periodically(leases: Arc<Mutex<Leases>>) {
+    let mut interval = tokio::time::interval(LEASE_EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        lock_leases(&leases).expire_due();
+    }
+}
+
+async fn get_value(
+    params: Result<Query<GetQueryParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<String, ApiError> {
+    let Query(params) = params?;
     let key = &params.key;
 
-    let store = state.store.lock().expect("mutex was poisoned");
+    let store = state.store();
     let returned_value = match store.get(key) {
-        Some(value) => value,
-        None => "No Value Set",
+        Some(Value::String(value)) => value.to_string(),
+        Some(value) => value.to_string(),
+        None => "No Value Set".to_string(),
     };
 
-    format!("get - key: {}, returned value: {}", key, returned_value)
+    Ok(format!(
+        "get - key: {}, returned value: {}",
+        key, returned_value
+    ))
 }
 
-async fn set_value(params: Query<SetQueryParams>, State(state): State<AppState>) -> String {
+async fn set_value(
+    params: Result<Query<SetQueryParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<([(&'static str, String); 1], String), ApiError> {
+    let Query(params) = params?;
     let key = &params.key;
     let value = &params.value;
 
-    let mut store = state.store.lock().expect("mutex was poisoned");
+    let version = state
+        .store()
+        .set(key.to_string(), Value::String(value.to_string()))?;
+    let durable = settle(&state, version, params.durability).await?;
+
+    Ok((
+        [(DURABLE_HEADER, durable.to_string())],
+        format!("set - key: {}, value: {}", key, value),
+    ))
+}
+
+async fn get_document(
+    Path(key): Path<String>,
+    params: Result<Query<DocumentQueryParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<([(&'static str, String); 1], Json<Value>), ApiError> {
+    let Query(params) = params?;
+    let path = JsonPath::parse(params.path.as_deref().unwrap_or("$"))?;
+
+    let store = state.store();
+    let as_of = params.as_of.unwrap_or(store.version());
+    let Some(document) = store.get_at(&key, as_of)? else {
+        return Err(ApiError::KeyNotFound(key));
+    };
+
+    match path.select(document) {
+        Some(value) => Ok(([(VERSION_HEADER, as_of.to_string())], Json(value.clone()))),
+        None => Err(ApiError::PathNotFound(key, params.path.unwrap_or_default())),
+    }
+}
+
+async fn put_document(
+    Path(key): Path<String>,
+    params: Result<Query<PutParams>, QueryRejection>,
+    State(state): State<AppState>,
+    document: Result<Json<Value>, JsonRejection>,
+) -> Result<(StatusCode, [(&'static str, String); 2]), ApiError> {
+    let Query(params) = params?;
+    let Json(document) = document?;
+
+    let (status, version) = {
+        let mut store = state.store();
+
+        let status = match store.get(&key) {
+            Some(_) => StatusCode::NO_CONTENT,
+            None => StatusCode::CREATED,
+        };
+        let version = match params.ttl {
+            Some(ttl) => store.set_with_ttl(key, document, Duration::from_secs(ttl))?,
+            None => store.set(key, document)?,
+        };
+        (status, version)
+    };
+    let durable = settle(&state, version, params.durability).await?;
+
+    Ok((
+        status,
+        [
+            (VERSION_HEADER, version.to_string()),
+            (DURABLE_HEADER, durable.to_string()),
+        ],
+    ))
+}
+
+async fn delete_document(
+    Path(key): Path<String>,
+    params: Result<Query<WriteParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<(StatusCode, [(&'static str, String); 2]), ApiError> {
+    let Query(params) = params?;
+
+    let deleted = state.store().delete(&key)?;
+    let Some(version) = deleted else {
+        return Err(ApiError::KeyNotFound(key));
+    };
+    let durable = settle(&state, version, params.durability).await?;
+
+    Ok((
+        StatusCode::NO_CONTENT,
+        [
+            (VERSION_HEADER, version.to_string()),
+            (DURABLE_HEADER, durable.to_string()),
+        ],
+    ))
+}
+
+async fn compare_and_set(
+    Path(key): Path<String>,
+    params: Result<Query<WriteParams>, QueryRejection>,
+    State(state): State<AppState>,
+    request: Result<Json<CasRequest>, JsonRejection>,
+) -> Result<[(&'static str, String); 2], ApiError> {
+    let Query(params) = params?;
+    let Json(request) = request?;
+
+    let expected = request.expected.as_ref();
+    let swapped = state
+        .store()
+        .compare_and_set(key.clone(), expected, request.value)?;
+    let Some(version) = swapped else {
+        return Err(ApiError::CompareFailed(key));
+    };
+    let durable = settle(&state, version, params.durability).await?;
+
+    Ok([
+        (VERSION_HEADER, version.to_string()),
+        (DURABLE_HEADER, durable.to_string()),
+    ])
+}
+
+// Long-polls the mutation log: answers straight away when there are changes
+// after `since`, otherwise waits up to `wait` seconds for the next write.
+async fn list_changes(
+    params: Result<Query<ChangesParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<ChangesResponse>, ApiError> {
+    let Query(params) = params?;
+    let since = params.since.unwrap_or(0);
+    let limit = params.limit.unwrap_or(DEFAULT_CHANGES_LIMIT);
+    let wait = Duration::from_secs(params.wait.unwrap_or(0)).min(MAX_CHANGES_WAIT);
+
+    let mut latest = {
+        let store = state.store();
+        let changes = store.changes_since(since, limit)?;
+        if !changes.is_empty() || wait.is_zero() {
+            return Ok(Json(ChangesResponse::new(changes, since)));
+        }
+        store.subscribe()
+    };
+
+    // A timeout just means an empty batch; the client polls again with the same offset.
+    let _ = tokio::time::timeout(wait, latest.wait_for(|version| *version > since)).await;
+
+    let changes = state.store().changes_since(since, limit)?;
+    Ok(Json(ChangesResponse::new(changes, since)))
+}
+
+async fn patch_document(
+    Path(key): Path<String>,
+    params: Result<Query<WriteParams>, QueryRejection>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<([(&'static str, String); 2], Json<Value>), ApiError> {
+    let Query(params) = params?;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let (version, patched) = {
+        let mut store = state.store();
+        let current = store.get(&key).cloned();
+
+        let patched = apply_patch(&key, current, content_type, &body)?;
+        (store.set(key, patched.clone())?, patched)
+    };
+    let durable = settle(&state, version, params.durability).await?;
+
+    Ok((
+        [
+            (VERSION_HEADER, version.to_string()),
+            (DURABLE_HEADER, durable.to_string()),
+        ],
+        Json(patched),
+    ))
+}
+
+async fn scan_keys(
+    params: Result<Query<ScanParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<ScanResponse>, ApiError> {
+    let Query(params) = params?;
+    let prefix = params.prefix.unwrap_or_default();
+    let limit = params.limit.unwrap_or(DEFAULT_SCAN_LIMIT);
+
+    let store = state.store();
+    let version = params.as_of.unwrap_or(store.version());
+    let entries: Vec<ScanEntry> = store
+        .scan(&prefix, params.after.as_deref(), limit, version)?
+        .into_iter()
+        .map(|(key, value)| ScanEntry {
+            key: key.to_string(),
+            value: value.clone(),
+        })
+        .collect();
+
+    let next = match entries.len() == limit {
+        true => entries.last().map(|e| e.key.clone()),
+        false => None,
+    };
+
+    Ok(Json(ScanResponse {
+        version,
+        entries,
+        next,
+    }))
+}
+
+async fn key_history(
+    Path(key): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<VecDeque<Version>>, ApiError> {
+    let store = state.store();
+
+    match store.history(&key) {
+        Some(history) => Ok(Json(history.clone())),
+        None => Err(ApiError::KeyNotFound(key)),
+    }
+}
+
+fn check_batch_size(len: usize) -> Result<(), ApiError> {
+    match len > MAX_BATCH_SIZE {
+        true => Err(ApiError::BatchTooLarge(len, MAX_BATCH_SIZE)),
+        false => Ok(()),
+    }
+}
+
+// All keys are read under one lock acquisition, so the results come from a
+// single snapshot even without `as_of`.
+async fn mget(
+    State(state): State<AppState>,
+    request: Result<Json<MgetRequest>, JsonRejection>,
+) -> Result<Json<MgetResponse>, ApiError> {
+    let Json(request) = request?;
+    check_batch_size(request.keys.len())?;
+
+    let store = state.store();
+    let version = request.as_of.unwrap_or(store.version());
+    let results = mget_results(&store, request.keys, version);
+
+    Ok(Json(MgetResponse { version, results }))
+}
+
+fn mget_results(store: &Store, keys: Vec<String>, version: u64) -> Vec<MgetResult> {
+    keys.into_iter()
+        .map(|key| {
+            let (value, error) = match store.get_at(&key, version) {
+                Ok(Some(value)) => (Some(value.clone()), None),
+                Ok(None) => (None, Some(format!("no value set for key {}", key))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            MgetResult { key, value, error }
+        })
+        .collect()
+}
+
+async fn mset(
+    params: Result<Query<WriteParams>, QueryRejection>,
+    State(state): State<AppState>,
+    request: Result<Json<MsetRequest>, JsonRejection>,
+) -> Result<([(&'static str, String); 1], Json<MsetResponse>), ApiError> {
+    let Query(params) = params?;
+    let Json(request) = request?;
+    check_batch_size(request.entries.len())?;
+
+    let results = mset_results(&mut state.store(), request.entries);
+    // Waiting for the last write covers all the ones before it. Nothing was
+    // written when every entry failed, so nothing is durable either.
+    let durable = match results.iter().filter_map(|r| r.version).max() {
+        Some(last) => settle(&state, last, params.durability).await?,
+        None => false,
+    };
+
+    Ok((
+        [(DURABLE_HEADER, durable.to_string())],
+        Json(MsetResponse { results }),
+    ))
+}
+
+fn mset_results(store: &mut Store, entries: Vec<MsetEntry>) -> Vec<MsetResult> {
+    entries
+        .into_iter()
+        .map(|entry| {
+            if entry.key.is_empty() {
+                return MsetResult {
+                    key: entry.key,
+                    version: None,
+                    error: Some("key must not be empty".to_string()),
+                };
+            }
+
+            let key = entry.key.clone();
+            let written = match entry.ttl {
+                Some(ttl) => store.set_with_ttl(entry.key, entry.value, Duration::from_secs(ttl)),
+                None => store.set(entry.key, entry.value),
+            };
+            match written {
+                Ok(version) => MsetResult {
+                    key,
+                    version: Some(version),
+                    error: None,
+                },
+                Err(e) => MsetResult {
+                    key,
+                    version: None,
+                    error: Some(e.to_string()),
+                },
+            }
+        })
+        .collect()
+}
+
+async fn list_indexes(State(state): State<AppState>) -> Json<BTreeMap<String, String>> {
+    let store = state.store();
+
+    let indexes = store
+        .indexes()
+        .map(|(name, path)| (name.to_string(), path.to_string()))
+        .collect();
+
+    Json(indexes)
+}
+
+async fn create_index(
+    Path(name): Path<String>,
+    params: Result<Query<IndexParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let Query(params) = params?;
+    let mut store = state.store();
+
+    store.create_index(&name, &params.path)?;
+
+    Ok(StatusCode::CREATED)
+}
+
+async fn drop_index(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let mut store = state.store();
+
+    match store.drop_index(&name)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(ApiError::IndexNotFound(name)),
+    }
+}
+
+async fn query_index(
+    params: Result<Query<QueryParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let Query(params) = params?;
+    let store = state.store();
+
+    match store.query(&params.index, &params.eq) {
+        Some(keys) => Ok(Json(keys)),
+        None => Err(ApiError::IndexNotFound(params.index)),
+    }
+}
+
+fn lease_ttl(ttl: Option<u64>) -> Result<Duration, ApiError> {
+    match ttl {
+        Some(0) => Err(ApiError::InvalidQuery(
+            "ttl must be at least 1 second".to_string(),
+        )),
+        Some(ttl) => Ok(Duration::from_secs(ttl)),
+        None => Ok(DEFAULT_LEASE_TTL),
+    }
+}
+
+async fn lock_status(Path(name): Path<String>, State(state): State<AppState>) -> Json<LockStatus> {
+    Json(state.leases().status(&name))
+}
+
+async fn acquire_lock(
+    Path(name): Path<String>,
+    params: Result<Query<AcquireParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Grant>, ApiError> {
+    let Query(params) = params?;
+    let ttl = lease_ttl(params.ttl)?;
+    let wait = Duration::from_secs(params.wait.unwrap_or(0)).min(MAX_LOCK_WAIT);
+
+    let acquired = state.leases().acquire(&name, ttl, !wait.is_zero());
+    let (waiter, mut receiver) = match acquired {
+        Acquire::Granted(grant) => return Ok(Json(grant)),
+        Acquire::Busy => return Err(ApiError::LockHeld(name)),
+        Acquire::Waiting(waiter, receiver) => (waiter, receiver),
+    };
+
+    if let Ok(Ok(grant)) = tokio::time::timeout(wait, &mut receiver).await {
+        return Ok(Json(grant));
+    }
+
+    // The lock may have been handed over between the timeout and taking the
+    // lease lock, in which case the grant is already in the channel.
+    let mut leases = state.leases();
+    if leases.cancel(&name, waiter) {
+        return Err(ApiError::LockHeld(name));
+    }
+    receiver
+        .try_recv()
+        .map(Json)
+        .map_err(|_| ApiError::LockHeld(name))
+}
+
+async fn renew_lock(
+    Path(name): Path<String>,
+    params: Result<Query<RenewParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Grant>, ApiError> {
+    let Query(params) = params?;
+    let ttl = lease_ttl(params.ttl)?;
+
+    let grant = state.leases().renew(&name, params.token, ttl)?;
+
+    Ok(Json(grant))
+}
+
+async fn release_lock(
+    Path(name): Path<String>,
+    params: Result<Query<ReleaseParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let Query(params) = params?;
+
+    state.leases().release(&name, params.token)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn publish(
+    Path(channel): Path<String>,
+    State(state): State<AppState>,
+    payload: Result<Json<Value>, JsonRejection>,
+) -> Result<Json<PublishResponse>, ApiError> {
+    let Json(payload) = payload?;
+
+    let subscribers = state.pubsub.publish(&channel, payload)?;
+
+    Ok(Json(PublishResponse { subscribers }))
+}
+
+async fn subscribe(
+    params: Result<Query<SubscribeParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let Query(params) = params?;
+    let patterns = params
+        .channels
+        .split(',')
+        .map(Pattern::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let messages = BroadcastStream::new(state.pubsub.subscribe()).filter_map(move |received| {
+        let event = match received {
+            Ok(message) if patterns.iter().any(|p| p.matches(&message.channel)) => Event::default()
+                .event("message")
+                .json_data(&*message)
+                .ok()?,
+            Ok(_) => return None,
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                Event::default().event("lagged").data(skipped.to_string())
+            }
+        };
+        Some(Ok(event))
+    });
+
+    Ok(Sse::new(messages).keep_alive(KeepAlive::default()))
+}
+
+async fn count_requests(State(stats): State<Arc<Stats>>, request: Request, next: Next) -> Response {
+    stats.record(Instant::now());
+    next.run(request).await
+}
+
+// Compares in a time that does not depend on where the first difference is.
+fn same_secret(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn admin_token(state: &AppState) -> Result<&str, ApiError> {
+    state.admin_token.as_deref().ok_or_else(|| {
+        ApiError::Unauthorized(
+            "the admin dashboard is disabled, set DATABASE_ADMIN_TOKEN to enable it".to_string(),
+        )
+    })
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|c| c.trim().strip_prefix(ADMIN_COOKIE)?.strip_prefix('='))
+}
+
+// Admin requests carry the token as a bearer token, or the session cookie set
+// by logging in to the dashboard.
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let token = admin_token(state)?;
+
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|given| same_secret(given.as_bytes(), token.as_bytes()));
+    let cookie = session_cookie(headers).map(|id| state.sessions.is_live(id, Instant::now()));
+
+    match bearer.or(cookie) {
+        Some(true) => Ok(()),
+        Some(false) => Err(ApiError::Unauthorized(
+            "wrong admin token or expired session".to_string(),
+        )),
+        None => Err(ApiError::Unauthorized(
+            "an admin token is required".to_string(),
+        )),
+    }
+}
+
+fn login_page(message: &str) -> Response {
+    let page = include_str!("admin_login.html").replace("{message}", message);
+    (StatusCode::UNAUTHORIZED, Html(page)).into_response()
+}
+
+async fn admin_dashboard(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    match authorize(&state, &headers) {
+        Ok(()) => Html(include_str!("admin.html")).into_response(),
+        Err(_) if state.admin_token.is_some() => login_page(""),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn admin_login(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    form: Result<Form<LoginForm>, FormRejection>,
+) -> Result<Response, ApiError> {
+    let Form(form) = form.map_err(|e| ApiError::InvalidBody(e.body_text()))?;
+    let token = admin_token(&state)?;
+    if !same_secret(form.token.as_bytes(), token.as_bytes()) {
+        return Ok(login_page("Wrong token."));
+    }
+
+    let id = state.sessions.start(Instant::now());
+    let mut cookie = format!(
+        "{}={}; Path=/admin; HttpOnly; SameSite=Strict; Max-Age={}",
+        ADMIN_COOKIE,
+        id,
+        state.sessions.ttl().as_secs()
+    );
+    // The server speaks plain HTTP, so TLS is only ever ended by a proxy in
+    // front of it.
+    let https = headers
+        .get("x-forwarded-proto")
+        .is_some_and(|proto| proto.as_bytes().eq_ignore_ascii_case(b"https"));
+    if https {
+        cookie.push_str("; Secure");
+    }
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to("/admin")).into_response())
+}
+
+async fn admin_logout(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Some(id) = session_cookie(&headers) {
+        state.sessions.end(id);
+    }
+    let cookie = format!(
+        "{}=; Path=/admin; HttpOnly; SameSite=Strict; Max-Age=0",
+        ADMIN_COOKIE
+    );
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/admin"))
+}
+
+// The dashboard saves and deletes keys through these rather than /keys, so
+// that only a logged in admin can edit from it.
+async fn admin_put_document(
+    key: Path<String>,
+    params: Result<Query<PutParams>, QueryRejection>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    document: Result<Json<Value>, JsonRejection>,
+) -> Result<(StatusCode, [(&'static str, String); 2]), ApiError> {
+    authorize(&state, &headers)?;
+    put_document(key, params, State(state), document).await
+}
+
+async fn admin_delete_document(
+    key: Path<String>,
+    params: Result<Query<WriteParams>, QueryRejection>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, [(&'static str, String); 2]), ApiError> {
+    authorize(&state, &headers)?;
+    delete_document(key, params, State(state)).await
+}
+
+async fn admin_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<AdminStats>, ApiError> {
+    authorize(&state, &headers)?;
+
+    let now = Instant::now();
+    let store = state.store();
+    Ok(Json(AdminStats {
+        keys: store.key_count(),
+        version: store.version(),
+        requests: state.stats.total(),
+        requests_per_sec: state.stats.rate(now),
+        memory_bytes: stats::memory_bytes(),
+        log_bytes: store.log_len(),
+        uptime_secs: state.stats.uptime(now).as_secs(),
+    }))
+}
+
+async fn openapi_document() -> Json<Value> {
+    Json(openapi::document())
+}
+
+async fn api_explorer() -> Html<&'static str> {
+    Html(include_str!("docs.html"))
+}
+
+// Patches are applied to a copy of the document, so a failing operation leaves
+// the stored value untouched.
+fn apply_patch(
+    key: &str,
+    current: Option<Value>,
+    content_type: &str,
+    body: &[u8],
+) -> Result<Value, ApiError> {
+    let invalid_body = |e: serde_json::Error| ApiError::InvalidBody(e.to_string());
+
+    match content_type.split(';').next().unwrap_or_default().trim() {
+        MERGE_PATCH => {
+            let merge: Value = serde_json::from_slice(body).map_err(invalid_body)?;
+            let mut document = current.unwrap_or(Value::Null);
+            json_patch::merge(&mut document, &merge);
+            Ok(document)
+        }
+        JSON_PATCH => {
+            let patch: json_patch::Patch = serde_json::from_slice(body).map_err(invalid_body)?;
+            let Some(mut document) = current else {
+                return Err(ApiError::KeyNotFound(key.to_string()));
+            };
+            json_patch::patch(&mut document, &patch)
+                .map_err(|e| ApiError::PatchConflict(e.to_string()))?;
+            Ok(document)
+        }
+        other => Err(ApiError::UnsupportedMediaType(format!(
+            "unsupported content type '{}', expected {} or {}",
+            other, MERGE_PATCH, JSON_PATCH
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::Message;
+    use crate::store::ChangeKind;
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    fn test_state() -> AppState {
+        AppState {
+            store: Arc::new(Mutex::new(Store::default())),
+            leases: Arc::new(Mutex::new(Leases::default())),
+            pubsub: Arc::new(PubSub::new(PUBSUB_BUFFER)),
+            syncer: None,
+            durability: Durability::default(),
+            admin_token: Some(Arc::from("secret")),
+            sessions: Arc::new(Sessions::new(ADMIN_SESSION_TTL)),
+            stats: Arc::new(Stats::default()),
+        }
+    }
+
+    async fn send(
+        state: &AppState,
+        request: Request<Body>,
+    ) -> Result<(StatusCode, Value), Box<dyn std::error::Error>> {
+        let response = app(state.clone()).oneshot(request).await?;
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        let body = match body.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_slice(&body)?,
+        };
+        Ok((status, body))
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri)
+            .body(Body::empty())
+            .expect("valid request")
+    }
+
+    #[tokio::test]
+    async fn missing_key_is_a_problem() -> Result<(), Box<dyn std::error::Error>> {
+        let (status, body) = send(&test_state(), get("/keys/nope")).await?;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "key_not_found");
+        assert_eq!(body["status"], 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejections_are_problems() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+
+        let (status, body) = send(&state, get("/keys?limit=many")).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_query");
+
+        let (status, body) = send(&state, get("/keys/a?path=nope")).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_path");
+
+        let request = Request::put("/keys/a").body(Body::from("{}"))?;
+        let (status, body) = send(&state, request).await?;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["code"], "unsupported_media_type");
+
+        let request = Request::post("/mget")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{\"keys\": 1}"))?;
+        let (status, body) = send(&state, request).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_body");
+
+        Ok(())
+    }
+
+    fn post(uri: &str) -> Request<Body> {
+        Request::post(uri)
+            .body(Body::empty())
+            .expect("valid request")
+    }
+
+    #[tokio::test]
+    async fn compare_and_set_swaps_the_expected_value() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+        let cas = |body: Value| {
+            Request::post("/keys/a/cas")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .expect("valid request")
+        };
+
+        let (status, _) = send(&state, cas(json!({"expected": null, "value": 1}))).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&state, cas(json!({"expected": null, "value": 2}))).await?;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "compare_failed");
+
+        let (status, _) = send(&state, cas(json!({"expected": 1, "value": 2}))).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.store().get("a"), Some(&json!(2)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_waiter_gets_lock_on_release() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+        let (status, holder) = send(&state, post("/locks/job/acquire?ttl=30")).await?;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&state, post("/locks/job/acquire")).await?;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "lock_held");
+
+        let waiter = tokio::spawn({
+            let state = state.clone();
+            async move { send(&state, post("/locks/job/acquire?wait=5")).await.ok() }
+        });
+        while state.leases().status("job").waiters == 0 {
+            tokio::task::yield_now().await;
+        }
+        let release = format!("/locks/job/release?token={}", holder["token"]);
+        let (status, _) = send(&state, post(&release)).await?;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (status, next) = waiter.await?.ok_or("waiter failed")?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(next["token"].as_u64() > holder["token"].as_u64());
+
+        let (status, body) = send(&state, post(&release)).await?;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "lease_not_held");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_wait_times_out() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+        send(&state, post("/locks/job/acquire")).await?;
+
+        let (status, body) = send(&state, post("/locks/job/acquire?wait=1")).await?;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "lock_held");
+        assert_eq!(state.leases().status("job").waiters, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_matching_messages() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+        let response = app(state.clone())
+            .oneshot(get("/subscribe?channels=orders.*"))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut events = response.into_body().into_data_stream();
+
+        let publish = |channel: &str, body: &str| {
+            Request::post(format!("/publish/{}", channel))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .expect("valid request")
+        };
+        let (status, body) = send(&state, publish("users.created", "1")).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["subscribers"], 1);
+        send(&state, publish("orders.created", r#"{"id":7}"#)).await?;
+
+        let event = events.next().await.ok_or("stream ended")??;
+        let event = std::str::from_utf8(&event)?;
+        assert!(event.starts_with("event: message\n"));
+        let data = event.lines().find_map(|l| l.strip_prefix("data: "));
+        let data: Value = serde_json::from_str(data.ok_or("no data")?)?;
+        assert_eq!(data["channel"], "orders.created");
+        assert_eq!(data["payload"], json!({"id": 7}));
+
+        let (status, body) = send(&state, publish("orders.*", "1")).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_channel");
+
+        let (status, body) = send(&state, get("/subscribe?channels=orders.")).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_pattern");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_report_durability() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (store, _) = Store::open(dir.path(), Retention::default())?;
+        let state = AppState {
+            syncer: store.syncer(),
+            durability: Durability::Buffered,
+            store: Arc::new(Mutex::new(store)),
+            ..test_state()
+        };
+        let durable =
+            |response: &axum::response::Response| response.headers().get(DURABLE_HEADER).cloned();
+        let put = |uri: &str| {
+            Request::put(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from("1"))
+                .expect("valid request")
+        };
+
+        let response = app(state.clone()).oneshot(put("/keys/a")).await?;
+        assert_eq!(durable(&response), Some("false".parse()?));
+
+        let response = app(state.clone())
+            .oneshot(put("/keys/b?durability=sync"))
+            .await?;
+        assert_eq!(durable(&response), Some("true".parse()?));
+
+        let response = app(state.clone())
+            .oneshot(get("/set?key=c&value=x&durability=sync"))
+            .await?;
+        assert_eq!(durable(&response), Some("true".parse()?));
+
+        let response = app(state.clone())
+            .oneshot(
+                Request::post("/mset?durability=sync")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"entries":[{"key":"","value":1}]}"#))?,
+            )
+            .await?;
+        assert_eq!(durable(&response), Some("false".parse()?));
+
+        let (status, body) = send(&state, put("/keys/d?durability=eventually")).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_query");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn poisoned_lock_is_recovered() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+        {
+            let mut store = state.store();
+            store.create_index("email", "$.email")?;
+            store.set("a".to_string(), json!({"email": "x"}))?;
+        }
+
+        let store = state.store.clone();
+        let panicked = std::thread::spawn(move || {
+            let _guard = store.lock();
+            panic!("handler bug");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(state.store.is_poisoned());
+
+        let (status, body) = send(&state, get("/query?index=email&eq=x")).await?;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!(["a"]));
+        assert!(!state.store.is_poisoned());
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_patch_creates_and_updates() -> Result<(), Box<dyn std::error::Error>> {
+        let patched = apply_patch("doc", None, MERGE_PATCH, br#"{"a":{"b":1}}"#)?;
+        assert_eq!(patched, json!({"a": {"b": 1}}));
+
+        let patched = apply_patch(
+            "doc",
+            Some(patched),
+            "application/merge-patch+json; charset=utf-8",
+            br#"{"a":{"b":null,"c":2}}"#,
+        )?;
+        assert_eq!(patched, json!({"a": {"c": 2}}));
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_patch_applies_operations() -> Result<(), Box<dyn std::error::Error>> {
+        let current = json!({"a": {"b": 1}, "list": [1, 2]});
+        let body = br#"[
+            {"op": "replace", "path": "/a/b", "value": 3},
+            {"op": "add", "path": "/list/-", "value": 3}
+        ]"#;
+
+        let patched = apply_patch("doc", Some(current), JSON_PATCH, body)?;
+
+        assert_eq!(patched, json!({"a": {"b": 3}, "list": [1, 2, 3]}));
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_patch_failed_test_is_conflict() {
+        let current = json!({"a": 1});
+        let body = br#"[{"op": "test", "path": "/a", "value": 2}]"#;
+
+        let result = apply_patch("doc", Some(current), JSON_PATCH, body);
+
+        assert_eq!(result.map_err(|e| e.code()), Err("patch_conflict"));
+    }
+
+    #[test]
+    fn json_patch_missing_key_is_not_found() {
+        let result = apply_patch("doc", None, JSON_PATCH, b"[]");
+
+        assert_eq!(result.map_err(|e| e.code()), Err("key_not_found"));
+    }
+
+    #[test]
+    fn declare_indexes_from_list() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+
+        declare_indexes(&mut store, "email=$.email, age = $.age")?;
+
+        let declared: Vec<(&str, &str)> = store.indexes().collect();
+        assert_eq!(declared, vec![("age", "$.age"), ("email", "$.email")]);
+        assert!(declare_indexes(&mut store, "email").is_err());
+        assert!(declare_indexes(&mut store, "email=email").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mget_reports_missing_keys() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        let version = store.set("a".to_string(), json!(1))?;
+        store.set("a".to_string(), json!(2))?;
+
+        let results = mget_results(&store, vec!["a".to_string(), "b".to_string()], version);
+
+        assert_eq!(
+            results,
+            vec![
+                MgetResult {
+                    key: "a".to_string(),
+                    value: Some(json!(1)),
+                    error: None,
+                },
+                MgetResult {
+                    key: "b".to_string(),
+                    value: None,
+                    error: Some("no value set for key b".to_string()),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mset_writes_valid_entries() {
+        let mut store = Store::default();
+        let entries = vec![
+            MsetEntry {
+                key: "a".to_string(),
+                value: json!(1),
+                ttl: None,
+            },
+            MsetEntry {
+                key: "".to_string(),
+                value: json!(2),
+                ttl: None,
+            },
+            MsetEntry {
+                key: "c".to_string(),
+                value: json!(3),
+                ttl: Some(60),
+            },
+        ];
+
+        let results = mset_results(&mut store, entries);
+
+        let versions: Vec<Option<u64>> = results.iter().map(|r| r.version).collect();
+        assert_eq!(versions, vec![Some(1), None, Some(2)]);
+        assert!(results[1].error.is_some());
+        assert_eq!(store.get("c"), Some(&json!(3)));
+    }
+
+    #[test]
+    fn batches_are_limited() {
+        assert!(check_batch_size(MAX_BATCH_SIZE).is_ok());
+        assert_eq!(
+            check_batch_size(MAX_BATCH_SIZE + 1).map_err(|e| e.code()),
+            Err("batch_too_large")
+        );
+    }
+
+    #[test]
+    fn unknown_content_type_is_rejected() {
+        let result = apply_patch("doc", None, "application/json", b"{}");
+
+        assert_eq!(result.map_err(|e| e.code()), Err("unsupported_media_type"));
+    }
+
+    // Fails when a route or method is added to or removed from the router
+    // without updating the OpenAPI document, or the other way round. The
+    // methods a path answers are read from the `allow` header of a 405.
+    #[tokio::test]
+    async fn openapi_document_matches_the_router() -> Result<(), Box<dyn std::error::Error>> {
+        let document = openapi::document();
+        let paths = document["paths"].as_object().ok_or("no paths")?;
+
+        let routed: Vec<&str> = routes().into_iter().map(|(path, _)| path).collect();
+        let mut documented: Vec<&str> = paths.keys().map(String::as_str).collect();
+        let mut sorted = routed.clone();
+        sorted.sort();
+        documented.sort();
+        assert_eq!(
+            sorted, documented,
+            "routed paths differ from documented paths"
+        );
+
+        for path in routed {
+            let uri = path.replace(['{', '}'], "");
+            let request = Request::builder()
+                .method("TRACE")
+                .uri(&uri)
+                .body(Body::empty())?;
+            let response = app(test_state()).oneshot(request).await?;
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{}",
+                path
+            );
+
+            let allow = response
+                .headers()
+                .get(header::ALLOW)
+                .ok_or("no allow header")?
+                .to_str()?;
+            let mut allowed: Vec<String> = allow
+                .split(',')
+                .map(|m| m.trim().to_lowercase())
+                .filter(|m| m != "head")
+                .collect();
+            let mut methods: Vec<String> = paths[path]
+                .as_object()
+                .ok_or("no methods")?
+                .keys()
+                .cloned()
+                .collect();
+            allowed.sort();
+            methods.sort();
+            assert_eq!(allowed, methods, "methods of {} differ", path);
+        }
+
+        Ok(())
+    }
+
+    // Stands in for a query string or a request body to find out which fields
+    // a derived `Deserialize` reads, which serde passes to `deserialize_struct`.
+    struct FieldNames;
+
+    #[derive(Debug)]
+    struct Fields(&'static [&'static str]);
+
+    impl std::fmt::Display for Fields {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "fields {:?}", self.0)
+        }
+    }
+
+    impl std::error::Error for Fields {}
+
+    impl de::Error for Fields {
+        fn custom<T: std::fmt::Display>(_: T) -> Self {
+            Fields(&[])
+        }
+    }
+
+    impl<'de> Deserializer<'de> for FieldNames {
+        type Error = Fields;
+
+        fn deserialize_any<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value, Fields> {
+            Err(Fields(&[]))
+        }
+
+        fn deserialize_struct<V: de::Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Fields> {
+            Err(Fields(fields))
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes
+            byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    fn struct_fields<T: de::DeserializeOwned>() -> Vec<String> {
+        let mut fields: Vec<String> = match T::deserialize(FieldNames) {
+            Ok(_) => vec![],
+            Err(Fields(fields)) => fields.iter().map(|f| f.to_string()).collect(),
+        };
+        fields.sort();
+        fields
+    }
+
+    // Handlers that take their query string as `Result<Query<P>, _>`, first
+    // or second, so the parameters of a route are read off its handler. `M`
+    // only tells the impls apart.
+    trait ReadsQuery<P, M> {}
+
+    macro_rules! reads_query {
+        ($($before:ident),* ; $($after:ident),*) => {
+            impl<F, R, P, $($before,)* $($after,)*>
+                ReadsQuery<P, (($($before,)*), ($($after,)*))> for F
+            where
+                F: Fn($($before,)* Result<Query<P>, QueryRejection>, $($after,)*) -> R,
+            {
+            }
+        };
+    }
+
+    reads_query!(; B);
+    reads_query!(; B, C);
+    reads_query!(A; C);
+    reads_query!(A; C, D);
+    reads_query!(A; C, D, E);
+
+    fn query_of<P: de::DeserializeOwned, M>(_: impl ReadsQuery<P, M>) -> Vec<String> {
+        struct_fields::<P>()
+    }
+
+    #[test]
+    fn openapi_query_parameters_match_the_extractors() -> Result<(), Box<dyn std::error::Error>> {
+        let document = openapi::document();
+        let extracted = [
+            ("/get", "get", query_of(get_value)),
+            ("/set", "get", query_of(set_value)),
+            ("/keys", "get", query_of(scan_keys)),
+            ("/keys/{key}", "get", query_of(get_document)),
+            ("/keys/{key}", "put", query_of(put_document)),
+            ("/keys/{key}", "patch", query_of(patch_document)),
+            ("/keys/{key}", "delete", query_of(delete_document)),
+            ("/keys/{key}/cas", "post", query_of(compare_and_set)),
+            ("/changes", "get", query_of(list_changes)),
+            ("/mset", "post", query_of(mset)),
+            ("/indexes/{name}", "put", query_of(create_index)),
+            ("/query", "get", query_of(query_index)),
+            ("/locks/{name}/acquire", "post", query_of(acquire_lock)),
+            ("/locks/{name}/renew", "post", query_of(renew_lock)),
+            ("/locks/{name}/release", "post", query_of(release_lock)),
+            ("/subscribe", "get", query_of(subscribe)),
+            ("/admin/keys/{key}", "put", query_of(admin_put_document)),
+            (
+                "/admin/keys/{key}",
+                "delete",
+                query_of(admin_delete_document),
+            ),
+        ];
+
+        let paths = document["paths"].as_object().ok_or("no paths")?;
+        for (path, operations) in paths {
+            for (method, operation) in operations.as_object().ok_or("no methods")? {
+                let mut documented = vec![];
+                for parameter in operation["parameters"].as_array().into_iter().flatten() {
+                    let parameter = resolve(&document, parameter)?;
+                    if parameter["in"] == "query" {
+                        documented.push(parameter["name"].as_str().ok_or("unnamed parameter")?);
+                    }
+                }
+                documented.sort();
+
+                let expected = extracted
+                    .iter()
+                    .find(|(p, m, _)| p == path && m == method)
+                    .map(|(_, _, fields)| fields.clone())
+                    .unwrap_or_default();
+                assert_eq!(
+                    documented, expected,
+                    "query parameters of {} {} differ",
+                    method, path
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    // Follows a `$ref` within the document.
+    fn resolve<'a>(
+        document: &'a Value,
+        node: &'a Value,
+    ) -> Result<&'a Value, Box<dyn std::error::Error>> {
+        match node["$ref"].as_str() {
+            Some(reference) => Ok(document
+                .pointer(reference.trim_start_matches('#'))
+                .ok_or_else(|| format!("unresolved reference {}", reference))?),
+            None => Ok(node),
+        }
+    }
+
+    fn properties(schema: &Value) -> Vec<String> {
+        let mut names: Vec<String> = schema["properties"]
+            .as_object()
+            .map(|properties| properties.keys().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    // Checks `sample`, serialized from a response type with every optional
+    // field set, against `schema`: the same properties at every level, and
+    // none required that the type does not write.
+    fn check_sample(
+        document: &Value,
+        schema: &Value,
+        sample: &Value,
+        at: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let schema = resolve(document, schema)?;
+        match sample {
+            Value::Object(fields) if schema.get("properties").is_some() => {
+                let mut written: Vec<String> = fields.keys().cloned().collect();
+                written.sort();
+                assert_eq!(properties(schema), written, "properties of {} differ", at);
+                for required in schema["required"].as_array().into_iter().flatten() {
+                    let required = required.as_str().ok_or("unnamed property")?;
+                    assert!(
+                        fields.contains_key(required),
+                        "{}.{} is not written",
+                        at,
+                        required
+                    );
+                }
+                for (name, value) in fields {
+                    let at = format!("{}.{}", at, name);
+                    check_sample(document, &schema["properties"][name], value, &at)?;
+                }
+            }
+            Value::Array(items) if schema.get("items").is_some() => {
+                for item in items {
+                    check_sample(document, &schema["items"], item, &format!("{}[]", at))?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Fails when a request or response type gains or loses a field without the
+    // component schema describing it following.
+    #[tokio::test]
+    async fn openapi_schemas_match_the_serde_types() -> Result<(), Box<dyn std::error::Error>> {
+        let document = openapi::document();
+        let schemas = document["components"]["schemas"]
+            .as_object()
+            .ok_or("no schemas")?;
+        let mut checked = vec![];
+
+        // Request bodies are only deserialized, so their fields are read off
+        // `Deserialize`, nested ones included.
+        let requests = [
+            ("CasRequest", "", struct_fields::<CasRequest>()),
+            ("MgetRequest", "", struct_fields::<MgetRequest>()),
+            ("MsetRequest", "", struct_fields::<MsetRequest>()),
+            (
+                "MsetRequest",
+                "/properties/entries/items",
+                struct_fields::<MsetEntry>(),
+            ),
+        ];
+        for (name, pointer, fields) in requests {
+            let schema = schemas[name].pointer(pointer).ok_or("no such schema")?;
+            assert_eq!(
+                properties(schema),
+                fields,
+                "properties of {}{} differ",
+                name,
+                pointer
+            );
+            checked.push(name);
+        }
+
+        let version = Version {
+            version: 1,
+            timestamp_ms: 2,
+            kind: ChangeKind::Set,
+            value: Some(json!(3)),
+        };
+        let change = Change {
+            offset: 1,
+            timestamp_ms: 2,
+            kind: ChangeKind::Set,
+            key: "a".to_string(),
+            value: Some(json!(3)),
+        };
+        let grant = Grant {
+            name: "a".to_string(),
+            token: 1,
+            ttl_ms: 2,
+        };
+        let problem = ApiError::KeyNotFound("a".to_string()).into_response();
+        let problem: Value =
+            serde_json::from_slice(&to_bytes(problem.into_body(), usize::MAX).await?)?;
+        let responses = [
+            ("Problem", problem),
+            ("Version", serde_json::to_value(&version)?),
+            ("Change", serde_json::to_value(&change)?),
+            (
+                "ChangesResponse",
+                serde_json::to_value(ChangesResponse {
+                    changes: vec![change.clone()],
+                    next: 1,
+                })?,
+            ),
+            (
+                "ScanResponse",
+                serde_json::to_value(ScanResponse {
+                    version: 1,
+                    entries: vec![ScanEntry {
+                        key: "a".to_string(),
+                        value: json!(1),
+                    }],
+                    next: Some("a".to_string()),
+                })?,
+            ),
+            (
+                "MgetResponse",
+                serde_json::to_value(MgetResponse {
+                    version: 1,
+                    results: vec![MgetResult {
+                        key: "a".to_string(),
+                        value: Some(json!(1)),
+                        error: Some("e".to_string()),
+                    }],
+                })?,
+            ),
+            (
+                "MsetResponse",
+                serde_json::to_value(MsetResponse {
+                    results: vec![MsetResult {
+                        key: "a".to_string(),
+                        version: Some(1),
+                        error: Some("e".to_string()),
+                    }],
+                })?,
+            ),
+            ("Grant", serde_json::to_value(&grant)?),
+            (
+                "LockStatus",
+                serde_json::to_value(LockStatus {
+                    name: "a".to_string(),
+                    token: Some(1),
+                    ttl_ms: Some(2),
+                    waiters: 3,
+                })?,
+            ),
+            (
+                "PublishResponse",
+                serde_json::to_value(PublishResponse { subscribers: 1 })?,
+            ),
+            (
+                "AdminStats",
+                serde_json::to_value(AdminStats {
+                    keys: 1,
+                    version: 2,
+                    requests: 3,
+                    requests_per_sec: 4.0,
+                    memory_bytes: Some(5),
+                    log_bytes: Some(6),
+                    uptime_secs: 7,
+                })?,
+            ),
+            (
+                "Message",
+                serde_json::to_value(Message {
+                    channel: "a".to_string(),
+                    payload: json!(1),
+                    timestamp_ms: 2,
+                })?,
+            ),
+        ];
+        for (name, sample) in responses {
+            check_sample(&document, &schemas[name], &sample, name)?;
+            checked.push(name);
+        }
+
+        let mut documented: Vec<&str> = schemas.keys().map(String::as_str).collect();
+        documented.sort();
+        checked.sort();
+        checked.dedup();
+        assert_eq!(
+            documented, checked,
+            "schemas without a type to check against"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_the_api_explorer() -> Result<(), Box<dyn std::error::Error>> {
+        let (status, body) = send(&test_state(), get("/openapi.json")).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["openapi"], "3.0.3");
+
+        let response = app(test_state()).oneshot(get("/docs")).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = to_bytes(response.into_body(), usize::MAX).await?;
+        assert!(String::from_utf8(page.to_vec())?.contains("/openapi.json"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admin_stats_need_the_token() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+        state.store().set("a".to_string(), json!(1))?;
+        let stats = |authorization: &str| {
+            Request::get("/admin/stats")
+                .header(header::AUTHORIZATION, authorization)
+                .body(Body::empty())
+                .expect("valid request")
+        };
+
+        let (status, body) = send(&state, get("/admin/stats")).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "unauthorized");
+
+        let (status, _) = send(&state, stats("Bearer wrong")).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = send(&state, stats("Bearer secret")).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["keys"], 1);
+        assert_eq!(body["requests"], 3);
+
+        let disabled = AppState {
+            admin_token: None,
+            ..test_state()
+        };
+        let (status, body) = send(&disabled, stats("Bearer secret")).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(
+            body["detail"]
+                .as_str()
+                .unwrap_or_default()
+                .contains("disabled")
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dashboard_login_starts_a_session() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+        let login = |token: &str| {
+            Request::post("/admin/login")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header("x-forwarded-proto", "https")
+                .body(Body::from(format!("token={}", token)))
+                .expect("valid request")
+        };
+        let with_cookie = |request: axum::http::request::Builder, cookie: &str| {
+            request
+                .header(header::COOKIE, cookie)
+                .body(Body::empty())
+                .expect("valid request")
+        };
+
+        let response = app(state.clone()).oneshot(get("/admin")).await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app(state.clone()).oneshot(login("wrong")).await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+        let response = app(state.clone()).oneshot(login("secret")).await?;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let set_cookie = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .ok_or("no cookie")?
+            .to_str()?;
+        assert!(set_cookie.contains("; Secure"));
+        assert!(set_cookie.contains("; Max-Age=43200"));
+        let cookie = set_cookie
+            .split(';')
+            .next()
+            .ok_or("empty cookie")?
+            .to_string();
+        assert!(
+            !cookie.contains("736563726574"),
+            "the token is not in the cookie"
+        );
+
+        let response = app(state.clone())
+            .oneshot(with_cookie(Request::get("/admin"), &cookie))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = to_bytes(response.into_body(), usize::MAX).await?;
+        assert!(String::from_utf8(page.to_vec())?.contains("/admin/stats"));
+
+        let response = app(state.clone())
+            .oneshot(with_cookie(Request::post("/admin/logout"), &cookie))
+            .await?;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let response = app(state.clone())
+            .oneshot(with_cookie(Request::get("/admin"), &cookie))
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dashboard_edits_need_the_token() -> Result<(), Box<dyn std::error::Error>> {
+        let state = test_state();
+        let put = |authorization: &str| {
+            Request::put("/admin/keys/a")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, authorization)
+                .body(Body::from("1"))
+                .expect("valid request")
+        };
+        let delete = |authorization: &str| {
+            Request::delete("/admin/keys/a")
+                .header(header::AUTHORIZATION, authorization)
+                .body(Body::empty())
+                .expect("valid request")
+        };
+
+        let (status, _) = send(&state, put("Bearer wrong")).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(state.store().get("a"), None);
+
+        let (status, _) = send(&state, put("Bearer secret")).await?;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(state.store().get("a"), Some(&json!(1)));
 
-    store.insert(key.to_string(), value.to_string());
+        let (status, _) = send(&state, delete("Bearer wrong")).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(&state, delete("Bearer secret")).await?;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(state.store().get("a"), None);
 
-    format!("set - key: {}, value: {}", key, value)
+        Ok(())
+    }
 }
@@ -0,0 +1,755 @@
+use serde_json::{Value, json};
+
+// The OpenAPI 3 description of the HTTP API, served at /openapi.json and
+// rendered by the explorer at /docs. Tests in main.rs check it against the
+// router, the query extractors of the handlers and the serde types behind the
+// component schemas, so a route, query parameter or field added or removed
+// without updating this fails the build.
+
+fn path_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": {"type": "string"},
+    })
+}
+
+fn query_param(name: &str, schema: Value, required: bool, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": required,
+        "description": description,
+        "schema": schema,
+    })
+}
+
+fn integer() -> Value {
+    json!({"type": "integer", "minimum": 0})
+}
+
+fn string() -> Value {
+    json!({"type": "string"})
+}
+
+fn schema(name: &str) -> Value {
+    json!({"$ref": format!("#/components/schemas/{}", name)})
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({"content": {"application/json": {"schema": schema}}})
+}
+
+fn ok(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": {"application/json": {"schema": schema}},
+    })
+}
+
+fn empty(description: &str) -> Value {
+    json!({"description": description})
+}
+
+fn problem(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {"application/problem+json": {"schema": schema("Problem")}},
+    })
+}
+
+fn header(name: &str) -> Value {
+    json!({"$ref": format!("#/components/headers/{}", name)})
+}
+
+fn key() -> Value {
+    path_param("key", "The key")
+}
+
+fn durability() -> Value {
+    json!({"$ref": "#/components/parameters/durability"})
+}
+
+fn as_of() -> Value {
+    query_param(
+        "as_of",
+        integer(),
+        false,
+        "Read as of this store version instead of the latest",
+    )
+}
+
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "database-server",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "A key/value store of JSON documents with history, \
+                secondary indexes, a change feed, leases and pub/sub. Errors are \
+                RFC 7807 problem documents with a stable `code`.",
+        },
+        "paths": {
+            "/get": {
+                "get": {
+                    "tags": ["legacy"],
+                    "summary": "Read a key as text",
+                    "parameters": [query_param("key", string(), true, "The key")],
+                    "responses": {
+                        "200": {
+                            "description": "The value, or a note that none is set",
+                            "content": {"text/plain": {"schema": string()}},
+                        },
+                        "400": problem("Missing key"),
+                    },
+                },
+            },
+            "/set": {
+                "get": {
+                    "tags": ["legacy"],
+                    "summary": "Write a key as a string",
+                    "parameters": [
+                        query_param("key", string(), true, "The key"),
+                        query_param("value", string(), true, "The value"),
+                        durability(),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Written",
+                            "headers": {"x-durable": header("durable")},
+                            "content": {"text/plain": {"schema": string()}},
+                        },
+                        "400": problem("Invalid parameters"),
+                    },
+                },
+            },
+            "/keys": {
+                "get": {
+                    "tags": ["keys"],
+                    "summary": "Scan keys by prefix",
+                    "description": "Pass `next` back as `after`, and `version` as \
+                        `as_of`, to read the following page from the same snapshot.",
+                    "parameters": [
+                        query_param("prefix", string(), false, "Only keys starting with this"),
+                        query_param("after", string(), false, "Start after this key"),
+                        query_param("limit", integer(), false, "Page size, 100 by default"),
+                        as_of(),
+                    ],
+                    "responses": {
+                        "200": ok("A page of entries", schema("ScanResponse")),
+                        "400": problem("Invalid parameters"),
+                        "410": problem("The snapshot is no longer retained"),
+                    },
+                },
+            },
+            "/keys/{key}": {
+                "get": {
+                    "tags": ["keys"],
+                    "summary": "Read a document, or part of it",
+                    "parameters": [
+                        key(),
+                        query_param("path", string(), false, "JSON path into the document, e.g. $.a.b"),
+                        as_of(),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The document or the selected value",
+                            "headers": {"x-version": header("version")},
+                            "content": {"application/json": {"schema": {}}},
+                        },
+                        "400": problem("Invalid path or version"),
+                        "404": problem("No such key or path"),
+                        "410": problem("The version is no longer retained"),
+                    },
+                },
+                "put": {
+                    "tags": ["keys"],
+                    "summary": "Write a document",
+                    "parameters": [
+                        key(),
+                        query_param("ttl", integer(), false, "Expire the key after this many seconds"),
+                        durability(),
+                    ],
+                    "requestBody": {"required": true, "content": {"application/json": {"schema": {}}}},
+                    "responses": {
+                        "201": {"description": "Created", "headers": write_headers()},
+                        "204": {"description": "Replaced", "headers": write_headers()},
+                        "400": problem("Invalid body or parameters"),
+                        "415": problem("Body is not JSON"),
+                    },
+                },
+                "patch": {
+                    "tags": ["keys"],
+                    "summary": "Patch a document",
+                    "description": "Send a JSON merge patch (RFC 7396) as \
+                        application/merge-patch+json or a JSON patch (RFC 6902) as \
+                        application/json-patch+json.",
+                    "parameters": [key(), durability()],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/merge-patch+json": {"schema": {}},
+                            "application/json-patch+json": {"schema": {"type": "array", "items": {"type": "object"}}},
+                        },
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "The patched document",
+                            "headers": write_headers(),
+                            "content": {"application/json": {"schema": {}}},
+                        },
+                        "400": problem("Invalid patch"),
+                        "409": problem("The patch does not apply"),
+                        "415": problem("Unsupported patch format"),
+                    },
+                },
+                "delete": {
+                    "tags": ["keys"],
+                    "summary": "Delete a key",
+                    "parameters": [key(), durability()],
+                    "responses": {
+                        "204": {"description": "Deleted", "headers": write_headers()},
+                        "404": problem("No value for the key"),
+                    },
+                },
+            },
+            "/keys/{key}/history": {
+                "get": {
+                    "tags": ["keys"],
+                    "summary": "Retained versions of a key, oldest first",
+                    "parameters": [key()],
+                    "responses": {
+                        "200": ok("The versions", json!({"type": "array", "items": schema("Version")})),
+                        "404": problem("The key was never written"),
+                    },
+                },
+            },
+            "/keys/{key}/cas": {
+                "post": {
+                    "tags": ["keys"],
+                    "summary": "Compare and swap",
+                    "description": "Sets the key to `value` only if it holds `expected`; \
+                        an `expected` of null matches a key without a value.",
+                    "parameters": [key(), durability()],
+                    "requestBody": json_body(schema("CasRequest")),
+                    "responses": {
+                        "200": {"description": "Swapped", "headers": write_headers()},
+                        "400": problem("Invalid body"),
+                        "409": problem("The key holds a different value"),
+                        "415": problem("Body is not JSON"),
+                    },
+                },
+            },
+            "/changes": {
+                "get": {
+                    "tags": ["changes"],
+                    "summary": "Read the change feed",
+                    "description": "Returns changes after `since`, or waits up to \
+                        `wait` seconds for one. Pass `next` back as `since` to resume.",
+                    "parameters": [
+                        query_param("since", integer(), false, "Offset of the last change seen"),
+                        query_param("limit", integer(), false, "At most this many changes"),
+                        query_param("wait", integer(), false, "Seconds to wait for a change, at most 60"),
+                    ],
+                    "responses": {
+                        "200": ok("A batch of changes", schema("ChangesResponse")),
+                        "400": problem("Invalid parameters"),
+                        "410": problem("Changes since the offset are no longer retained"),
+                    },
+                },
+            },
+            "/mget": {
+                "post": {
+                    "tags": ["batch"],
+                    "summary": "Read many keys from one snapshot",
+                    "requestBody": json_body(schema("MgetRequest")),
+                    "responses": {
+                        "200": ok("One result per key", schema("MgetResponse")),
+                        "400": problem("Invalid body"),
+                        "413": problem("Too many keys"),
+                    },
+                },
+            },
+            "/mset": {
+                "post": {
+                    "tags": ["batch"],
+                    "summary": "Write many keys",
+                    "parameters": [durability()],
+                    "requestBody": json_body(schema("MsetRequest")),
+                    "responses": {
+                        "200": {
+                            "description": "One result per entry",
+                            "headers": {"x-durable": header("durable")},
+                            "content": {"application/json": {"schema": schema("MsetResponse")}},
+                        },
+                        "400": problem("Invalid body"),
+                        "413": problem("Too many entries"),
+                    },
+                },
+            },
+            "/indexes": {
+                "get": {
+                    "tags": ["indexes"],
+                    "summary": "List indexes and the path each covers",
+                    "responses": {
+                        "200": ok("Index names to JSON paths", json!({"type": "object", "additionalProperties": string()})),
+                    },
+                },
+            },
+            "/indexes/{name}": {
+                "put": {
+                    "tags": ["indexes"],
+                    "summary": "Create or replace an index",
+                    "parameters": [
+                        path_param("name", "The index name"),
+                        query_param("path", string(), true, "JSON path of the indexed field"),
+                    ],
+                    "responses": {
+                        "201": empty("Created"),
+                        "400": problem("Invalid path"),
+                    },
+                },
+                "delete": {
+                    "tags": ["indexes"],
+                    "summary": "Drop an index",
+                    "parameters": [path_param("name", "The index name")],
+                    "responses": {
+                        "204": empty("Dropped"),
+                        "404": problem("No such index"),
+                    },
+                },
+            },
+            "/query": {
+                "get": {
+                    "tags": ["indexes"],
+                    "summary": "Keys whose indexed field equals a value",
+                    "parameters": [
+                        query_param("index", string(), true, "The index name"),
+                        query_param("eq", string(), true, "The value to match"),
+                    ],
+                    "responses": {
+                        "200": ok("Matching keys", json!({"type": "array", "items": string()})),
+                        "404": problem("No such index"),
+                    },
+                },
+            },
+            "/locks/{name}": {
+                "get": {
+                    "tags": ["locks"],
+                    "summary": "Who holds a lock",
+                    "parameters": [path_param("name", "The lock name")],
+                    "responses": {
+                        "200": ok("The lock", schema("LockStatus")),
+                    },
+                },
+            },
+            "/locks/{name}/acquire": {
+                "post": {
+                    "tags": ["locks"],
+                    "summary": "Acquire a lock",
+                    "parameters": [
+                        path_param("name", "The lock name"),
+                        query_param("ttl", integer(), false, "Lease length in seconds, 30 by default"),
+                        query_param("wait", integer(), false, "Seconds to queue for a held lock, at most 60"),
+                    ],
+                    "responses": {
+                        "200": ok("Granted, with a fencing token", schema("Grant")),
+                        "400": problem("Invalid parameters"),
+                        "409": problem("Held by someone else"),
+                    },
+                },
+            },
+            "/locks/{name}/renew": {
+                "post": {
+                    "tags": ["locks"],
+                    "summary": "Extend a lease",
+                    "parameters": [
+                        path_param("name", "The lock name"),
+                        query_param("token", integer(), true, "The token of the grant"),
+                        query_param("ttl", integer(), false, "New lease length in seconds"),
+                    ],
+                    "responses": {
+                        "200": ok("Renewed", schema("Grant")),
+                        "409": problem("The lease was lost"),
+                    },
+                },
+            },
+            "/locks/{name}/release": {
+                "post": {
+                    "tags": ["locks"],
+                    "summary": "Release a lock",
+                    "parameters": [
+                        path_param("name", "The lock name"),
+                        query_param("token", integer(), true, "The token of the grant"),
+                    ],
+                    "responses": {
+                        "204": empty("Released"),
+                        "409": problem("The lease was lost"),
+                    },
+                },
+            },
+            "/publish/{channel}": {
+                "post": {
+                    "tags": ["pubsub"],
+                    "summary": "Publish a message",
+                    "parameters": [path_param("channel", "Dot separated channel name")],
+                    "requestBody": {"required": true, "content": {"application/json": {"schema": {}}}},
+                    "responses": {
+                        "200": ok("Offered to the open subscriptions", schema("PublishResponse")),
+                        "400": problem("Invalid channel or body"),
+                    },
+                },
+            },
+            "/subscribe": {
+                "get": {
+                    "tags": ["pubsub"],
+                    "summary": "Subscribe to channels as server-sent events",
+                    "description": "Sends a `message` event per matching message, \
+                        and a `lagged` event with the number dropped when the \
+                        subscriber falls behind.",
+                    "parameters": [
+                        query_param("channels", string(), true, "Comma separated patterns; `*` matches one segment"),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "An event stream of messages",
+                            "content": {"text/event-stream": {"schema": schema("Message")}},
+                        },
+                        "400": problem("Invalid pattern"),
+                    },
+                },
+            },
+            "/openapi.json": {
+                "get": {
+                    "tags": ["meta"],
+                    "summary": "This document",
+                    "responses": {"200": ok("The OpenAPI document", json!({"type": "object"}))},
+                },
+            },
+            "/docs": {
+                "get": {
+                    "tags": ["meta"],
+                    "summary": "An explorer for this document",
+                    "responses": {
+                        "200": {"description": "HTML page", "content": {"text/html": {}}},
+                    },
+                },
+            },
+            "/admin": {
+                "get": {
+                    "tags": ["admin"],
+                    "summary": "The admin dashboard",
+                    "description": "Needs DATABASE_ADMIN_TOKEN to be set. Without \
+                        the token this is a login page.",
+                    "security": admin_security(),
+                    "responses": {
+                        "200": {"description": "HTML page", "content": {"text/html": {}}},
+                        "401": {"description": "Login page, or a problem when the dashboard is disabled"},
+                    },
+                },
+            },
+            "/admin/login": {
+                "post": {
+                    "tags": ["admin"],
+                    "summary": "Log in to the dashboard",
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/x-www-form-urlencoded": {
+                                "schema": {
+                                    "type": "object",
+                                    "required": ["token"],
+                                    "properties": {"token": string()},
+                                },
+                            },
+                        },
+                    },
+                    "responses": {
+                        "303": {
+                            "description": "Logged in, with a session cookie set",
+                            "headers": {"set-cookie": {"schema": string()}},
+                        },
+                        "401": {"description": "Wrong token"},
+                    },
+                },
+            },
+            "/admin/logout": {
+                "post": {
+                    "tags": ["admin"],
+                    "summary": "Log out of the dashboard",
+                    "responses": {"303": {"description": "The session is ended and its cookie cleared"}},
+                },
+            },
+            "/admin/stats": {
+                "get": {
+                    "tags": ["admin"],
+                    "summary": "Live server statistics",
+                    "security": admin_security(),
+                    "responses": {
+                        "200": ok("The statistics", schema("AdminStats")),
+                        "401": problem("Missing or wrong admin token"),
+                    },
+                },
+            },
+            "/admin/keys/{key}": admin_keys(),
+        },
+        "components": {
+            "parameters": {
+                "durability": query_param(
+                    "durability",
+                    json!({"type": "string", "enum": ["sync", "group", "buffered"]}),
+                    false,
+                    "When to acknowledge the write; the deployment's policy by default",
+                ),
+            },
+            "headers": {
+                "version": {
+                    "description": "The store version the request read or wrote",
+                    "schema": integer(),
+                },
+                "durable": {
+                    "description": "Whether the write is on disk",
+                    "schema": {"type": "boolean"},
+                },
+            },
+            "securitySchemes": {
+                "adminToken": {"type": "http", "scheme": "bearer"},
+                "adminCookie": {"type": "apiKey", "in": "cookie", "name": "admin_session"},
+            },
+            "schemas": schemas(),
+        },
+    })
+}
+
+// The dashboard's own write routes, which need the admin token or session.
+fn admin_keys() -> Value {
+    json!({
+        "put": {
+            "tags": ["admin"],
+            "summary": "Write a document from the dashboard",
+            "description": "Like `PUT /keys/{key}`, but needs the admin token or session.",
+            "security": admin_security(),
+            "parameters": [
+                key(),
+                query_param("ttl", integer(), false, "Expire the key after this many seconds"),
+                durability(),
+            ],
+            "requestBody": {"required": true, "content": {"application/json": {"schema": {}}}},
+            "responses": {
+                "201": {"description": "Created", "headers": write_headers()},
+                "204": {"description": "Replaced", "headers": write_headers()},
+                "400": problem("Invalid body or parameters"),
+                "401": problem("Missing or wrong admin token"),
+                "415": problem("Body is not JSON"),
+            },
+        },
+        "delete": {
+            "tags": ["admin"],
+            "summary": "Delete a key from the dashboard",
+            "description": "Like `DELETE /keys/{key}`, but needs the admin token or session.",
+            "security": admin_security(),
+            "parameters": [key(), durability()],
+            "responses": {
+                "204": {"description": "Deleted", "headers": write_headers()},
+                "401": problem("Missing or wrong admin token"),
+                "404": problem("No value for the key"),
+            },
+        },
+    })
+}
+
+fn admin_security() -> Value {
+    json!([{"adminToken": []}, {"adminCookie": []}])
+}
+
+fn write_headers() -> Value {
+    json!({"x-version": header("version"), "x-durable": header("durable")})
+}
+
+fn schemas() -> Value {
+    let change_kind = json!({"type": "string", "enum": ["set", "delete", "expire"]});
+    json!({
+        "Problem": {
+            "type": "object",
+            "required": ["type", "title", "status", "detail", "code"],
+            "properties": {
+                "type": string(),
+                "title": string(),
+                "status": {"type": "integer"},
+                "detail": string(),
+                "code": string(),
+            },
+        },
+        "Version": {
+            "type": "object",
+            "required": ["version", "timestamp_ms", "kind"],
+            "properties": {
+                "version": integer(),
+                "timestamp_ms": integer(),
+                "kind": change_kind,
+                "value": {},
+            },
+        },
+        "Change": {
+            "type": "object",
+            "required": ["offset", "timestamp_ms", "kind", "key"],
+            "properties": {
+                "offset": integer(),
+                "timestamp_ms": integer(),
+                "kind": change_kind,
+                "key": string(),
+                "value": {},
+            },
+        },
+        "ChangesResponse": {
+            "type": "object",
+            "required": ["changes", "next"],
+            "properties": {
+                "changes": {"type": "array", "items": schema("Change")},
+                "next": integer(),
+            },
+        },
+        "ScanResponse": {
+            "type": "object",
+            "required": ["version", "entries"],
+            "properties": {
+                "version": integer(),
+                "entries": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["key", "value"],
+                        "properties": {"key": string(), "value": {}},
+                    },
+                },
+                "next": {"type": "string", "nullable": true},
+            },
+        },
+        "CasRequest": {
+            "type": "object",
+            "required": ["expected", "value"],
+            "properties": {"expected": {"nullable": true}, "value": {}},
+        },
+        "MgetRequest": {
+            "type": "object",
+            "required": ["keys"],
+            "properties": {
+                "keys": {"type": "array", "items": string()},
+                "as_of": integer(),
+            },
+        },
+        "MgetResponse": {
+            "type": "object",
+            "required": ["version", "results"],
+            "properties": {
+                "version": integer(),
+                "results": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["key"],
+                        "properties": {"key": string(), "value": {}, "error": string()},
+                    },
+                },
+            },
+        },
+        "MsetRequest": {
+            "type": "object",
+            "required": ["entries"],
+            "properties": {
+                "entries": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["key", "value"],
+                        "properties": {"key": string(), "value": {}, "ttl": integer()},
+                    },
+                },
+            },
+        },
+        "MsetResponse": {
+            "type": "object",
+            "required": ["results"],
+            "properties": {
+                "results": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["key"],
+                        "properties": {"key": string(), "version": integer(), "error": string()},
+                    },
+                },
+            },
+        },
+        "Grant": {
+            "type": "object",
+            "required": ["name", "token", "ttl_ms"],
+            "properties": {"name": string(), "token": integer(), "ttl_ms": integer()},
+        },
+        "LockStatus": {
+            "type": "object",
+            "required": ["name", "waiters"],
+            "properties": {
+                "name": string(),
+                "token": {"type": "integer", "nullable": true},
+                "ttl_ms": {"type": "integer", "nullable": true},
+                "waiters": integer(),
+            },
+        },
+        "PublishResponse": {
+            "type": "object",
+            "required": ["subscribers"],
+            "properties": {"subscribers": integer()},
+        },
+        "AdminStats": {
+            "type": "object",
+            "required": ["keys", "version", "requests", "requests_per_sec", "uptime_secs"],
+            "properties": {
+                "keys": integer(),
+                "version": integer(),
+                "requests": integer(),
+                "requests_per_sec": {"type": "number"},
+                "memory_bytes": {"type": "integer", "nullable": true},
+                "log_bytes": {"type": "integer", "nullable": true},
+                "uptime_secs": integer(),
+            },
+        },
+        "Message": {
+            "type": "object",
+            "required": ["channel", "payload", "timestamp_ms"],
+            "properties": {"channel": string(), "payload": {}, "timestamp_ms": integer()},
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every `$ref` has to point at something under `components`.
+    #[test]
+    fn references_resolve() {
+        fn check(document: &Value, node: &Value) {
+            match node {
+                Value::Object(map) => {
+                    if let Some(Value::String(reference)) = map.get("$ref") {
+                        let pointer = reference.trim_start_matches('#');
+                        assert!(
+                            document.pointer(pointer).is_some(),
+                            "dangling reference {}",
+                            reference
+                        );
+                    }
+                    map.values().for_each(|v| check(document, v));
+                }
+                Value::Array(items) => items.iter().for_each(|v| check(document, v)),
+                _ => {}
+            }
+        }
+
+        let document = document();
+        check(&document, &document);
+    }
+}
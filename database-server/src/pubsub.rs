@@ -0,0 +1,172 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::store::now_ms;
+
+// Fire-and-forget messaging between clients, kept apart from the key/value
+// store. Channels are dot separated names such as `orders.created`, and a
+// subscription pattern may use `*` to match any one segment: `orders.*`.
+// Delivery is at-most-once; a subscriber that falls too far behind loses the
+// oldest messages rather than holding up publishers.
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Message {
+    pub channel: String,
+    pub payload: Value,
+    pub timestamp_ms: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PubSubError {
+    InvalidChannel(String),
+    InvalidPattern(String),
+}
+
+impl fmt::Display for PubSubError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PubSubError::InvalidChannel(channel) => write!(
+                f,
+                "invalid channel {:?}: expected dot separated names without wildcards",
+                channel
+            ),
+            PubSubError::InvalidPattern(pattern) => write!(
+                f,
+                "invalid pattern {:?}: expected dot separated names or *",
+                pattern
+            ),
+        }
+    }
+}
+
+impl Error for PubSubError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    segments: Vec<Option<String>>,
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern, PubSubError> {
+        let segments = pattern
+            .split('.')
+            .map(|segment| match segment {
+                "*" => Ok(None),
+                s if valid_segment(s) => Ok(Some(s.to_string())),
+                _ => Err(PubSubError::InvalidPattern(pattern.to_string())),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Pattern { segments })
+    }
+
+    pub fn matches(&self, channel: &str) -> bool {
+        let mut names = channel.split('.');
+        let all_match = self
+            .segments
+            .iter()
+            .all(|segment| match (segment, names.next()) {
+                (_, None) => false,
+                (None, Some(_)) => true,
+                (Some(expected), Some(name)) => expected == name,
+            });
+        all_match && names.next().is_none()
+    }
+}
+
+fn valid_segment(segment: &str) -> bool {
+    !segment.is_empty() && !segment.contains('*')
+}
+
+pub fn validate_channel(channel: &str) -> Result<(), PubSubError> {
+    if channel.split('.').all(valid_segment) {
+        Ok(())
+    } else {
+        Err(PubSubError::InvalidChannel(channel.to_string()))
+    }
+}
+
+pub struct PubSub {
+    sender: broadcast::Sender<Arc<Message>>,
+}
+
+impl PubSub {
+    // `capacity` is how many messages a slow subscriber may fall behind by
+    // before it starts missing them.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        PubSub { sender }
+    }
+
+    // Returns the number of open subscriptions the message was offered to,
+    // whether or not their patterns match the channel.
+    pub fn publish(&self, channel: &str, payload: Value) -> Result<usize, PubSubError> {
+        validate_channel(channel)?;
+
+        let message = Message {
+            channel: channel.to_string(),
+            payload,
+            timestamp_ms: now_ms(),
+        };
+        Ok(self.sender.send(Arc::new(message)).unwrap_or(0))
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Message>> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn patterns_match_whole_segments() -> Result<(), Box<dyn std::error::Error>> {
+        let orders = Pattern::parse("orders.*")?;
+        assert!(orders.matches("orders.created"));
+        assert!(!orders.matches("orders"));
+        assert!(!orders.matches("orders.created.eu"));
+        assert!(!orders.matches("ordersx.created"));
+
+        let middle = Pattern::parse("orders.*.eu")?;
+        assert!(middle.matches("orders.created.eu"));
+        assert!(!middle.matches("orders.created.us"));
+
+        assert!(Pattern::parse("orders")?.matches("orders"));
+        assert!(!Pattern::parse("orders")?.matches("orders.created"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_names() {
+        assert!(Pattern::parse("orders.").is_err());
+        assert!(Pattern::parse("orders.cr*").is_err());
+        assert!(validate_channel("orders.*").is_err());
+        assert!(validate_channel("").is_err());
+        assert!(validate_channel("orders.created").is_ok());
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_lose_old_messages() -> Result<(), Box<dyn std::error::Error>> {
+        let pubsub = PubSub::new(2);
+        let mut receiver = pubsub.subscribe();
+
+        for n in 0..3 {
+            pubsub.publish("ticks", json!(n))?;
+        }
+
+        assert!(matches!(
+            receiver.recv().await,
+            Err(broadcast::error::RecvError::Lagged(1))
+        ));
+        assert_eq!(receiver.recv().await?.payload, json!(1));
+        assert_eq!(receiver.recv().await?.payload, json!(2));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Dashboard logins. Each one gets a random id, handed to the browser in a
+// cookie, that is good until it expires or is logged out. The cookie says
+// nothing about the admin token itself.
+pub struct Sessions {
+    ttl: Duration,
+    // Session id to when it expires.
+    expiries: Mutex<HashMap<String, Instant>>,
+}
+
+impl Sessions {
+    pub fn new(ttl: Duration) -> Self {
+        Sessions {
+            ttl,
+            expiries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    // Starts a session and returns its id. Expired sessions are dropped here,
+    // so the map only grows with the number of live logins.
+    pub fn start(&self, now: Instant) -> String {
+        let id = hex(&rand::random::<[u8; 32]>());
+        let mut expiries = self.expiries.lock().unwrap_or_else(|p| p.into_inner());
+        expiries.retain(|_, expires| *expires > now);
+        expiries.insert(id.clone(), now + self.ttl);
+        id
+    }
+
+    pub fn is_live(&self, id: &str, now: Instant) -> bool {
+        let expiries = self.expiries.lock().unwrap_or_else(|p| p.into_inner());
+        expiries.get(id).is_some_and(|expires| *expires > now)
+    }
+
+    pub fn end(&self, id: &str) {
+        let mut expiries = self.expiries.lock().unwrap_or_else(|p| p.into_inner());
+        expiries.remove(id);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_expire_and_end() {
+        let sessions = Sessions::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        let first = sessions.start(now);
+        let second = sessions.start(now);
+
+        assert_ne!(first, second);
+        assert_eq!(first.len(), 64);
+        assert!(sessions.is_live(&first, now + Duration::from_secs(59)));
+        assert!(!sessions.is_live(&first, now + Duration::from_secs(60)));
+        assert!(!sessions.is_live("guessed", now));
+
+        sessions.end(&second);
+        assert!(!sessions.is_live(&second, now));
+    }
+}
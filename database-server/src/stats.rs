@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// The request rate is averaged over this many whole seconds.
+const RATE_WINDOW_SECS: u64 = 10;
+
+// Counts requests for the admin dashboard.
+pub struct Stats {
+    started: Instant,
+    // (second since `started`, requests in it) for the recent seconds that
+    // saw any, oldest first.
+    buckets: Mutex<VecDeque<(u64, u64)>>,
+    total: AtomicU64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new(Instant::now())
+    }
+}
+
+impl Stats {
+    pub fn new(started: Instant) -> Self {
+        Stats {
+            started,
+            buckets: Mutex::new(VecDeque::new()),
+            total: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, at: Instant) {
+        let second = at.duration_since(self.started).as_secs();
+        self.total.fetch_add(1, Ordering::Relaxed);
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|p| p.into_inner());
+        match buckets.back_mut() {
+            Some((s, count)) if *s == second => *count += 1,
+            _ => buckets.push_back((second, 1)),
+        }
+        while buckets
+            .front()
+            .is_some_and(|(s, _)| s + RATE_WINDOW_SECS <= second)
+        {
+            buckets.pop_front();
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    // Requests per second over the last complete RATE_WINDOW_SECS seconds,
+    // or since startup when that is shorter.
+    pub fn rate(&self, at: Instant) -> f64 {
+        let now = at.duration_since(self.started).as_secs();
+        let from = now.saturating_sub(RATE_WINDOW_SECS);
+        let window = (now - from).max(1);
+
+        let buckets = self.buckets.lock().unwrap_or_else(|p| p.into_inner());
+        let count: u64 = buckets
+            .iter()
+            .filter(|(s, _)| *s >= from && *s < now.max(1))
+            .map(|(_, count)| count)
+            .sum();
+        count as f64 / window as f64
+    }
+
+    pub fn uptime(&self, at: Instant) -> Duration {
+        at.duration_since(self.started)
+    }
+}
+
+// The resident set size of this process, where the OS reports it.
+pub fn memory_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_covers_the_last_complete_seconds() {
+        let start = Instant::now();
+        let stats = Stats::new(start);
+        let at = |ms| start + Duration::from_millis(ms);
+
+        for ms in 0..20 {
+            stats.record(at(ms * 50));
+        }
+        for ms in 0..5 {
+            stats.record(at(1_000 + ms * 100));
+        }
+
+        assert_eq!(stats.total(), 25);
+        // Only the first second has finished, and it saw 20.
+        assert_eq!(stats.rate(at(1_500)), 20.0);
+        // Seconds 0 and 1 over a two second window.
+        assert_eq!(stats.rate(at(2_000)), 12.5);
+        // Both have left the ten second window.
+        assert_eq!(stats.rate(at(12_000)), 0.0);
+    }
+}
@@ -0,0 +1,1261 @@
+use crate::disk::{self, DiskError, LOG_FILE, Log, SNAPSHOT_FILE};
+use crate::durability::Syncer;
+use crate::json_path::{JsonPath, JsonPathError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+// Secondary index over one field of the JSON documents in the store. Scalars
+// are indexed by their text, so `eq=42` matches both `42` and `"42"`.
+struct Index {
+    path: String,
+    json_path: JsonPath,
+    entries: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Index {
+    fn new(path: &str) -> Result<Self, JsonPathError> {
+        Ok(Index {
+            path: path.to_string(),
+            json_path: JsonPath::parse(path)?,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    fn index_key(&self, document: &Value) -> Option<String> {
+        match self.json_path.select(document)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Null | Value::Array(_) | Value::Object(_) => None,
+            scalar => Some(scalar.to_string()),
+        }
+    }
+
+    fn insert(&mut self, key: &str, document: &Value) {
+        if let Some(index_key) = self.index_key(document) {
+            self.entries
+                .entry(index_key)
+                .or_default()
+                .insert(key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str, document: &Value) {
+        if let Some(index_key) = self.index_key(document)
+            && let Some(keys) = self.entries.get_mut(&index_key)
+        {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.entries.remove(&index_key);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Set,
+    Delete,
+    Expire,
+}
+
+// A single write to a key. Versions are numbered from one store-wide counter,
+// so a version number doubles as a snapshot of the whole store. Deletes and
+// expiries are recorded as versions without a value.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Version {
+    pub version: u64,
+    pub timestamp_ms: u64,
+    pub kind: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+// An entry in the mutation log. The offset is the version the change created,
+// so consumers resume by asking for changes since the last offset they saw.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Change {
+    pub offset: u64,
+    pub timestamp_ms: u64,
+    pub kind: ChangeKind,
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+// A write as it is kept in the write-ahead log.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub version: u64,
+    pub timestamp_ms: u64,
+    pub kind: ChangeKind,
+    pub key: String,
+    pub value: Option<Value>,
+    pub expires_at_ms: Option<u64>,
+}
+
+// A declared index as it is kept in the write-ahead log, or its removal when
+// there is no path.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct IndexRecord {
+    pub index: String,
+    pub path: Option<String>,
+}
+
+// Anything the write-ahead log holds. Each is written out as the record
+// itself, so logs from before indexes were logged still read back.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum LogEntry {
+    Write(Record),
+    Index(IndexRecord),
+}
+
+// Snapshots hold the latest value of every key, split into blocks of up to
+// SNAPSHOT_BLOCK_ENTRIES so damage to one block loses only its entries. Every
+// block repeats the snapshot version and the declared indexes, by name, and
+// only the final one is `last`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotBlock {
+    pub version: u64,
+    pub entries: Vec<SnapshotEntry>,
+    #[serde(default)]
+    pub indexes: BTreeMap<String, String>,
+    pub last: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub key: String,
+    pub version: u64,
+    pub timestamp_ms: u64,
+    pub value: Value,
+    pub expires_at_ms: Option<u64>,
+}
+
+const SNAPSHOT_BLOCK_ENTRIES: usize = 1_000;
+
+// The latest values as of `version`, see `Store::snapshot`.
+pub struct Snapshot {
+    path: PathBuf,
+    version: u64,
+    entries: Vec<SnapshotEntry>,
+    indexes: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    pub fn write(&self) -> Result<(), StoreError> {
+        let mut blocks = vec![];
+        let mut chunks = self.entries.chunks(SNAPSHOT_BLOCK_ENTRIES).peekable();
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            let last = chunks.peek().is_none();
+            let block = SnapshotBlock {
+                version: self.version,
+                entries: chunk.to_vec(),
+                indexes: self.indexes.clone(),
+                last,
+            };
+            blocks.push(serde_json::to_vec(&block).map_err(|e| StoreError::Disk(e.to_string()))?);
+            if last {
+                break;
+            }
+        }
+
+        disk::replace(&self.path, blocks.iter().map(Vec::as_slice))?;
+        Ok(())
+    }
+}
+
+// What `Store::open` found on disk.
+#[derive(Debug, PartialEq)]
+pub struct Recovery {
+    pub snapshot_version: u64,
+    pub replayed: usize,
+    // Bytes dropped from the end of the log, left by a write cut short.
+    pub truncated: Option<Range<u64>>,
+}
+
+#[derive(Default)]
+struct History {
+    versions: VecDeque<Version>,
+    // Set once older versions have been dropped by retention, after which reads
+    // before the first retained version can no longer be answered.
+    truncated: bool,
+    expires_at_ms: Option<u64>,
+}
+
+impl History {
+    fn latest(&self) -> Option<&Version> {
+        self.versions.back()
+    }
+
+    fn latest_value(&self) -> Option<&Value> {
+        self.latest().and_then(|v| v.value.as_ref())
+    }
+
+    // Past its TTL but not yet removed by `Store::expire_due`.
+    fn expired(&self, now_ms: u64) -> bool {
+        self.expires_at_ms.is_some_and(|at| at <= now_ms)
+    }
+
+    fn live_value(&self, now_ms: u64) -> Option<&Value> {
+        match self.expired(now_ms) {
+            true => None,
+            false => self.latest_value(),
+        }
+    }
+
+    // The value as of a version. The TTL only applies to the latest write.
+    fn value_at(&self, as_of: u64, now_ms: u64) -> Result<Option<&Value>, StoreError> {
+        let Some(version) = self.at(as_of)? else {
+            return Ok(None);
+        };
+        let is_latest = self.latest().is_some_and(|l| l.version == version.version);
+        match is_latest && self.expired(now_ms) {
+            true => Ok(None),
+            false => Ok(version.value.as_ref()),
+        }
+    }
+
+    fn at(&self, as_of: u64) -> Result<Option<&Version>, StoreError> {
+        match self.versions.iter().rev().find(|v| v.version <= as_of) {
+            Some(version) => Ok(Some(version)),
+            None if self.truncated => Err(StoreError::Compacted(as_of)),
+            None => Ok(None),
+        }
+    }
+
+    fn prune(&mut self, retention: &Retention, now_ms: u64) {
+        while self.versions.len() > 1 {
+            let too_many = retention
+                .max_versions
+                .is_some_and(|max| self.versions.len() > max.max(1));
+            let too_old = retention.max_age.is_some_and(|max_age| {
+                let age = now_ms.saturating_sub(self.versions[1].timestamp_ms);
+                u128::from(age) > max_age.as_millis()
+            });
+
+            if !too_many && !too_old {
+                break;
+            }
+            self.versions.pop_front();
+            self.truncated = true;
+        }
+    }
+}
+
+// How many old versions of each key are kept. The latest version is always
+// kept. An old version is dropped once it has been superseded for longer
+// than `max_age`. `max_changes` bounds the mutation log.
+#[derive(Clone, Debug, Default)]
+pub struct Retention {
+    pub max_versions: Option<usize>,
+    pub max_age: Option<Duration>,
+    pub max_changes: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StoreError {
+    Compacted(u64),
+    FutureVersion(u64, u64),
+    ChangesTruncated(u64, u64),
+    InvalidPath(JsonPathError),
+    Disk(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Compacted(as_of) => {
+                write!(f, "version {} is older than the retained history", as_of)
+            }
+            StoreError::FutureVersion(as_of, current) => write!(
+                f,
+                "version {} has not been written yet, current version is {}",
+                as_of, current
+            ),
+            StoreError::ChangesTruncated(since, oldest) => write!(
+                f,
+                "changes since {} are no longer retained, the oldest offset is {}",
+                since, oldest
+            ),
+            StoreError::InvalidPath(e) => write!(f, "{}", e),
+            StoreError::Disk(e) => write!(f, "could not write to disk: {}", e),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<DiskError> for StoreError {
+    fn from(err: DiskError) -> Self {
+        StoreError::Disk(err.to_string())
+    }
+}
+
+#[derive(Default)]
+pub struct Store {
+    entries: BTreeMap<String, History>,
+    indexes: BTreeMap<String, Index>,
+    version: u64,
+    retention: Retention,
+    changes: VecDeque<Change>,
+    expirations: BTreeSet<(u64, String)>,
+    latest: watch::Sender<u64>,
+    // The version of the snapshot the store was restored from. Keys deleted
+    // before it are not in the snapshot at all, so no read before it can be
+    // answered.
+    history_floor: u64,
+    // Set when the store is kept on disk, see `open`.
+    log: Option<Log>,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+impl Store {
+    pub fn with_retention(retention: Retention) -> Self {
+        Store {
+            retention,
+            ..Self::default()
+        }
+    }
+
+    // Loads the store kept in `dir`, creating the directory if needed. Writes
+    // are appended to its log from then on.
+    pub fn open(dir: &Path, retention: Retention) -> Result<(Store, Recovery), DiskError> {
+        fs::create_dir_all(dir).map_err(|e| DiskError::Io(dir.to_path_buf(), e))?;
+        let mut store = Store::with_retention(retention);
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        if let Some(bytes) = disk::read(&snapshot_path)? {
+            store.restore(&snapshot_path, &bytes)?;
+        }
+        let snapshot_version = store.version;
+
+        let log_path = dir.join(LOG_FILE);
+        let (log, contents) = Log::open(&log_path)?;
+        let mut replayed = 0;
+        for (range, payload) in contents.records {
+            let corrupt = || DiskError::Corrupt(log_path.clone(), vec![range.clone()]);
+            match serde_json::from_slice(&payload).map_err(|_| corrupt())? {
+                LogEntry::Write(record) if record.version > store.version => {
+                    store.apply(record);
+                    replayed += 1;
+                }
+                LogEntry::Write(_) => {}
+                // Index changes carry no version, so all of them are replayed,
+                // in order, over the indexes the snapshot declared.
+                LogEntry::Index(record) => store.apply_index(record).map_err(|_| corrupt())?,
+            }
+        }
+
+        // What was read back is taken to be on disk already.
+        let syncer = log.syncer();
+        syncer.written(store.version);
+        syncer.synced_through(store.version);
+
+        store.log = Some(log);
+        let recovery = Recovery {
+            snapshot_version,
+            replayed,
+            truncated: contents.truncated,
+        };
+        Ok((store, recovery))
+    }
+
+    fn restore(&mut self, path: &Path, bytes: &[u8]) -> Result<(), DiskError> {
+        let scan = disk::scan(bytes);
+        if !scan.damaged.is_empty() {
+            return Err(DiskError::Corrupt(path.to_path_buf(), scan.damaged));
+        }
+
+        let mut complete = false;
+        for (range, payload) in scan.frames {
+            let corrupt = || DiskError::Corrupt(path.to_path_buf(), vec![range.clone()]);
+            let block: SnapshotBlock = serde_json::from_slice(payload).map_err(|_| corrupt())?;
+            self.version = block.version;
+            complete = block.last;
+            for (name, index_path) in block.indexes {
+                let index = Index::new(&index_path).map_err(|_| corrupt())?;
+                self.indexes.insert(name, index);
+            }
+            for entry in block.entries {
+                let history = self.entries.entry(entry.key.clone()).or_default();
+                history.versions.push_back(Version {
+                    version: entry.version,
+                    timestamp_ms: entry.timestamp_ms,
+                    kind: ChangeKind::Set,
+                    value: Some(entry.value),
+                });
+                // Anything older than the snapshot is gone.
+                history.truncated = true;
+                if let Some(expires_at_ms) = entry.expires_at_ms {
+                    history.expires_at_ms = Some(expires_at_ms);
+                    self.expirations.insert((expires_at_ms, entry.key));
+                }
+            }
+        }
+
+        // A snapshot is renamed into place only once fully written, so a
+        // missing final block means the file was damaged afterwards.
+        if !complete {
+            return Err(DiskError::Incomplete(path.to_path_buf()));
+        }
+        self.history_floor = self.version;
+        self.rebuild_indexes();
+        self.latest.send_replace(self.version);
+        Ok(())
+    }
+
+    // Writes a snapshot of the latest values and empties the log, so that
+    // startup does not have to replay every write ever made. The server does
+    // this in two steps instead, see `snapshot`.
+    #[cfg(test)]
+    pub fn checkpoint(&mut self) -> Result<(), StoreError> {
+        let Some(snapshot) = self.snapshot() else {
+            return Ok(());
+        };
+        snapshot.write()?;
+        self.truncate_log(&snapshot)?;
+        Ok(())
+    }
+
+    // Copies the latest values for `Snapshot::write`, so that the store does
+    // not have to stay locked while they go to disk. Returns `None` when the
+    // store is not kept on disk.
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        let log = self.log.as_ref()?;
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(key, history)| {
+                let latest = history.latest()?;
+                Some(SnapshotEntry {
+                    key: key.clone(),
+                    version: latest.version,
+                    timestamp_ms: latest.timestamp_ms,
+                    value: latest.value.clone()?,
+                    expires_at_ms: history.expires_at_ms,
+                })
+            })
+            .collect();
+
+        Some(Snapshot {
+            path: log.path().with_file_name(SNAPSHOT_FILE),
+            version: self.version,
+            entries,
+            indexes: self.index_paths(),
+        })
+    }
+
+    // Empties the log once `snapshot` has been written. Writes or index
+    // changes made since are only in the log, so it is left alone then; replay
+    // skips the writes the snapshot covers. Returns whether the log was emptied.
+    pub fn truncate_log(&mut self, snapshot: &Snapshot) -> Result<bool, StoreError> {
+        if snapshot.version != self.version || snapshot.indexes != self.index_paths() {
+            return Ok(false);
+        }
+        let Some(log) = &mut self.log else {
+            return Ok(false);
+        };
+        log.reset(snapshot.version)?;
+        Ok(true)
+    }
+
+    pub fn syncer(&self) -> Option<Arc<Syncer>> {
+        self.log.as_ref().map(Log::syncer)
+    }
+
+    // Bytes written to the log since the last checkpoint, when kept on disk.
+    pub fn log_len(&self) -> Option<u64> {
+        self.log.as_ref().map(Log::len)
+    }
+
+    // The version of the most recent write, i.e. the current snapshot.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // Keys that currently hold a value.
+    pub fn key_count(&self) -> usize {
+        self.entries
+            .values()
+            .filter(|h| h.latest_value().is_some())
+            .count()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        let now_ms = now_ms();
+        self.entries.get(key).and_then(|h| h.live_value(now_ms))
+    }
+
+    pub fn get_at(&self, key: &str, as_of: u64) -> Result<Option<&Value>, StoreError> {
+        self.check_snapshot(as_of)?;
+
+        match self.entries.get(key) {
+            Some(history) => history.value_at(as_of, now_ms()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn history(&self, key: &str) -> Option<&VecDeque<Version>> {
+        self.entries.get(key).map(|h| &h.versions)
+    }
+
+    // Keys starting with `prefix` and sorting after `after`, as they were at
+    // version `as_of`. Passing the same `as_of` to every page of a scan gives
+    // one consistent snapshot however many writes happen in between.
+    pub fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+        as_of: u64,
+    ) -> Result<Vec<(&str, &Value)>, StoreError> {
+        self.check_snapshot(as_of)?;
+
+        // A cursor before the prefix would stop at the first key outside it.
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after),
+            _ => Bound::Included(prefix),
+        };
+
+        let now_ms = now_ms();
+        let mut entries = vec![];
+        for (key, history) in self.entries.range::<str, _>((start, Bound::Unbounded)) {
+            if entries.len() == limit || !key.starts_with(prefix) {
+                break;
+            }
+            if let Some(value) = history.value_at(as_of, now_ms)? {
+                entries.push((key.as_str(), value));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn check_snapshot(&self, as_of: u64) -> Result<(), StoreError> {
+        if as_of < self.history_floor {
+            return Err(StoreError::Compacted(as_of));
+        }
+        self.check_written(as_of)
+    }
+
+    fn check_written(&self, version: u64) -> Result<(), StoreError> {
+        match version > self.version {
+            true => Err(StoreError::FutureVersion(version, self.version)),
+            false => Ok(()),
+        }
+    }
+
+    pub fn set(&mut self, key: String, value: Value) -> Result<u64, StoreError> {
+        self.write(key, ChangeKind::Set, Some(value), None)
+    }
+
+    // Like `set`, but the key is removed with an expire change once `ttl` has
+    // passed, see `expire_due`.
+    pub fn set_with_ttl(
+        &mut self,
+        key: String,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<u64, StoreError> {
+        let expires_at_ms = now_ms().saturating_add(ttl.as_millis() as u64);
+        self.write(key, ChangeKind::Set, Some(value), Some(expires_at_ms))
+    }
+
+    // Returns `None` when there was no value to delete.
+    pub fn delete(&mut self, key: &str) -> Result<Option<u64>, StoreError> {
+        if self.get(key).is_none() {
+            return Ok(None);
+        }
+        self.write(key.to_string(), ChangeKind::Delete, None, None)
+            .map(Some)
+    }
+
+    // Sets `key` to `value` only if it currently holds `expected`, where `None`
+    // means the key has no value. Returns `None` when it holds something else.
+    pub fn compare_and_set(
+        &mut self,
+        key: String,
+        expected: Option<&Value>,
+        value: Value,
+    ) -> Result<Option<u64>, StoreError> {
+        if self.get(&key) != expected {
+            return Ok(None);
+        }
+        self.set(key, value).map(Some)
+    }
+
+    pub fn expire_due(&mut self) -> Result<(), StoreError> {
+        let now_ms = now_ms();
+        while let Some((expires_at_ms, _)) = self.expirations.first()
+            && *expires_at_ms <= now_ms
+        {
+            if let Some((expires_at_ms, key)) = self.expirations.pop_first()
+                && let Err(e) = self.write(key.clone(), ChangeKind::Expire, None, None)
+            {
+                self.expirations.insert((expires_at_ms, key));
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // Every write goes through here. It is logged first, when the store is on
+    // disk, so a write that could not be logged is not applied either.
+    // Returns the version of the write.
+    fn write(
+        &mut self,
+        key: String,
+        kind: ChangeKind,
+        value: Option<Value>,
+        expires_at_ms: Option<u64>,
+    ) -> Result<u64, StoreError> {
+        let record = Record {
+            version: self.version + 1,
+            timestamp_ms: now_ms(),
+            kind,
+            key,
+            value,
+            expires_at_ms,
+        };
+        self.log(&record, record.version)?;
+        self.apply(record);
+        Ok(self.version)
+    }
+
+    // Appends a `LogEntry` to the log, when the store is kept on disk.
+    fn log(&mut self, entry: &impl Serialize, version: u64) -> Result<(), StoreError> {
+        if let Some(log) = &mut self.log {
+            let payload = serde_json::to_vec(entry).map_err(|e| StoreError::Disk(e.to_string()))?;
+            log.append(&payload, version)?;
+        }
+        Ok(())
+    }
+
+    // Applies a write to memory, keeping the indexes and the mutation log in
+    // step with the entries. Also used to replay the log on startup.
+    fn apply(&mut self, record: Record) {
+        let Record {
+            version,
+            timestamp_ms: now_ms,
+            kind,
+            key,
+            value,
+            expires_at_ms,
+        } = record;
+
+        let previous = self.entries.get(&key).and_then(History::latest_value);
+        for index in self.indexes.values_mut() {
+            if let Some(previous) = previous {
+                index.remove(&key, previous);
+            }
+            if let Some(value) = &value {
+                index.insert(&key, value);
+            }
+        }
+
+        self.version = version;
+        let history = self.entries.entry(key.clone()).or_default();
+
+        if let Some(old) = history.expires_at_ms.take() {
+            self.expirations.remove(&(old, key.clone()));
+        }
+        if let Some(expires_at_ms) = expires_at_ms {
+            history.expires_at_ms = Some(expires_at_ms);
+            self.expirations.insert((expires_at_ms, key.clone()));
+        }
+
+        history.versions.push_back(Version {
+            version: self.version,
+            timestamp_ms: now_ms,
+            kind,
+            value: value.clone(),
+        });
+        history.prune(&self.retention, now_ms);
+
+        self.changes.push_back(Change {
+            offset: self.version,
+            timestamp_ms: now_ms,
+            kind,
+            key,
+            value,
+        });
+        let max_changes = self.retention.max_changes.unwrap_or(usize::MAX).max(1);
+        while self.changes.len() > max_changes {
+            self.changes.pop_front();
+        }
+
+        self.latest.send_replace(self.version);
+    }
+
+    // Up to `limit` changes with an offset after `since`, oldest first.
+    pub fn changes_since(&self, since: u64, limit: usize) -> Result<Vec<Change>, StoreError> {
+        self.check_written(since)?;
+
+        // The log can be empty with writes behind it, e.g. after a restart.
+        let oldest = self.changes.front().map_or(self.version + 1, |c| c.offset);
+        if since < self.version && oldest > since + 1 {
+            return Err(StoreError::ChangesTruncated(since, oldest));
+        }
+
+        let skip = self.changes.partition_point(|c| c.offset <= since);
+        Ok(self
+            .changes
+            .iter()
+            .skip(skip)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    // Observes the version of the latest write, for waiting on new changes.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.latest.subscribe()
+    }
+
+    // Applies age based retention to keys that have not been written recently.
+    pub fn compact(&mut self) {
+        let now_ms = now_ms();
+        for history in self.entries.values_mut() {
+            history.prune(&self.retention, now_ms);
+        }
+    }
+
+    // Declares an index, logged so that it is still there after a restart.
+    // Declaring one again with the same path changes nothing.
+    pub fn create_index(&mut self, name: &str, path: &str) -> Result<(), StoreError> {
+        JsonPath::parse(path).map_err(StoreError::InvalidPath)?;
+        if self
+            .indexes
+            .get(name)
+            .is_some_and(|index| index.path == path)
+        {
+            return Ok(());
+        }
+        let record = IndexRecord {
+            index: name.to_string(),
+            path: Some(path.to_string()),
+        };
+        self.log(&record, self.version)?;
+        self.apply_index(record).map_err(StoreError::InvalidPath)
+    }
+
+    // Returns false when no index with this name has been declared.
+    pub fn drop_index(&mut self, name: &str) -> Result<bool, StoreError> {
+        if !self.indexes.contains_key(name) {
+            return Ok(false);
+        }
+        let record = IndexRecord {
+            index: name.to_string(),
+            path: None,
+        };
+        self.log(&record, self.version)?;
+        self.apply_index(record).map_err(StoreError::InvalidPath)?;
+        Ok(true)
+    }
+
+    // Applies an index change to memory. Also used to replay the log.
+    fn apply_index(&mut self, record: IndexRecord) -> Result<(), JsonPathError> {
+        match record.path {
+            Some(path) => {
+                self.indexes
+                    .insert(record.index.clone(), Index::new(&path)?);
+                self.rebuild_index(&record.index);
+            }
+            None => {
+                self.indexes.remove(&record.index);
+            }
+        }
+        Ok(())
+    }
+
+    fn index_paths(&self) -> BTreeMap<String, String> {
+        self.indexes()
+            .map(|(name, path)| (name.to_string(), path.to_string()))
+            .collect()
+    }
+
+    pub fn indexes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.indexes
+            .iter()
+            .map(|(name, index)| (name.as_str(), index.path.as_str()))
+    }
+
+    pub fn rebuild_indexes(&mut self) {
+        let names: Vec<String> = self.indexes.keys().cloned().collect();
+        for name in names {
+            self.rebuild_index(&name);
+        }
+    }
+
+    fn rebuild_index(&mut self, name: &str) {
+        let Some(index) = self.indexes.get_mut(name) else {
+            return;
+        };
+
+        index.entries.clear();
+        for (key, history) in &self.entries {
+            if let Some(value) = history.latest_value() {
+                index.insert(key, value);
+            }
+        }
+    }
+
+    // Returns `None` when no index with this name has been declared.
+    pub fn query(&self, name: &str, eq: &str) -> Option<Vec<String>> {
+        let index = self.indexes.get(name)?;
+        let keys = index
+            .entries
+            .get(eq)
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default();
+        Some(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn index_is_built_from_existing_entries() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        store.set("a".to_string(), json!({"email": "x@example.com"}))?;
+        store.set("b".to_string(), json!({"email": "y@example.com"}))?;
+        store.set("c".to_string(), json!({"email": "x@example.com"}))?;
+
+        store.create_index("email", "$.email")?;
+
+        assert_eq!(
+            store.query("email", "x@example.com"),
+            Some(vec!["a".to_string(), "c".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_follows_writes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        store.create_index("email", "$.email")?;
+
+        store.set("a".to_string(), json!({"email": "x@example.com"}))?;
+        store.set("a".to_string(), json!({"email": "y@example.com"}))?;
+        store.set("b".to_string(), json!("not a document"))?;
+
+        assert_eq!(store.query("email", "x@example.com"), Some(vec![]));
+        assert_eq!(
+            store.query("email", "y@example.com"),
+            Some(vec!["a".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn scalars_are_indexed_by_text() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        store.create_index("age", "$.age")?;
+
+        store.set("a".to_string(), json!({"age": 42}))?;
+        store.set("b".to_string(), json!({"age": "42"}))?;
+        store.set("c".to_string(), json!({"age": [42]}))?;
+
+        assert_eq!(
+            store.query("age", "42"),
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_unknown_index() {
+        let store = Store::default();
+
+        assert_eq!(store.query("email", "x"), None);
+    }
+
+    #[test]
+    fn compare_and_set_needs_the_expected_value() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+
+        assert!(
+            store
+                .compare_and_set("a".to_string(), Some(&json!(1)), json!(2))?
+                .is_none()
+        );
+        assert!(
+            store
+                .compare_and_set("a".to_string(), None, json!(1))?
+                .is_some()
+        );
+        assert!(
+            store
+                .compare_and_set("a".to_string(), None, json!(2))?
+                .is_none()
+        );
+        assert!(
+            store
+                .compare_and_set("a".to_string(), Some(&json!(1)), json!(2))?
+                .is_some()
+        );
+        assert_eq!(store.get("a"), Some(&json!(2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_at_reads_older_versions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        let first = store.set("a".to_string(), json!(1))?;
+        store.set("b".to_string(), json!(2))?;
+        let third = store.set("a".to_string(), json!(3))?;
+
+        assert_eq!(store.get_at("a", first)?, Some(&json!(1)));
+        assert_eq!(store.get_at("a", third - 1)?, Some(&json!(1)));
+        assert_eq!(store.get_at("a", third)?, Some(&json!(3)));
+        assert_eq!(store.get_at("b", first)?, None);
+        assert_eq!(
+            store.get_at("a", third + 1),
+            Err(StoreError::FutureVersion(third + 1, third))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn scan_sees_one_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        store.set("user:1".to_string(), json!("ann"))?;
+        store.set("user:2".to_string(), json!("bob"))?;
+        store.set("zzz".to_string(), json!("other"))?;
+        let snapshot = store.version();
+
+        let page = store.scan("user:", None, 1, snapshot)?;
+        assert_eq!(page, vec![("user:1", &json!("ann"))]);
+
+        store.set("user:2".to_string(), json!("bill"))?;
+        store.set("user:3".to_string(), json!("cat"))?;
+
+        let page = store.scan("user:", Some("user:1"), 10, snapshot)?;
+        assert_eq!(page, vec![("user:2", &json!("bob"))]);
+
+        let latest = store.scan("user:", None, 10, store.version())?;
+        assert_eq!(latest.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn scan_after_a_key_before_the_prefix() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        store.set("a".to_string(), json!(0))?;
+        store.set("user/1".to_string(), json!(1))?;
+        store.set("user/2".to_string(), json!(2))?;
+
+        let page = store.scan("user/", Some("a"), 10, store.version())?;
+        assert_eq!(page, vec![("user/1", &json!(1)), ("user/2", &json!(2))]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn retention_limits_versions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::with_retention(Retention {
+            max_versions: Some(2),
+            ..Retention::default()
+        });
+        let first = store.set("a".to_string(), json!(1))?;
+        store.set("a".to_string(), json!(2))?;
+        let third = store.set("a".to_string(), json!(3))?;
+
+        let versions: Vec<u64> = store
+            .history("a")
+            .unwrap()
+            .iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(versions, vec![first + 1, third]);
+        assert_eq!(store.get_at("a", first), Err(StoreError::Compacted(first)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn retention_by_age_keeps_latest() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::with_retention(Retention {
+            max_age: Some(Duration::ZERO),
+            ..Retention::default()
+        });
+        store.set("a".to_string(), json!(1))?;
+        let latest = store.set("a".to_string(), json!(2))?;
+        std::thread::sleep(Duration::from_millis(2));
+        store.compact();
+
+        let versions: Vec<u64> = store
+            .history("a")
+            .unwrap()
+            .iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(versions, vec![latest]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_hides_value_and_keeps_history() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        store.create_index("email", "$.email")?;
+        let set = store.set("a".to_string(), json!({"email": "x"}))?;
+
+        let deleted = store.delete("a")?;
+
+        assert_eq!(store.get("a"), None);
+        assert_eq!(store.get_at("a", set)?, Some(&json!({"email": "x"})));
+        assert_eq!(store.query("email", "x"), Some(vec![]));
+        assert_eq!(store.delete("a")?, None);
+        let kinds: Vec<ChangeKind> = store.history("a").unwrap().iter().map(|v| v.kind).collect();
+        assert_eq!(kinds, vec![ChangeKind::Set, ChangeKind::Delete]);
+        assert_eq!(deleted, Some(set + 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ttl_expires_keys() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        store.set_with_ttl("a".to_string(), json!(1), Duration::ZERO)?;
+        store.set_with_ttl("b".to_string(), json!(2), Duration::from_secs(60))?;
+        store.set_with_ttl("c".to_string(), json!(3), Duration::ZERO)?;
+        store.set("c".to_string(), json!(4))?;
+
+        assert_eq!(store.get("a"), None);
+        assert_eq!(store.get_at("a", store.version())?, None);
+        assert_eq!(store.get("c"), Some(&json!(4)));
+
+        store.expire_due()?;
+
+        assert_eq!(store.get("a"), None);
+        assert_eq!(store.get("b"), Some(&json!(2)));
+        assert_eq!(store.get("c"), Some(&json!(4)));
+        let last = store.changes_since(0, 10).unwrap().pop().unwrap();
+        assert_eq!((last.kind, last.key.as_str()), (ChangeKind::Expire, "a"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn changes_resume_from_offset() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::default();
+        store.set("a".to_string(), json!(1))?;
+        store.set("b".to_string(), json!(2))?;
+        store.delete("a")?;
+
+        let first = store.changes_since(0, 2)?;
+        let offsets: Vec<u64> = first.iter().map(|c| c.offset).collect();
+        assert_eq!(offsets, vec![1, 2]);
+
+        let rest = store.changes_since(2, 10)?;
+        assert_eq!(rest.len(), 1);
+        assert_eq!(
+            (rest[0].kind, rest[0].key.as_str()),
+            (ChangeKind::Delete, "a")
+        );
+        assert_eq!(store.changes_since(3, 10)?, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn changes_report_truncation() -> Result<(), Box<dyn std::error::Error>> {
+        let mut store = Store::with_retention(Retention {
+            max_changes: Some(2),
+            ..Retention::default()
+        });
+        for i in 0..4 {
+            store.set("a".to_string(), json!(i))?;
+        }
+
+        assert_eq!(
+            store.changes_since(1, 10),
+            Err(StoreError::ChangesTruncated(1, 3))
+        );
+        assert_eq!(store.changes_since(2, 10).map(|c| c.len()), Ok(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reopening_restores_snapshot_and_log() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        {
+            let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+            store.set("a".to_string(), json!(1))?;
+            store.set_with_ttl("b".to_string(), json!(2), Duration::from_secs(60))?;
+            store.checkpoint()?;
+            store.set("a".to_string(), json!(3))?;
+            store.delete("b")?;
+        }
+
+        let (store, recovery) = Store::open(dir.path(), Retention::default())?;
+
+        assert_eq!(
+            recovery,
+            Recovery {
+                snapshot_version: 2,
+                replayed: 2,
+                truncated: None,
+            }
+        );
+        assert_eq!(store.version(), 4);
+        assert_eq!(store.get("a"), Some(&json!(3)));
+        assert_eq!(store.get("b"), None);
+        assert_eq!(store.get_at("a", 2)?, Some(&json!(1)));
+        assert_eq!(store.get_at("a", 0), Err(StoreError::Compacted(0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_before_the_snapshot_are_compacted() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        {
+            let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+            store.set("a".to_string(), json!(1))?;
+            store.set("b".to_string(), json!(2))?;
+            store.delete("a")?;
+            store.checkpoint()?;
+        }
+
+        let (store, _) = Store::open(dir.path(), Retention::default())?;
+
+        // The deleted key left nothing in the snapshot to say it existed.
+        assert_eq!(store.get_at("a", 1), Err(StoreError::Compacted(1)));
+        assert_eq!(store.scan("", None, 10, 2), Err(StoreError::Compacted(2)));
+        assert_eq!(store.get_at("a", 3)?, None);
+        assert_eq!(store.scan("", None, 10, 3)?, vec![("b", &json!(2))]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn log_is_kept_when_written_during_a_snapshot() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        {
+            let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+            store.set("a".to_string(), json!(1))?;
+            let snapshot = store.snapshot().ok_or("store is on disk")?;
+            store.set("b".to_string(), json!(2))?;
+            snapshot.write()?;
+
+            assert!(!store.truncate_log(&snapshot)?);
+            assert!(store.log_len().is_some_and(|len| len > 0));
+        }
+
+        let (store, recovery) = Store::open(dir.path(), Retention::default())?;
+
+        assert_eq!((recovery.snapshot_version, recovery.replayed), (1, 1));
+        assert_eq!(store.get("a"), Some(&json!(1)));
+        assert_eq!(store.get("b"), Some(&json!(2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn indexes_survive_a_restart() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        {
+            let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+            store.create_index("email", "$.email")?;
+            store.create_index("age", "$.age")?;
+            store.set("a".to_string(), json!({"email": "x", "name": "ann"}))?;
+            store.checkpoint()?;
+            store.drop_index("age")?;
+            store.create_index("name", "$.name")?;
+            store.set("b".to_string(), json!({"email": "x", "name": "bob"}))?;
+        }
+
+        let (store, _) = Store::open(dir.path(), Retention::default())?;
+
+        let indexes: Vec<(&str, &str)> = store.indexes().collect();
+        assert_eq!(indexes, vec![("email", "$.email"), ("name", "$.name")]);
+        assert_eq!(
+            store.query("email", "x"),
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(store.query("name", "ann"), Some(vec!["a".to_string()]));
+        assert!(
+            crate::fsck::fsck(dir.path(), false)?
+                .iter()
+                .all(crate::fsck::FileReport::is_clean)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn log_is_kept_when_an_index_changes_during_a_snapshot()
+    -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        {
+            let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+            store.set("a".to_string(), json!({"email": "x"}))?;
+            let snapshot = store.snapshot().ok_or("store is on disk")?;
+            store.create_index("email", "$.email")?;
+            snapshot.write()?;
+
+            assert!(!store.truncate_log(&snapshot)?);
+        }
+
+        let (store, _) = Store::open(dir.path(), Retention::default())?;
+
+        assert_eq!(store.query("email", "x"), Some(vec!["a".to_string()]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn changes_before_a_restart_are_truncated() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        {
+            let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+            store.set("a".to_string(), json!(1))?;
+            store.set("a".to_string(), json!(2))?;
+            store.checkpoint()?;
+        }
+
+        let (mut store, _) = Store::open(dir.path(), Retention::default())?;
+
+        assert_eq!(
+            store.changes_since(1, 10),
+            Err(StoreError::ChangesTruncated(1, 3))
+        );
+        assert_eq!(store.changes_since(2, 10)?, vec![]);
+        store.set("a".to_string(), json!(3))?;
+        assert_eq!(store.changes_since(2, 10).map(|c| c.len()), Ok(1));
+        assert_eq!(
+            store.changes_since(0, 10),
+            Err(StoreError::ChangesTruncated(0, 3))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+// Runs many clients against a live server at once, records what each of them
+// saw, and checks that the whole history is linearizable: that every
+// operation could have taken effect at a single instant between its request
+// being sent and its response arriving, one at a time, on one copy of each key.
+//
+// Set LINEARIZABILITY_SEED to replay the same choice of operations and delays.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const CLIENTS: u64 = 8;
+const OPS_PER_CLIENT: u64 = 100;
+const KEYS: [&str; 3] = ["a", "b", "c"];
+const MAX_DELAY_US: u64 = 2_000;
+
+// The server binary, started on a free port and killed when dropped.
+struct Server {
+    child: Child,
+    url: String,
+}
+
+impl Server {
+    fn start(envs: &[(&str, &str)]) -> Result<Server, Box<dyn Error>> {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_database-server"))
+            .env_remove("DATABASE_DATA_DIR")
+            .env_remove("DATABASE_DURABILITY")
+            .env("DATABASE_ADDR", "127.0.0.1:0")
+            .envs(envs.iter().copied())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().ok_or("no stdout")?;
+        let mut server = Server {
+            child,
+            url: String::new(),
+        };
+
+        let mut line = String::new();
+        BufReader::new(stdout).read_line(&mut line)?;
+        let addr = line
+            .trim()
+            .strip_prefix("listening on ")
+            .ok_or_else(|| format!("server did not start, it printed {:?}", line))?;
+        server.url = format!("http://{}", addr);
+        Ok(server)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Get,
+    Set(u64),
+    // Swap in the second value if the key holds the first; `None` is no value.
+    Cas(Option<u64>, u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Read(Option<u64>),
+    Written,
+    Swapped(bool),
+    // The request failed in transit, so it may or may not have taken effect.
+    Unknown,
+}
+
+#[derive(Clone, Debug)]
+struct Event {
+    client: u64,
+    key: &'static str,
+    op: Op,
+    outcome: Outcome,
+    // Nanoseconds since the run started.
+    call: u64,
+    ret: u64,
+}
+
+fn perform(http: &reqwest::blocking::Client, url: &str, key: &str, op: Op) -> Outcome {
+    let uri = format!("{}/keys/{}", url, key);
+    let response = match op {
+        Op::Get => http.get(&uri).send(),
+        Op::Set(value) => http.put(&uri).json(&value).send(),
+        Op::Cas(expected, value) => http
+            .post(format!("{}/cas", uri))
+            .json(&json!({"expected": expected, "value": value}))
+            .send(),
+    };
+    let Ok(response) = response else {
+        return Outcome::Unknown;
+    };
+
+    let status = response.status().as_u16();
+    match (op, status) {
+        (Op::Get, 200) => match response.json() {
+            Ok(value) => Outcome::Read(Some(value)),
+            Err(_) => Outcome::Unknown,
+        },
+        (Op::Get, 404) => Outcome::Read(None),
+        (Op::Set(_), 201 | 204) => Outcome::Written,
+        (Op::Cas(_, _), 200) => Outcome::Swapped(true),
+        (Op::Cas(_, _), 409) => Outcome::Swapped(false),
+        _ => panic!(
+            "{:?} on {} answered {}: {}",
+            op,
+            key,
+            status,
+            response.text().unwrap_or_default()
+        ),
+    }
+}
+
+fn run_client(url: &str, client: u64, seed: u64, start: Instant) -> Vec<Event> {
+    let http = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("http client");
+    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(client));
+    let mut seen: HashMap<&str, Option<u64>> = HashMap::new();
+    let mut history = vec![];
+
+    for n in 0..OPS_PER_CLIENT {
+        thread::sleep(Duration::from_micros(rng.random_range(0..MAX_DELAY_US)));
+
+        let key = KEYS[rng.random_range(0..KEYS.len())];
+        // Every write is of a distinct value, so a read shows which one it saw.
+        let value = client * OPS_PER_CLIENT + n + 1;
+        let op = match rng.random_range(0..3) {
+            0 => Op::Get,
+            1 => Op::Set(value),
+            _ => Op::Cas(seen.get(key).copied().flatten(), value),
+        };
+
+        let call = start.elapsed().as_nanos() as u64;
+        let outcome = perform(&http, url, key, op);
+        let ret = match outcome {
+            Outcome::Unknown => u64::MAX,
+            _ => start.elapsed().as_nanos() as u64,
+        };
+
+        match outcome {
+            Outcome::Read(read) => {
+                seen.insert(key, read);
+            }
+            Outcome::Written | Outcome::Swapped(true) => {
+                seen.insert(key, Some(value));
+            }
+            Outcome::Swapped(false) | Outcome::Unknown => {}
+        }
+        history.push(Event {
+            client,
+            key,
+            op,
+            outcome,
+            call,
+            ret,
+        });
+    }
+
+    history
+}
+
+fn run(server: &Server, seed: u64) -> Vec<Event> {
+    let start = Instant::now();
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|client| {
+            let url = server.url.clone();
+            thread::spawn(move || run_client(&url, client, seed, start))
+        })
+        .collect();
+
+    clients
+        .into_iter()
+        .flat_map(|client| client.join().expect("client panicked"))
+        .collect()
+}
+
+// A key modelled as a register: the states it can be in after `op` took
+// effect with `outcome` when it was in `state`. Empty when the outcome is
+// impossible from that state.
+fn step(state: Option<u64>, op: Op, outcome: Outcome) -> Vec<Option<u64>> {
+    match (op, outcome) {
+        (Op::Get, Outcome::Read(read)) if read == state => vec![state],
+        (Op::Get, Outcome::Unknown) => vec![state],
+        (Op::Set(value), Outcome::Written | Outcome::Unknown) => vec![Some(value)],
+        (Op::Cas(expected, value), Outcome::Swapped(true)) if expected == state => {
+            vec![Some(value)]
+        }
+        (Op::Cas(expected, _), Outcome::Swapped(false)) if expected != state => vec![state],
+        // A lost swap that would not have applied changes nothing, and one
+        // that would have can equally be placed after everything else.
+        (Op::Cas(expected, value), Outcome::Unknown) => match expected == state {
+            true => vec![Some(value)],
+            false => vec![state],
+        },
+        _ => vec![],
+    }
+}
+
+// Searches for an order of the events on one key that respects real time,
+// i.e. never puts an operation before one that returned before it was called,
+// and that the register model accepts. Configurations already explored, as
+// the set of events placed so far and the resulting state, are not revisited.
+fn linearizable(events: &[&Event]) -> bool {
+    let words = events.len().div_ceil(64);
+    let mut stack = vec![(vec![0u64; words], None)];
+    let mut visited = HashSet::new();
+
+    while let Some((placed, state)) = stack.pop() {
+        let is_placed = |i: usize| placed[i / 64] & (1 << (i % 64)) != 0;
+        let pending: Vec<usize> = (0..events.len()).filter(|&i| !is_placed(i)).collect();
+        if pending.is_empty() {
+            return true;
+        }
+
+        let first_return = pending
+            .iter()
+            .map(|&i| events[i].ret)
+            .min()
+            .unwrap_or(u64::MAX);
+        for &i in pending.iter().filter(|&&i| events[i].call <= first_return) {
+            for next in step(state, events[i].op, events[i].outcome) {
+                let mut placed = placed.clone();
+                placed[i / 64] |= 1 << (i % 64);
+                if visited.insert((placed.clone(), next)) {
+                    stack.push((placed, next));
+                }
+            }
+        }
+    }
+
+    false
+}
+
+// Keys are independent registers, so each one's history is checked alone.
+fn check(history: &[Event]) -> Result<(), String> {
+    for key in KEYS {
+        let mut events: Vec<&Event> = history.iter().filter(|e| e.key == key).collect();
+        events.sort_by_key(|e| e.call);
+        if linearizable(&events) {
+            continue;
+        }
+
+        let mut report = format!("history of key {} is not linearizable:\n", key);
+        for e in events {
+            let ret = match e.ret {
+                u64::MAX => "?".to_string(),
+                ret => (ret / 1_000).to_string(),
+            };
+            report += &format!(
+                "  client {} {:?} -> {:?} [{}us, {}us]\n",
+                e.client,
+                e.op,
+                e.outcome,
+                e.call / 1_000,
+                ret
+            );
+        }
+        return Err(report);
+    }
+    Ok(())
+}
+
+fn seed() -> u64 {
+    let seed = std::env::var("LINEARIZABILITY_SEED")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+    eprintln!("LINEARIZABILITY_SEED={}", seed);
+    seed
+}
+
+#[test]
+fn concurrent_clients_see_a_linearizable_history() -> Result<(), Box<dyn Error>> {
+    let server = Server::start(&[])?;
+
+    let history = run(&server, seed());
+
+    assert_eq!(history.len() as u64, CLIENTS * OPS_PER_CLIENT);
+    check(&history).map_err(Into::into)
+}
+
+#[test]
+fn history_is_linearizable_with_the_log_on_disk() -> Result<(), Box<dyn Error>> {
+    let dir = tempfile::tempdir()?;
+    let dir = dir.path().to_str().ok_or("data dir is not utf-8")?;
+    let server = Server::start(&[("DATABASE_DATA_DIR", dir)])?;
+
+    let history = run(&server, seed());
+
+    check(&history).map_err(Into::into)
+}
+
+fn event(key: &'static str, op: Op, outcome: Outcome, call: u64, ret: u64) -> Event {
+    Event {
+        client: 0,
+        key,
+        op,
+        outcome,
+        call,
+        ret,
+    }
+}
+
+#[test]
+fn checker_accepts_reads_overlapping_a_write() {
+    let history = [
+        event("a", Op::Set(1), Outcome::Written, 0, 100),
+        event("a", Op::Get, Outcome::Read(None), 10, 20),
+        event("a", Op::Get, Outcome::Read(Some(1)), 30, 40),
+        event("a", Op::Cas(Some(1), 2), Outcome::Swapped(true), 50, 60),
+        event("a", Op::Cas(Some(1), 3), Outcome::Swapped(false), 70, 80),
+    ];
+
+    assert_eq!(check(&history), Ok(()));
+}
+
+#[test]
+fn checker_rejects_a_stale_read() {
+    let history = [
+        event("a", Op::Set(1), Outcome::Written, 0, 10),
+        event("a", Op::Set(2), Outcome::Written, 20, 30),
+        event("a", Op::Get, Outcome::Read(Some(1)), 40, 50),
+    ];
+
+    assert!(check(&history).is_err());
+}
+
+#[test]
+fn checker_rejects_two_swaps_from_the_same_value() {
+    let history = [
+        event("a", Op::Set(1), Outcome::Written, 0, 10),
+        event("a", Op::Cas(Some(1), 2), Outcome::Swapped(true), 20, 50),
+        event("a", Op::Cas(Some(1), 3), Outcome::Swapped(true), 30, 60),
+    ];
+
+    assert!(check(&history).is_err());
+}
@@ -0,0 +1,1073 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{self, EvalError, EvalErrorKind};
+use crate::lexer::{Token, classify_atom};
+use crate::value::{Arity, Builtin, Value};
+
+const fn builtin(
+    name: &'static str,
+    arity: Arity,
+    func: fn(&[Value]) -> Result<Value, EvalError>,
+) -> Builtin {
+    Builtin { name, arity, func }
+}
+
+static BUILTINS: &[Builtin] = &[
+    // Numbers
+    builtin("+", Arity::AtLeast(0), add),
+    builtin("-", Arity::AtLeast(1), subtract),
+    builtin("*", Arity::AtLeast(0), multiply),
+    builtin("/", Arity::AtLeast(1), divide),
+    builtin("quotient", Arity::Exactly(2), quotient),
+    builtin("remainder", Arity::Exactly(2), remainder),
+    builtin("modulo", Arity::Exactly(2), modulo),
+    builtin("abs", Arity::Exactly(1), abs),
+    builtin("min", Arity::AtLeast(1), min),
+    builtin("max", Arity::AtLeast(1), max),
+    builtin("expt", Arity::Exactly(2), expt),
+    builtin("sqrt", Arity::Exactly(1), sqrt),
+    builtin("floor", Arity::Exactly(1), floor),
+    builtin("ceiling", Arity::Exactly(1), ceiling),
+    builtin("round", Arity::Exactly(1), round),
+    builtin("truncate", Arity::Exactly(1), truncate),
+    builtin("exact", Arity::Exactly(1), exact),
+    builtin("inexact", Arity::Exactly(1), inexact),
+    builtin("=", Arity::AtLeast(1), equal),
+    builtin("<", Arity::AtLeast(1), less),
+    builtin(">", Arity::AtLeast(1), greater),
+    builtin("<=", Arity::AtLeast(1), less_or_equal),
+    builtin(">=", Arity::AtLeast(1), greater_or_equal),
+    builtin("number?", Arity::Exactly(1), is_number),
+    builtin("integer?", Arity::Exactly(1), is_integer),
+    builtin("zero?", Arity::Exactly(1), is_zero),
+    builtin("positive?", Arity::Exactly(1), is_positive),
+    builtin("negative?", Arity::Exactly(1), is_negative),
+    builtin("even?", Arity::Exactly(1), is_even),
+    builtin("odd?", Arity::Exactly(1), is_odd),
+    // Lists
+    builtin("car", Arity::Exactly(1), car),
+    builtin("cdr", Arity::Exactly(1), cdr),
+    builtin("cons", Arity::Exactly(2), cons),
+    builtin("list", Arity::AtLeast(0), list),
+    builtin("append", Arity::AtLeast(0), append),
+    builtin("reverse", Arity::Exactly(1), reverse),
+    builtin("length", Arity::Exactly(1), length),
+    builtin("list-ref", Arity::Exactly(2), list_ref),
+    builtin("null?", Arity::Exactly(1), is_null),
+    builtin("pair?", Arity::Exactly(1), is_pair),
+    builtin("list?", Arity::Exactly(1), is_list),
+    // Procedures
+    builtin("map", Arity::AtLeast(2), map),
+    builtin("for-each", Arity::AtLeast(2), for_each),
+    builtin("filter", Arity::Exactly(2), filter),
+    builtin("fold", Arity::Exactly(3), fold),
+    builtin("apply", Arity::AtLeast(2), apply),
+    builtin("procedure?", Arity::Exactly(1), is_procedure),
+    // Strings and symbols
+    builtin("string-length", Arity::Exactly(1), string_length),
+    builtin("string-append", Arity::AtLeast(0), string_append),
+    builtin("substring", Arity::Between(2, 3), substring),
+    builtin("string-upcase", Arity::Exactly(1), string_upcase),
+    builtin("string-downcase", Arity::Exactly(1), string_downcase),
+    builtin("string-split", Arity::Between(1, 2), string_split),
+    builtin("string-join", Arity::Between(1, 2), string_join),
+    builtin("string=?", Arity::AtLeast(1), string_equal),
+    builtin("string<?", Arity::AtLeast(1), string_less),
+    builtin("string>?", Arity::AtLeast(1), string_greater),
+    builtin("string->number", Arity::Exactly(1), string_to_number),
+    builtin("number->string", Arity::Exactly(1), number_to_string),
+    builtin("string->symbol", Arity::Exactly(1), string_to_symbol),
+    builtin("symbol->string", Arity::Exactly(1), symbol_to_string),
+    builtin("string?", Arity::Exactly(1), is_string),
+    builtin("symbol?", Arity::Exactly(1), is_symbol),
+    // Equality and booleans
+    builtin("not", Arity::Exactly(1), not),
+    builtin("eq?", Arity::Exactly(2), is_eq),
+    builtin("equal?", Arity::Exactly(2), is_equal),
+    builtin("boolean?", Arity::Exactly(1), is_boolean),
+    // The program
+    builtin("display", Arity::Exactly(1), display),
+    builtin("newline", Arity::Exactly(0), newline),
+    builtin("command-line", Arity::Exactly(0), command_line),
+    builtin("exit", Arity::Between(0, 1), exit),
+];
+
+thread_local! {
+    static COMMAND_LINE: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+// What `command-line` returns: the script being run and its arguments.
+pub fn set_command_line(args: Vec<String>) {
+    COMMAND_LINE.with(|command_line| *command_line.borrow_mut() = args);
+}
+
+// A top-level scope holding every builtin.
+pub fn global_env() -> Env {
+    let env = Env::new();
+    for builtin in BUILTINS {
+        env.define(builtin.name, Value::Builtin(builtin));
+    }
+    env
+}
+
+fn to_float(value: &Value) -> Result<f64, EvalError> {
+    match value {
+        Value::Integer(i) => Ok(*i as f64),
+        Value::Float(f) => Ok(*f),
+        value => Err(EvalError::type_error("a number", value)),
+    }
+}
+
+fn as_integer(value: &Value) -> Result<i64, EvalError> {
+    match value {
+        Value::Integer(i) => Ok(*i),
+        value => Err(EvalError::type_error("an integer", value)),
+    }
+}
+
+fn as_index(value: &Value) -> Result<usize, EvalError> {
+    match value {
+        Value::Integer(i) if *i >= 0 => Ok(*i as usize),
+        value => Err(EvalError::type_error("a non-negative integer", value)),
+    }
+}
+
+fn as_str(value: &Value) -> Result<&str, EvalError> {
+    match value {
+        Value::Str(s) => Ok(s),
+        value => Err(EvalError::type_error("a string", value)),
+    }
+}
+
+fn as_list(value: &Value) -> Result<Vec<Value>, EvalError> {
+    value
+        .to_vec()
+        .ok_or_else(|| EvalError::type_error("a list", value))
+}
+
+fn as_procedure(value: &Value) -> Result<&Value, EvalError> {
+    match value {
+        Value::Builtin(_) | Value::Procedure(_) => Ok(value),
+        value => Err(EvalError::type_error("a procedure", value)),
+    }
+}
+
+fn string(s: impl Into<Rc<str>>) -> Value {
+    Value::Str(s.into())
+}
+
+// Integers stay integers, failing rather than wrapping on overflow. Anything
+// involving a float is done in floats.
+fn arithmetic(
+    a: &Value,
+    b: &Value,
+    integer: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Value, EvalError> {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => integer(*a, *b)
+            .map(Value::Integer)
+            .ok_or_else(|| EvalError::new(EvalErrorKind::Overflow)),
+        _ => Ok(Value::Float(float(to_float(a)?, to_float(b)?))),
+    }
+}
+
+fn add(args: &[Value]) -> Result<Value, EvalError> {
+    args.iter().try_fold(Value::Integer(0), |sum, x| {
+        arithmetic(&sum, x, i64::checked_add, |a, b| a + b)
+    })
+}
+
+fn multiply(args: &[Value]) -> Result<Value, EvalError> {
+    args.iter().try_fold(Value::Integer(1), |product, x| {
+        arithmetic(&product, x, i64::checked_mul, |a, b| a * b)
+    })
+}
+
+// (- x) negates x.
+fn subtract(args: &[Value]) -> Result<Value, EvalError> {
+    let sub = |a: &Value, b: &Value| arithmetic(a, b, i64::checked_sub, |a, b| a - b);
+    match args {
+        [x] => sub(&Value::Integer(0), x),
+        [first, rest @ ..] => rest
+            .iter()
+            .try_fold(first.clone(), |difference, x| sub(&difference, x)),
+        [] => unreachable!("arity is checked"),
+    }
+}
+
+// Integers that divide exactly give an integer, and otherwise a float.
+fn divide_two(a: &Value, b: &Value) -> Result<Value, EvalError> {
+    let overflow = || EvalError::new(EvalErrorKind::Overflow);
+    match (a, b) {
+        (_, Value::Integer(0)) => Err(EvalError::new(EvalErrorKind::DivideByZero)),
+        (Value::Integer(x), Value::Integer(y)) => match x.checked_rem(*y) {
+            Some(0) => x.checked_div(*y).map(Value::Integer).ok_or_else(overflow),
+            Some(_) => Ok(Value::Float(*x as f64 / *y as f64)),
+            None => Err(overflow()),
+        },
+        _ => Ok(Value::Float(to_float(a)? / to_float(b)?)),
+    }
+}
+
+// (/ x) is the reciprocal of x.
+fn divide(args: &[Value]) -> Result<Value, EvalError> {
+    match args {
+        [x] => divide_two(&Value::Integer(1), x),
+        [first, rest @ ..] => rest
+            .iter()
+            .try_fold(first.clone(), |q, x| divide_two(&q, x)),
+        [] => unreachable!("arity is checked"),
+    }
+}
+
+fn overflow() -> EvalError {
+    EvalError::new(EvalErrorKind::Overflow)
+}
+
+// The integer division of the two arguments, with `op` once the divisor is
+// known not to be zero.
+fn integer_division(args: &[Value], op: fn(i64, i64) -> Option<i64>) -> Result<Value, EvalError> {
+    let (a, b) = (as_integer(&args[0])?, as_integer(&args[1])?);
+    if b == 0 {
+        return Err(EvalError::new(EvalErrorKind::DivideByZero));
+    }
+    op(a, b).map(Value::Integer).ok_or_else(overflow)
+}
+
+// Rounds towards zero.
+fn quotient(args: &[Value]) -> Result<Value, EvalError> {
+    integer_division(args, i64::checked_div)
+}
+
+// Takes the sign of the dividend.
+fn remainder(args: &[Value]) -> Result<Value, EvalError> {
+    integer_division(args, i64::checked_rem)
+}
+
+// Takes the sign of the divisor.
+fn modulo(args: &[Value]) -> Result<Value, EvalError> {
+    integer_division(args, |a, b| {
+        let r = a.checked_rem(b)?;
+        Some(if r != 0 && (r < 0) != (b < 0) {
+            r + b
+        } else {
+            r
+        })
+    })
+}
+
+fn abs(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Integer(i) => i.checked_abs().map(Value::Integer).ok_or_else(overflow),
+        x => Ok(Value::Float(to_float(x)?.abs())),
+    }
+}
+
+// The argument `keep` prefers over all the others.
+fn extreme(args: &[Value], keep: Ordering) -> Result<Value, EvalError> {
+    let mut best = &args[0];
+    to_float(best)?;
+    for x in &args[1..] {
+        if order(x, best)? == Some(keep) {
+            best = x;
+        }
+    }
+    Ok(best.clone())
+}
+
+fn min(args: &[Value]) -> Result<Value, EvalError> {
+    extreme(args, Ordering::Less)
+}
+
+fn max(args: &[Value]) -> Result<Value, EvalError> {
+    extreme(args, Ordering::Greater)
+}
+
+// An integer to a non-negative integer power stays an integer.
+fn expt(args: &[Value]) -> Result<Value, EvalError> {
+    match (&args[0], &args[1]) {
+        (Value::Integer(base), Value::Integer(power)) if *power >= 0 => {
+            let power = u32::try_from(*power).map_err(|_| overflow())?;
+            base.checked_pow(power)
+                .map(Value::Integer)
+                .ok_or_else(overflow)
+        }
+        (base, power) => Ok(Value::Float(to_float(base)?.powf(to_float(power)?))),
+    }
+}
+
+// Exact for integers that are perfect squares.
+fn sqrt(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Integer(i) if *i >= 0 && i.isqrt() * i.isqrt() == *i => {
+            Ok(Value::Integer(i.isqrt()))
+        }
+        x => Ok(Value::Float(to_float(x)?.sqrt())),
+    }
+}
+
+// Integers are already whole, so only floats are changed.
+fn rounding(args: &[Value], op: fn(f64) -> f64) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Integer(i) => Ok(Value::Integer(*i)),
+        x => Ok(Value::Float(op(to_float(x)?))),
+    }
+}
+
+fn floor(args: &[Value]) -> Result<Value, EvalError> {
+    rounding(args, f64::floor)
+}
+
+fn ceiling(args: &[Value]) -> Result<Value, EvalError> {
+    rounding(args, f64::ceil)
+}
+
+// Halves round to even, so (round 2.5) is 2.0.
+fn round(args: &[Value]) -> Result<Value, EvalError> {
+    rounding(args, f64::round_ties_even)
+}
+
+fn truncate(args: &[Value]) -> Result<Value, EvalError> {
+    rounding(args, f64::trunc)
+}
+
+// A float with no fractional part as an integer.
+fn exact(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Integer(i) => Ok(Value::Integer(*i)),
+        Value::Float(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+            Ok(Value::Integer(*f as i64))
+        }
+        x => Err(EvalError::type_error("a whole number", x)),
+    }
+}
+
+fn inexact(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Float(to_float(&args[0])?))
+}
+
+fn order(a: &Value, b: &Value) -> Result<Option<Ordering>, EvalError> {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(Some(a.cmp(b))),
+        _ => Ok(to_float(a)?.partial_cmp(&to_float(b)?)),
+    }
+}
+
+// Whether each argument is in the relation `holds` with the next.
+fn compare(args: &[Value], holds: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    let mut result = true;
+    for pair in args.windows(2) {
+        result &= order(&pair[0], &pair[1])?.is_some_and(holds);
+    }
+    // A single argument still has to be a number.
+    if let [x] = args {
+        to_float(x)?;
+    }
+    Ok(Value::Bool(result))
+}
+
+fn equal(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_eq)
+}
+
+fn less(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_lt)
+}
+
+fn greater(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_gt)
+}
+
+fn less_or_equal(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_le)
+}
+
+fn greater_or_equal(args: &[Value]) -> Result<Value, EvalError> {
+    compare(args, Ordering::is_ge)
+}
+
+fn is_number(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(
+        args[0],
+        Value::Integer(_) | Value::Float(_)
+    )))
+}
+
+// Floats with no fractional part count too.
+fn is_integer(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(match args[0] {
+        Value::Integer(_) => true,
+        Value::Float(f) => f.fract() == 0.0,
+        _ => false,
+    }))
+}
+
+fn sign(args: &[Value], holds: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    let sign = order(&args[0], &Value::Integer(0))?;
+    Ok(Value::Bool(sign.is_some_and(holds)))
+}
+
+fn is_zero(args: &[Value]) -> Result<Value, EvalError> {
+    sign(args, Ordering::is_eq)
+}
+
+fn is_positive(args: &[Value]) -> Result<Value, EvalError> {
+    sign(args, Ordering::is_gt)
+}
+
+fn is_negative(args: &[Value]) -> Result<Value, EvalError> {
+    sign(args, Ordering::is_lt)
+}
+
+fn is_even(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(as_integer(&args[0])? % 2 == 0))
+}
+
+fn is_odd(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(as_integer(&args[0])? % 2 != 0))
+}
+
+fn car(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Pair(pair) => Ok(pair.car.clone()),
+        value => Err(EvalError::type_error("a pair", value)),
+    }
+}
+
+fn cdr(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Pair(pair) => Ok(pair.cdr.clone()),
+        value => Err(EvalError::type_error("a pair", value)),
+    }
+}
+
+fn cons(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::cons(args[0].clone(), args[1].clone()))
+}
+
+fn list(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::list(args.iter().cloned()))
+}
+
+// Every argument but the last must be a list. The last is shared rather than
+// copied, and need not be a list.
+fn append(args: &[Value]) -> Result<Value, EvalError> {
+    let Some((last, lists)) = args.split_last() else {
+        return Ok(Value::Nil);
+    };
+    let mut result = last.clone();
+    for list in lists.iter().rev() {
+        for item in as_list(list)?.into_iter().rev() {
+            result = Value::cons(item, result);
+        }
+    }
+    Ok(result)
+}
+
+fn reverse(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::list(as_list(&args[0])?.into_iter().rev()))
+}
+
+fn length(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Integer(as_list(&args[0])?.len() as i64))
+}
+
+fn list_ref(args: &[Value]) -> Result<Value, EvalError> {
+    let items = as_list(&args[0])?;
+    let index = as_index(&args[1])?;
+    items.get(index).cloned().ok_or_else(|| {
+        EvalError::new(EvalErrorKind::IndexOutOfRange {
+            index,
+            len: items.len(),
+        })
+    })
+}
+
+fn is_null(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(args[0], Value::Nil)))
+}
+
+fn is_pair(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(args[0], Value::Pair(_))))
+}
+
+fn is_list(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(args[0].to_vec().is_some()))
+}
+
+// Calls `f` on the first elements of each list, then the second, and so on,
+// stopping at the end of the shortest list.
+fn each(
+    args: &[Value],
+    mut f: impl FnMut(Vec<Value>) -> Result<(), EvalError>,
+) -> Result<(), EvalError> {
+    let lists = args.iter().map(as_list).collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    for i in 0..len {
+        f(lists.iter().map(|list| list[i].clone()).collect())?;
+    }
+    Ok(())
+}
+
+fn map(args: &[Value]) -> Result<Value, EvalError> {
+    let procedure = as_procedure(&args[0])?;
+    let mut results = vec![];
+    each(&args[1..], |items| {
+        results.push(eval::apply(procedure, items)?);
+        Ok(())
+    })?;
+    Ok(Value::list(results))
+}
+
+fn for_each(args: &[Value]) -> Result<Value, EvalError> {
+    let procedure = as_procedure(&args[0])?;
+    each(&args[1..], |items| {
+        eval::apply(procedure, items).map(|_| ())
+    })?;
+    Ok(Value::Void)
+}
+
+fn filter(args: &[Value]) -> Result<Value, EvalError> {
+    let predicate = as_procedure(&args[0])?;
+    let mut kept = vec![];
+    for item in as_list(&args[1])? {
+        if eval::apply(predicate, vec![item.clone()])?.is_true() {
+            kept.push(item);
+        }
+    }
+    Ok(Value::list(kept))
+}
+
+// (fold f init list) calls (f item result) on each item from the left,
+// starting from init.
+fn fold(args: &[Value]) -> Result<Value, EvalError> {
+    let procedure = as_procedure(&args[0])?;
+    as_list(&args[2])?
+        .into_iter()
+        .try_fold(args[1].clone(), |result, item| {
+            eval::apply(procedure, vec![item, result])
+        })
+}
+
+// (apply f a b list) calls f with a, b and then the elements of list.
+fn apply(args: &[Value]) -> Result<Value, EvalError> {
+    let procedure = as_procedure(&args[0])?;
+    let (list, leading) = args[1..].split_last().expect("arity is checked");
+    let mut call_args = leading.to_vec();
+    call_args.extend(as_list(list)?);
+    eval::apply(procedure, call_args)
+}
+
+fn is_procedure(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(as_procedure(&args[0]).is_ok()))
+}
+
+// Lengths and positions count characters, not bytes.
+fn string_length(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Integer(as_str(&args[0])?.chars().count() as i64))
+}
+
+fn string_append(args: &[Value]) -> Result<Value, EvalError> {
+    let parts = args.iter().map(as_str).collect::<Result<Vec<_>, _>>()?;
+    Ok(string(parts.concat()))
+}
+
+// (substring s start) runs to the end of s.
+fn substring(args: &[Value]) -> Result<Value, EvalError> {
+    let s = as_str(&args[0])?;
+    let len = s.chars().count();
+    let start = as_index(&args[1])?;
+    let end = match args.get(2) {
+        Some(end) => as_index(end)?,
+        None => len,
+    };
+    // start must be within [0, end] and end within [0, len].
+    for (index, len) in [(end, len), (start, end)] {
+        if index > len {
+            return Err(EvalError::new(EvalErrorKind::IndexOutOfRange {
+                index,
+                len,
+            }));
+        }
+    }
+    Ok(string(
+        s.chars()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .collect::<String>(),
+    ))
+}
+
+fn string_upcase(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(string(as_str(&args[0])?.to_uppercase()))
+}
+
+fn string_downcase(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(string(as_str(&args[0])?.to_lowercase()))
+}
+
+// Splits on whitespace, or on the separator when there is one.
+fn string_split(args: &[Value]) -> Result<Value, EvalError> {
+    let s = as_str(&args[0])?;
+    let parts: Vec<&str> = match args.get(1) {
+        None => s.split_whitespace().collect(),
+        Some(Value::Str(separator)) if !separator.is_empty() => s.split(&**separator).collect(),
+        Some(separator) => return Err(EvalError::type_error("a non-empty string", separator)),
+    };
+    Ok(Value::list(parts.into_iter().map(string)))
+}
+
+// Joins with a space unless given another separator.
+fn string_join(args: &[Value]) -> Result<Value, EvalError> {
+    let items = as_list(&args[0])?;
+    let parts = items.iter().map(as_str).collect::<Result<Vec<_>, _>>()?;
+    let separator = match args.get(1) {
+        Some(separator) => as_str(separator)?,
+        None => " ",
+    };
+    Ok(string(parts.join(separator)))
+}
+
+fn compare_strings(args: &[Value], holds: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    let strings = args.iter().map(as_str).collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::Bool(
+        strings.windows(2).all(|pair| holds(pair[0].cmp(pair[1]))),
+    ))
+}
+
+fn string_equal(args: &[Value]) -> Result<Value, EvalError> {
+    compare_strings(args, Ordering::is_eq)
+}
+
+fn string_less(args: &[Value]) -> Result<Value, EvalError> {
+    compare_strings(args, Ordering::is_lt)
+}
+
+fn string_greater(args: &[Value]) -> Result<Value, EvalError> {
+    compare_strings(args, Ordering::is_gt)
+}
+
+// Reads numbers the way the reader does, giving #f for anything else.
+fn string_to_number(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(match classify_atom(as_str(&args[0])?.trim()) {
+        Some(Token::Integer(i)) => Value::Integer(i),
+        Some(Token::Float(f)) => Value::Float(f),
+        _ => Value::Bool(false),
+    })
+}
+
+fn number_to_string(args: &[Value]) -> Result<Value, EvalError> {
+    to_float(&args[0])?;
+    Ok(string(args[0].to_string()))
+}
+
+fn string_to_symbol(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::symbol(as_str(&args[0])?))
+}
+
+fn symbol_to_string(args: &[Value]) -> Result<Value, EvalError> {
+    match &args[0] {
+        Value::Symbol(name) => Ok(Value::Str(name.clone())),
+        value => Err(EvalError::type_error("a symbol", value)),
+    }
+}
+
+fn is_string(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(args[0], Value::Str(_))))
+}
+
+fn is_symbol(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(args[0], Value::Symbol(_))))
+}
+
+fn not(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(!args[0].is_true()))
+}
+
+// Pairs, strings and procedures are the same only if they are the same
+// object; everything else compares by value.
+fn is_eq(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(match (&args[0], &args[1]) {
+        (Value::Pair(a), Value::Pair(b)) => Rc::ptr_eq(a, b),
+        (Value::Str(a), Value::Str(b)) => Rc::ptr_eq(a, b),
+        (a, b) => a == b,
+    }))
+}
+
+// Compares lists and strings by their contents.
+fn is_equal(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(args[0] == args[1]))
+}
+
+fn is_boolean(args: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(matches!(args[0], Value::Bool(_))))
+}
+
+fn display(args: &[Value]) -> Result<Value, EvalError> {
+    print!("{}", args[0].display());
+    Ok(Value::Void)
+}
+
+fn newline(_: &[Value]) -> Result<Value, EvalError> {
+    println!();
+    Ok(Value::Void)
+}
+
+fn command_line(_: &[Value]) -> Result<Value, EvalError> {
+    let args = COMMAND_LINE.with(|command_line| command_line.borrow().clone());
+    Ok(Value::list(
+        args.iter().map(|arg| Value::Str(arg.as_str().into())),
+    ))
+}
+
+// (exit) and (exit #t) succeed, (exit #f) fails, and (exit n) exits with n.
+fn exit(args: &[Value]) -> Result<Value, EvalError> {
+    let status = match args {
+        [] | [Value::Bool(true)] => 0,
+        [Value::Bool(false)] => 1,
+        [Value::Integer(n @ 0..=255)] => *n as i32,
+        [value @ Value::Integer(_)] => {
+            return Err(EvalError::type_error("an exit status from 0 to 255", value));
+        }
+        [value] => return Err(EvalError::type_error("an integer or boolean", value)),
+        _ => unreachable!("arity is checked"),
+    };
+    Err(EvalError::new(EvalErrorKind::Exit(status)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_program;
+    use crate::parser::parse_program;
+
+    fn run(source: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        let forms = parse_program(source)?;
+        Ok(eval_program(&forms, &global_env())?)
+    }
+
+    #[test]
+    fn arithmetic_keeps_integers_exact() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("(+)")?, Value::Integer(0));
+        assert_eq!(run("(+ 1 2 3)")?, Value::Integer(6));
+        assert_eq!(run("(- 5)")?, Value::Integer(-5));
+        assert_eq!(run("(- 10 1 2)")?, Value::Integer(7));
+        assert_eq!(run("(* 2 3 4)")?, Value::Integer(24));
+        assert_eq!(run("(/ 12 2 3)")?, Value::Integer(2));
+        assert_eq!(run("(/ 1 4)")?, Value::Float(0.25));
+        assert_eq!(run("(+ 1 0.5)")?, Value::Float(1.5));
+        assert_eq!(run("(/ 2)")?, Value::Float(0.5));
+
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        let error = |source| run(source).expect_err("source fails").to_string();
+
+        assert_eq!(
+            error("(+ 1 \"2\")"),
+            "Eval error at 1:1: expected a number, got \"2\""
+        );
+        assert_eq!(error("(/ 1 0)"), "Eval error at 1:1: division by zero");
+        assert_eq!(
+            error("(* 9223372036854775807 2)"),
+            "Eval error at 1:1: integer overflow"
+        );
+        assert_eq!(
+            error("(-)"),
+            "Eval error at 1:1: expected at least 1 argument, got 0"
+        );
+    }
+
+    #[test]
+    fn comparisons_chain() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("(< 1 2 3)")?, Value::Bool(true));
+        assert_eq!(run("(< 1 3 2)")?, Value::Bool(false));
+        assert_eq!(run("(= 1 1.0)")?, Value::Bool(true));
+        assert_eq!(run("(>= 3 3 1)")?, Value::Bool(true));
+        assert_eq!(run("(> 1)")?, Value::Bool(true));
+
+        Ok(())
+    }
+
+    #[test]
+    fn exit_and_command_line() -> Result<(), Box<dyn std::error::Error>> {
+        let status = |source| run(source).map_err(|e| e.to_string());
+
+        assert_eq!(
+            status("(exit)"),
+            Err("Eval error at 1:1: exit with status 0".to_string())
+        );
+        assert_eq!(
+            status("(exit #f)"),
+            Err("Eval error at 1:1: exit with status 1".to_string())
+        );
+        assert_eq!(
+            status("(exit 3)"),
+            Err("Eval error at 1:1: exit with status 3".to_string())
+        );
+        assert_eq!(
+            status("(exit 255)"),
+            Err("Eval error at 1:1: exit with status 255".to_string())
+        );
+        assert_eq!(
+            status("(exit 300)"),
+            Err("Eval error at 1:1: expected an exit status from 0 to 255, got 300".to_string())
+        );
+        assert_eq!(
+            status("(exit -1)"),
+            Err("Eval error at 1:1: expected an exit status from 0 to 255, got -1".to_string())
+        );
+        assert_eq!(
+            status("(exit 1 2)"),
+            Err("Eval error at 1:1: expected 0 to 1 arguments, got 2".to_string())
+        );
+
+        set_command_line(vec!["a.lisp".to_string(), "-v".to_string()]);
+        assert_eq!(
+            run("(command-line)")?,
+            Value::list([Value::Str("a.lisp".into()), Value::Str("-v".into())])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn numeric_procedures() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("(quotient -7 2)")?, Value::Integer(-3));
+        assert_eq!(run("(remainder -7 2)")?, Value::Integer(-1));
+        assert_eq!(run("(modulo -7 2)")?, Value::Integer(1));
+        assert_eq!(run("(modulo 7 -2)")?, Value::Integer(-1));
+        assert_eq!(run("(abs -4)")?, Value::Integer(4));
+        assert_eq!(run("(abs -4.5)")?, Value::Float(4.5));
+        assert_eq!(run("(min 3 1.5 2)")?, Value::Float(1.5));
+        assert_eq!(run("(max 3 1 2)")?, Value::Integer(3));
+        assert_eq!(run("(expt 2 10)")?, Value::Integer(1024));
+        assert_eq!(run("(expt 2 -1)")?, Value::Float(0.5));
+        assert_eq!(run("(sqrt 16)")?, Value::Integer(4));
+        assert_eq!(run("(sqrt 2.25)")?, Value::Float(1.5));
+        assert_eq!(run("(floor -1.5)")?, Value::Float(-2.0));
+        assert_eq!(run("(ceiling 1.2)")?, Value::Float(2.0));
+        assert_eq!(run("(round 2.5)")?, Value::Float(2.0));
+        assert_eq!(run("(truncate -1.7)")?, Value::Float(-1.0));
+        assert_eq!(run("(round 7)")?, Value::Integer(7));
+        assert_eq!(run("(exact 3.0)")?, Value::Integer(3));
+        assert_eq!(run("(inexact 3)")?, Value::Float(3.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn numeric_predicates() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("(number? 1.5)")?, Value::Bool(true));
+        assert_eq!(run("(number? \"1\")")?, Value::Bool(false));
+        assert_eq!(run("(integer? 2.0)")?, Value::Bool(true));
+        assert_eq!(run("(integer? 2.5)")?, Value::Bool(false));
+        assert_eq!(run("(zero? 0.0)")?, Value::Bool(true));
+        assert_eq!(run("(positive? -1)")?, Value::Bool(false));
+        assert_eq!(run("(negative? -0.5)")?, Value::Bool(true));
+        assert_eq!(run("(even? 4)")?, Value::Bool(true));
+        assert_eq!(run("(odd? -3)")?, Value::Bool(true));
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_procedures() -> Result<(), Box<dyn std::error::Error>> {
+        let numbers = |ns: &[i64]| Value::list(ns.iter().copied().map(Value::Integer));
+
+        assert_eq!(run("(car (list 1 2))")?, Value::Integer(1));
+        assert_eq!(run("(cdr (list 1 2))")?, numbers(&[2]));
+        assert_eq!(run("(cons 0 (list 1))")?, numbers(&[0, 1]));
+        assert_eq!(run("(cons 1 2)")?.to_string(), "(1 . 2)");
+        assert_eq!(run("(list)")?, Value::Nil);
+        assert_eq!(
+            run("(append (list 1) (quote ()) (list 2 3) (list 4))")?,
+            numbers(&[1, 2, 3, 4])
+        );
+        assert_eq!(run("(append (list 1) 2)")?.to_string(), "(1 . 2)");
+        assert_eq!(run("(append)")?, Value::Nil);
+        assert_eq!(run("(reverse (list 1 2 3))")?, numbers(&[3, 2, 1]));
+        assert_eq!(run("(length (list 1 2 3))")?, Value::Integer(3));
+        assert_eq!(run("(list-ref (list 1 2 3) 2)")?, Value::Integer(3));
+        assert_eq!(run("(null? (quote ()))")?, Value::Bool(true));
+        assert_eq!(run("(null? (list 1))")?, Value::Bool(false));
+        assert_eq!(run("(pair? (cons 1 2))")?, Value::Bool(true));
+        assert_eq!(run("(list? (cons 1 2))")?, Value::Bool(false));
+        assert_eq!(run("(list? (quote ()))")?, Value::Bool(true));
+
+        Ok(())
+    }
+
+    #[test]
+    fn higher_order_procedures() -> Result<(), Box<dyn std::error::Error>> {
+        let numbers = |ns: &[i64]| Value::list(ns.iter().copied().map(Value::Integer));
+
+        assert_eq!(
+            run("(map (lambda (x) (* x x)) (list 1 2 3))")?,
+            numbers(&[1, 4, 9])
+        );
+        assert_eq!(
+            run("(map + (list 1 2 3) (list 10 20))")?,
+            numbers(&[11, 22])
+        );
+        assert_eq!(run("(filter odd? (list 1 2 3 4 5))")?, numbers(&[1, 3, 5]));
+        assert_eq!(run("(fold + 0 (list 1 2 3))")?, Value::Integer(6));
+        assert_eq!(
+            run("(fold cons (quote ()) (list 1 2 3))")?,
+            numbers(&[3, 2, 1])
+        );
+        assert_eq!(run("(apply + 1 2 (list 3 4))")?, Value::Integer(10));
+        assert_eq!(run("(apply list (quote ()))")?, Value::Nil);
+        assert_eq!(
+            run("(define n 0) (for-each (lambda (x) (set! n (+ n x))) (list 1 2 3)) n")?,
+            Value::Integer(6)
+        );
+        assert_eq!(run("(procedure? car)")?, Value::Bool(true));
+        assert_eq!(run("(procedure? (lambda () 1))")?, Value::Bool(true));
+        assert_eq!(run("(procedure? (quote car))")?, Value::Bool(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn string_procedures() -> Result<(), Box<dyn std::error::Error>> {
+        let string = |s: &str| Value::Str(s.into());
+        let strings = |ss: &[&str]| Value::list(ss.iter().map(|s| Value::Str((*s).into())));
+
+        assert_eq!(run("(string-length \"héllo\")")?, Value::Integer(5));
+        assert_eq!(run("(string-append \"a\" \"b\" \"c\")")?, string("abc"));
+        assert_eq!(run("(substring \"héllo\" 1 3)")?, string("él"));
+        assert_eq!(run("(substring \"hello\" 2)")?, string("llo"));
+        assert_eq!(run("(string-upcase \"abc\")")?, string("ABC"));
+        assert_eq!(run("(string-downcase \"ABC\")")?, string("abc"));
+        assert_eq!(
+            run("(string-split \" a  b c \")")?,
+            strings(&["a", "b", "c"])
+        );
+        assert_eq!(
+            run("(string-split \"a,b,\" \",\")")?,
+            strings(&["a", "b", ""])
+        );
+        assert_eq!(run("(string-join (list \"a\" \"b\"))")?, string("a b"));
+        assert_eq!(
+            run("(string-join (list \"a\" \"b\") \", \")")?,
+            string("a, b")
+        );
+        assert_eq!(run("(string=? \"a\" \"a\" \"a\")")?, Value::Bool(true));
+        assert_eq!(run("(string<? \"a\" \"b\" \"c\")")?, Value::Bool(true));
+        assert_eq!(run("(string>? \"a\" \"b\")")?, Value::Bool(false));
+        assert_eq!(run("(string->number \"-12\")")?, Value::Integer(-12));
+        assert_eq!(run("(string->number \"1e3\")")?, Value::Float(1000.0));
+        assert_eq!(run("(string->number \"nan\")")?, Value::Bool(false));
+        assert_eq!(run("(number->string 2.5)")?, string("2.5"));
+        assert_eq!(run("(string->symbol \"abc\")")?, Value::symbol("abc"));
+        assert_eq!(run("(symbol->string (quote abc))")?, string("abc"));
+        assert_eq!(run("(string? \"a\")")?, Value::Bool(true));
+        assert_eq!(run("(symbol? (quote a))")?, Value::Bool(true));
+
+        Ok(())
+    }
+
+    #[test]
+    fn equality_and_booleans() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("(not #f)")?, Value::Bool(true));
+        assert_eq!(run("(not 0)")?, Value::Bool(false));
+        assert_eq!(run("(eq? (quote a) (quote a))")?, Value::Bool(true));
+        assert_eq!(run("(eq? (list 1) (list 1))")?, Value::Bool(false));
+        assert_eq!(run("(define l (list 1)) (eq? l l)")?, Value::Bool(true));
+        assert_eq!(
+            run("(equal? (list 1 \"a\") (list 1 \"a\"))")?,
+            Value::Bool(true)
+        );
+        assert_eq!(run("(equal? 1 1.0)")?, Value::Bool(false));
+        assert_eq!(run("(boolean? #f)")?, Value::Bool(true));
+        assert_eq!(run("(boolean? (quote ()))")?, Value::Bool(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn arguments_are_checked() {
+        let error = |source| run(source).expect_err("source fails").to_string();
+
+        assert_eq!(
+            error("(car (quote ()))"),
+            "Eval error at 1:1: expected a pair, got ()"
+        );
+        assert_eq!(
+            error("(car 1 2)"),
+            "Eval error at 1:1: expected 1 argument, got 2"
+        );
+        assert_eq!(
+            error("(length (cons 1 2))"),
+            "Eval error at 1:1: expected a list, got (1 . 2)"
+        );
+        assert_eq!(
+            error("(list-ref (list 1) 1)"),
+            "Eval error at 1:1: index 1 is out of range for length 1"
+        );
+        assert_eq!(
+            error("(list-ref (list 1) -1)"),
+            "Eval error at 1:1: expected a non-negative integer, got -1"
+        );
+        assert_eq!(
+            error("(map 1 (list 1))"),
+            "Eval error at 1:1: expected a procedure, got 1"
+        );
+        assert_eq!(
+            error("(apply + 1)"),
+            "Eval error at 1:1: expected a list, got 1"
+        );
+        assert_eq!(
+            error("(fold + 0)"),
+            "Eval error at 1:1: expected 3 arguments, got 2"
+        );
+        assert_eq!(
+            error("(quotient 1 0)"),
+            "Eval error at 1:1: division by zero"
+        );
+        assert_eq!(
+            error("(quotient 1.5 1)"),
+            "Eval error at 1:1: expected an integer, got 1.5"
+        );
+        assert_eq!(
+            error("(expt 10 100)"),
+            "Eval error at 1:1: integer overflow"
+        );
+        assert_eq!(
+            error("(exact 1.5)"),
+            "Eval error at 1:1: expected a whole number, got 1.5"
+        );
+        assert_eq!(
+            error("(string-append \"a\" 1)"),
+            "Eval error at 1:1: expected a string, got 1"
+        );
+        assert_eq!(
+            error("(substring \"abc\" 2 1)"),
+            "Eval error at 1:1: index 2 is out of range for length 1"
+        );
+        assert_eq!(
+            error("(substring \"abc\" 0 4)"),
+            "Eval error at 1:1: index 4 is out of range for length 3"
+        );
+        assert_eq!(
+            error("(string-split \"abc\" \"\")"),
+            "Eval error at 1:1: expected a non-empty string, got \"\""
+        );
+        assert_eq!(
+            error("(symbol->string \"a\")"),
+            "Eval error at 1:1: expected a symbol, got \"a\""
+        );
+        // Errors inside a procedure passed in point at where they happened.
+        assert_eq!(
+            error("(map (lambda (x) (car x)) (list 1))"),
+            "Eval error at 1:18: expected a pair, got 1"
+        );
+    }
+}
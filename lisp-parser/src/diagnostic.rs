@@ -0,0 +1,100 @@
+use crate::span::{LineIndex, Span};
+
+// An error message pointing into the source, rendered as
+//
+//   error: unexpected end of input
+//    --> example.lisp:3:12
+//     |
+//   3 | (define (f x)
+//     |            ^
+//     = hint: unclosed '(' opened at 3:1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    // `origin` names the source, e.g. a file path. The line is left out if
+    // `source` does not have it, as when the span is from another source.
+    pub fn render(&self, source: &str, origin: &str) -> String {
+        let index = LineIndex::new(source);
+        let number = self.span.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        let mut rendered = format!(
+            "error: {}\n{}--> {}:{}",
+            self.message, gutter, origin, self.span
+        );
+        if let Some(line) = index.line(self.span.line) {
+            // Keep tabs in the indent so the caret lines up with the text above.
+            let indent: String = line
+                .chars()
+                .take(self.span.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let underlined = source
+                .get(self.span.start..self.span.end)
+                .unwrap_or_default()
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .count();
+            rendered += &format!(
+                "\n{} |\n{} | {}\n{} | {}{}",
+                gutter,
+                number,
+                line,
+                gutter,
+                indent,
+                "^".repeat(underlined.max(1)),
+            );
+        }
+        if let Some(hint) = &self.hint {
+            rendered += &format!("\n{} = hint: {}", gutter, hint);
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_line_with_a_caret() {
+        let source = "(define x 1)\n(define (f x)\n\t(+ x 1)";
+        let index = LineIndex::new(source);
+        let diagnostic = Diagnostic {
+            message: "unexpected end of input".to_string(),
+            span: index.span(33, 34),
+            hint: Some("unclosed '(' opened at 2:1".to_string()),
+        };
+
+        let expected = "\
+error: unexpected end of input
+ --> example.lisp:3:7
+  |
+3 | \t(+ x 1)
+  | \t     ^
+  = hint: unclosed '(' opened at 2:1";
+
+        assert_eq!(diagnostic.render(source, "example.lisp"), expected);
+    }
+
+    #[test]
+    fn lines_missing_from_the_source_are_left_out() {
+        let diagnostic = Diagnostic {
+            message: "unbound variable y".to_string(),
+            span: LineIndex::new("\n\n(+ x y)").span(7, 8),
+            hint: None,
+        };
+
+        assert_eq!(
+            diagnostic.render("(f 1)", "<repl>"),
+            "error: unbound variable y\n --> <repl>:3:6"
+        );
+    }
+}
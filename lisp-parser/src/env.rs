@@ -0,0 +1,125 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::value::Value;
+
+// A scope of variables, nested in the scope it was created in. Cloning an
+// `Env` shares the scope rather than copying it.
+#[derive(Clone, Default)]
+pub struct Env(Rc<Frame>);
+
+#[derive(Default)]
+struct Frame {
+    vars: RefCell<HashMap<String, Value>>,
+    parent: Option<Env>,
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env::default()
+    }
+
+    // A new scope inside this one.
+    pub fn child(&self) -> Env {
+        Env(Rc::new(Frame {
+            vars: RefCell::default(),
+            parent: Some(self.clone()),
+        }))
+    }
+
+    // Binds `name` in this scope, replacing any binding it already has here.
+    pub fn define(&self, name: &str, value: Value) {
+        self.0.vars.borrow_mut().insert(name.to_string(), value);
+    }
+
+    // Looks `name` up in this scope and then the ones it is nested in.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let mut env = self;
+        loop {
+            if let Some(value) = env.0.vars.borrow().get(name) {
+                return Some(value.clone());
+            }
+            env = env.0.parent.as_ref()?;
+        }
+    }
+
+    // Changes the nearest existing binding of `name`, returning false if
+    // there is none.
+    pub fn set(&self, name: &str, value: Value) -> bool {
+        let mut env = self;
+        loop {
+            if let Some(slot) = env.0.vars.borrow_mut().get_mut(name) {
+                *slot = value;
+                return true;
+            }
+            match &env.0.parent {
+                Some(parent) => env = parent,
+                None => return false,
+            }
+        }
+    }
+
+    // The bindings made in this scope itself, sorted by name.
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        let mut bindings: Vec<_> = self
+            .0
+            .vars
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    // Every name visible from this scope.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![];
+        let mut env = Some(self);
+        while let Some(scope) = env {
+            names.extend(scope.0.vars.borrow().keys().cloned());
+            env = scope.0.parent.as_ref();
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+// Procedures refer back to the scopes they were made in, so the variables
+// are left out.
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Env").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inner_scopes_shadow_and_assign_outer_ones() {
+        let outer = Env::new();
+        outer.define("x", Value::Integer(1));
+        outer.define("y", Value::Integer(2));
+        let inner = outer.child();
+        inner.define("x", Value::Integer(10));
+
+        assert_eq!(inner.get("x"), Some(Value::Integer(10)));
+        assert_eq!(outer.get("x"), Some(Value::Integer(1)));
+
+        assert!(inner.set("y", Value::Integer(20)));
+        assert_eq!(outer.get("y"), Some(Value::Integer(20)));
+        assert!(!inner.set("z", Value::Integer(0)));
+        assert_eq!(inner.get("z"), None);
+
+        assert_eq!(
+            inner.bindings(),
+            vec![("x".to_string(), Value::Integer(10))]
+        );
+        assert_eq!(inner.names(), vec!["x", "y"]);
+    }
+}
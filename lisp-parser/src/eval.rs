@@ -0,0 +1,885 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use std::thread;
+
+use crate::diagnostic::Diagnostic;
+use crate::env::Env;
+use crate::expand::expand;
+use crate::parser::AST;
+use crate::span::{Span, Spanned};
+use crate::value::{Arity, Procedure, Value};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalErrorKind {
+    Unbound(String),
+    NotAProcedure(String),
+    Arity {
+        expected: Arity,
+        got: usize,
+    },
+    Type {
+        expected: &'static str,
+        got: String,
+    },
+    DivideByZero,
+    Overflow,
+    IndexOutOfRange {
+        index: usize,
+        len: usize,
+    },
+    // Raised by `exit` to end the program with this status.
+    Exit(i32),
+    // A special form used with the wrong shape, and the shape it takes.
+    BadSyntax {
+        form: &'static str,
+        usage: &'static str,
+    },
+    // A use of this `syntax-rules` macro that none of its patterns match.
+    NoMatchingRule(String),
+    // Evaluation nested deeper than MAX_DEPTH.
+    TooDeep,
+}
+
+impl fmt::Display for EvalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalErrorKind::Unbound(name) => write!(f, "unbound variable {}", name),
+            EvalErrorKind::NotAProcedure(value) => write!(f, "{} is not a procedure", value),
+            EvalErrorKind::Arity { expected, got } => {
+                write!(f, "expected {}, got {}", expected, got)
+            }
+            EvalErrorKind::Type { expected, got } => {
+                write!(f, "expected {}, got {}", expected, got)
+            }
+            EvalErrorKind::DivideByZero => write!(f, "division by zero"),
+            EvalErrorKind::Overflow => write!(f, "integer overflow"),
+            EvalErrorKind::IndexOutOfRange { index, len } => {
+                write!(f, "index {} is out of range for length {}", index, len)
+            }
+            EvalErrorKind::Exit(code) => write!(f, "exit with status {}", code),
+            EvalErrorKind::BadSyntax { form, usage } => {
+                write!(f, "bad {} form, expected {}", form, usage)
+            }
+            EvalErrorKind::NoMatchingRule(name) => {
+                write!(f, "no rule of {} matches this form", name)
+            }
+            EvalErrorKind::TooDeep => write!(f, "recursion too deep"),
+        }
+    }
+}
+
+// Errors raised by builtins have no span until they reach the call that
+// raised them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalError {
+    pub kind: EvalErrorKind,
+    pub span: Option<Span>,
+}
+
+impl EvalError {
+    pub fn new(kind: EvalErrorKind) -> Self {
+        EvalError { kind, span: None }
+    }
+
+    pub fn type_error(expected: &'static str, got: &Value) -> Self {
+        EvalError::new(EvalErrorKind::Type {
+            expected,
+            got: got.to_string(),
+        })
+    }
+
+    pub fn at(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            message: self.kind.to_string(),
+            span: self.span.unwrap_or_default(),
+            hint: None,
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "Eval error at {}: {}", span, self.kind),
+            None => write!(f, "Eval error: {}", self.kind),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+pub fn bad_syntax(form: &'static str, usage: &'static str) -> EvalError {
+    EvalError::new(EvalErrorKind::BadSyntax { form, usage })
+}
+
+// Expands and evaluates the forms of a program in order, returning the last
+// one's value. Each form is expanded only once those before it have run, so
+// it can use the macros they define.
+pub fn eval_program(forms: &[Spanned<AST>], env: &Env) -> Result<Value, EvalError> {
+    let mut value = Value::Void;
+    for form in forms {
+        value = eval(&expand(form, env)?, env)?;
+    }
+    Ok(value)
+}
+
+// Each evaluation counts towards MAX_DEPTH from before its arguments are
+// evaluated, so forms nested through their arguments are counted too.
+pub fn eval(ast: &Spanned<AST>, env: &Env) -> Result<Value, EvalError> {
+    let _nested = Nested::enter().map_err(|e| e.at(ast.span))?;
+    run(eval_step(ast, env)?)
+}
+
+// How far evaluating a form got: to its value, or to a call in tail position
+// whose body is still to run in the given scope.
+enum Step {
+    Done(Value),
+    Body(Rc<Procedure>, Env),
+}
+
+// How deep evaluations may nest, e.g. calls that are not in tail position,
+// before giving up with an error rather than overflowing the stack.
+pub const MAX_DEPTH: usize = 20_000;
+
+// Enough for MAX_DEPTH nested evaluations with room to spare, even in a
+// debug build. See `on_large_stack`.
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// Counts one nested evaluation, or expansion, for as long as it is alive.
+pub struct Nested;
+
+impl Nested {
+    pub fn enter() -> Result<Nested, EvalError> {
+        DEPTH.with(|depth| {
+            if depth.get() >= MAX_DEPTH {
+                return Err(EvalError::new(EvalErrorKind::TooDeep));
+            }
+            depth.set(depth.get() + 1);
+            Ok(Nested)
+        })
+    }
+}
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+// Runs `f` on a thread with a stack that has room for MAX_DEPTH nested
+// evaluations, which the main thread's does not.
+pub fn on_large_stack<T: Send>(f: impl FnOnce() -> T + Send) -> io::Result<T> {
+    thread::scope(|scope| {
+        let evaluator = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)?;
+        Ok(evaluator
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+    })
+}
+
+// Runs procedure bodies until one gives a value. Tail calls come back here
+// instead of nesting, so a loop written as recursion runs in constant stack.
+fn run(mut step: Step) -> Result<Value, EvalError> {
+    loop {
+        match step {
+            Step::Done(value) => return Ok(value),
+            Step::Body(procedure, scope) => step = body_step(&procedure.body, &scope)?,
+        }
+    }
+}
+
+// Evaluates `ast` up to a call in tail position, if it ends in one.
+fn eval_step(ast: &Spanned<AST>, env: &Env) -> Result<Step, EvalError> {
+    eval_node(ast, env).map_err(|e| e.at(ast.span))
+}
+
+fn eval_node(ast: &Spanned<AST>, env: &Env) -> Result<Step, EvalError> {
+    let value = match &ast.node {
+        AST::Integer(i) => Value::Integer(*i),
+        AST::Float(f) => Value::Float(*f),
+        AST::Str(s) => Value::Str(s.as_str().into()),
+        AST::Bool(b) => Value::Bool(*b),
+        AST::Symbol(name) => env
+            .get(name)
+            .ok_or_else(|| EvalError::new(EvalErrorKind::Unbound(name.clone())))?,
+        AST::Lambda(params, body) => make_procedure(params, body, env),
+        AST::List(items) => {
+            let Some((head, args)) = items.split_first() else {
+                return Err(bad_syntax("()", "(procedure arguments...)"));
+            };
+            if let AST::Symbol(name) = &head.node
+                && let Some(result) = special_form(name, args, ast.span, env)
+            {
+                return result;
+            }
+
+            let procedure = eval(head, env)?;
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            return call(&procedure, args);
+        }
+    };
+    Ok(Step::Done(value))
+}
+
+// The names `special_form` handles.
+pub const SPECIAL_FORMS: [&str; 11] = [
+    "quote",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "if",
+    "cond",
+    "define",
+    "lambda",
+    "let",
+    "begin",
+    "set!",
+];
+
+// Evaluates `name` as a special form, or returns None if it is not one.
+fn special_form(
+    name: &str,
+    args: &[Spanned<AST>],
+    span: Span,
+    env: &Env,
+) -> Option<Result<Step, EvalError>> {
+    let result = match name {
+        "quote" => quote(args).map(Step::Done),
+        "quasiquote" => quasiquote(args, env).map(Step::Done),
+        "unquote" => Err(bad_syntax("unquote", ",expr inside a quasiquote")),
+        "unquote-splicing" => Err(bad_syntax(
+            "unquote-splicing",
+            ",@expr inside a quasiquoted list",
+        )),
+        "if" => if_form(args, env),
+        "cond" => cond(args, env),
+        "define" => define(args, span, env).map(Step::Done),
+        "lambda" => lambda(args).and_then(|lambda| eval_step(&Spanned::new(lambda, span), env)),
+        "let" => let_form(args, env),
+        "begin" => body_step(args, env),
+        "set!" => set(args, env).map(Step::Done),
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn quote(args: &[Spanned<AST>]) -> Result<Value, EvalError> {
+    match args {
+        [datum] => Ok(Value::from_ast(&datum.node)),
+        _ => Err(bad_syntax("quote", "(quote datum)")),
+    }
+}
+
+fn quasiquote(args: &[Spanned<AST>], env: &Env) -> Result<Value, EvalError> {
+    match args {
+        [template] => quasi(template, 1, env),
+        _ => Err(bad_syntax("quasiquote", "(quasiquote template)")),
+    }
+}
+
+// A symbol and one form after it, as `'x` and `,x` read.
+fn prefixed(items: &[Spanned<AST>]) -> Option<(&str, &Spanned<AST>)> {
+    match items {
+        [
+            Spanned {
+                node: AST::Symbol(name),
+                ..
+            },
+            form,
+        ] => Some((name, form)),
+        _ => None,
+    }
+}
+
+// Builds the datum `template` stands for, with the value of each `unquote`
+// and the elements of each `unquote-splicing` filled in. Only those `depth`
+// quasiquotes in belong to this one; deeper ones are left as they are.
+fn quasi(template: &Spanned<AST>, depth: usize, env: &Env) -> Result<Value, EvalError> {
+    let AST::List(items) = &template.node else {
+        return Ok(Value::from_ast(&template.node));
+    };
+    if let Some((name, form)) = prefixed(items) {
+        let depth = match name {
+            "unquote" if depth == 1 => return eval(form, env),
+            "unquote-splicing" if depth == 1 => {
+                return Err(bad_syntax(
+                    "unquote-splicing",
+                    ",@expr inside a quasiquoted list",
+                ));
+            }
+            "unquote" | "unquote-splicing" => Some(depth - 1),
+            "quasiquote" => Some(depth + 1),
+            _ => None,
+        };
+        if let Some(depth) = depth {
+            return Ok(Value::list([Value::symbol(name), quasi(form, depth, env)?]));
+        }
+    }
+
+    let mut values = vec![];
+    for item in items {
+        if let AST::List(splice) = &item.node
+            && let Some(("unquote-splicing", form)) = prefixed(splice)
+            && depth == 1
+        {
+            let spliced = eval(form, env)?;
+            let spliced = spliced
+                .to_vec()
+                .ok_or_else(|| EvalError::type_error("a list", &spliced).at(item.span))?;
+            values.extend(spliced);
+        } else {
+            values.push(quasi(item, depth, env)?);
+        }
+    }
+    Ok(Value::list(values))
+}
+
+fn if_form(args: &[Spanned<AST>], env: &Env) -> Result<Step, EvalError> {
+    let (test, consequent, alternative) = match args {
+        [test, consequent] => (test, consequent, None),
+        [test, consequent, alternative] => (test, consequent, Some(alternative)),
+        _ => {
+            return Err(bad_syntax(
+                "if",
+                "(if test consequent) or (if test consequent alternative)",
+            ));
+        }
+    };
+
+    if eval(test, env)?.is_true() {
+        eval_step(consequent, env)
+    } else {
+        alternative.map_or(Ok(Step::Done(Value::Void)), |alternative| {
+            eval_step(alternative, env)
+        })
+    }
+}
+
+// Runs the body of the first clause whose test is true. A clause without a
+// body gives the value of its test, and `else` always matches.
+fn cond(args: &[Spanned<AST>], env: &Env) -> Result<Step, EvalError> {
+    let usage = "(cond (test body...)... (else body...))";
+    for (i, clause) in args.iter().enumerate() {
+        let AST::List(clause) = &clause.node else {
+            return Err(bad_syntax("cond", usage));
+        };
+        let Some((test, body)) = clause.split_first() else {
+            return Err(bad_syntax("cond", usage));
+        };
+
+        if let AST::Symbol(name) = &test.node
+            && name == "else"
+        {
+            if i + 1 != args.len() || body.is_empty() {
+                return Err(bad_syntax("cond", usage));
+            }
+            return body_step(body, env);
+        }
+        let value = eval(test, env)?;
+        if value.is_true() {
+            return match body.is_empty() {
+                true => Ok(Step::Done(value)),
+                false => body_step(body, env),
+            };
+        }
+    }
+    Ok(Step::Done(Value::Void))
+}
+
+const DEFINE_USAGE: &str = "(define name value) or (define (name params...) body...)";
+
+fn define(args: &[Spanned<AST>], span: Span, env: &Env) -> Result<Value, EvalError> {
+    let (name, value) = match args {
+        [
+            Spanned {
+                node: AST::Symbol(name),
+                ..
+            },
+            value,
+        ] => (name, eval(value, env)?),
+        // (define (name params...) body...) is short for
+        // (define name (lambda (params...) body...)).
+        [
+            Spanned {
+                node: AST::List(signature),
+                span: signature_span,
+            },
+            body @ ..,
+        ] => {
+            let Some((
+                Spanned {
+                    node: AST::Symbol(name),
+                    ..
+                },
+                params,
+            )) = signature.split_first()
+            else {
+                return Err(bad_syntax("define", DEFINE_USAGE));
+            };
+            let params = Spanned::new(AST::List(params.to_vec()), *signature_span);
+            let lambda =
+                lambda_parts(&params, body).ok_or_else(|| bad_syntax("define", DEFINE_USAGE))?;
+            (name, eval(&Spanned::new(lambda, span), env)?)
+        }
+        _ => return Err(bad_syntax("define", DEFINE_USAGE)),
+    };
+
+    env.define(name, value);
+    Ok(Value::Void)
+}
+
+// Checks the shape of a `lambda` form and turns it into an `AST::Lambda`.
+fn lambda(args: &[Spanned<AST>]) -> Result<AST, EvalError> {
+    let usage = "(lambda (params...) body...)";
+    let [params, body @ ..] = args else {
+        return Err(bad_syntax("lambda", usage));
+    };
+    lambda_parts(params, body).ok_or_else(|| bad_syntax("lambda", usage))
+}
+
+// The parameters are a list of symbols, optionally ending in `. rest`, or a
+// single symbol that takes every argument. The body must not be empty.
+fn lambda_parts(params: &Spanned<AST>, body: &[Spanned<AST>]) -> Option<AST> {
+    let names: Vec<String> = match &params.node {
+        AST::Symbol(rest) => vec![".".to_string(), rest.clone()],
+        AST::List(params) => params
+            .iter()
+            .map(|param| match &param.node {
+                AST::Symbol(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+
+    let dot = names.iter().position(|name| name == ".");
+    if body.is_empty() || dot.is_some_and(|dot| dot + 2 != names.len()) {
+        return None;
+    }
+    Some(AST::Lambda(names, Rc::from(body)))
+}
+
+fn make_procedure(params: &[String], body: &Rc<[Spanned<AST>]>, env: &Env) -> Value {
+    let (params, rest) = match params {
+        [params @ .., dot, rest] if dot == "." => (params, Some(rest.clone())),
+        params => (params, None),
+    };
+    Value::Procedure(Rc::new(Procedure {
+        params: params.to_vec(),
+        rest,
+        body: body.clone(),
+        env: env.clone(),
+    }))
+}
+
+fn let_form(args: &[Spanned<AST>], env: &Env) -> Result<Step, EvalError> {
+    let usage = "(let ((name value)...) body...)";
+    let [
+        Spanned {
+            node: AST::List(bindings),
+            ..
+        },
+        body @ ..,
+    ] = args
+    else {
+        return Err(bad_syntax("let", usage));
+    };
+    if body.is_empty() {
+        return Err(bad_syntax("let", usage));
+    }
+
+    let scope = env.child();
+    for binding in bindings {
+        let AST::List(binding) = &binding.node else {
+            return Err(bad_syntax("let", usage));
+        };
+        let [
+            Spanned {
+                node: AST::Symbol(name),
+                ..
+            },
+            value,
+        ] = binding.as_slice()
+        else {
+            return Err(bad_syntax("let", usage));
+        };
+        // Values are evaluated outside the new scope, so they cannot see
+        // each other.
+        scope.define(name, eval(value, env)?);
+    }
+    body_step(body, &scope)
+}
+
+fn set(args: &[Spanned<AST>], env: &Env) -> Result<Value, EvalError> {
+    let [
+        Spanned {
+            node: AST::Symbol(name),
+            ..
+        },
+        value,
+    ] = args
+    else {
+        return Err(bad_syntax("set!", "(set! name value)"));
+    };
+
+    let value = eval(value, env)?;
+    if env.set(name, value) {
+        Ok(Value::Void)
+    } else {
+        Err(EvalError::new(EvalErrorKind::Unbound(name.clone())))
+    }
+}
+
+// Evaluates all but the last form of `body`, which is in tail position.
+fn body_step(body: &[Spanned<AST>], env: &Env) -> Result<Step, EvalError> {
+    let Some((last, init)) = body.split_last() else {
+        return Ok(Step::Done(Value::Void));
+    };
+    for form in init {
+        eval(form, env)?;
+    }
+    eval_step(last, env)
+}
+
+pub fn apply(procedure: &Value, args: Vec<Value>) -> Result<Value, EvalError> {
+    let _nested = Nested::enter()?;
+    run(call(procedure, args)?)
+}
+
+// Checks and binds the arguments of a call, leaving a procedure's body for
+// `run` so the call does not hold on to the stack.
+fn call(procedure: &Value, args: Vec<Value>) -> Result<Step, EvalError> {
+    let arity = match procedure {
+        Value::Builtin(builtin) => builtin.arity,
+        Value::Procedure(procedure) => procedure.arity(),
+        value => {
+            return Err(EvalError::new(EvalErrorKind::NotAProcedure(
+                value.to_string(),
+            )));
+        }
+    };
+    if !arity.accepts(args.len()) {
+        return Err(EvalError::new(EvalErrorKind::Arity {
+            expected: arity,
+            got: args.len(),
+        }));
+    }
+
+    match procedure {
+        Value::Procedure(procedure) => {
+            let scope = procedure.env.child();
+            let mut args = args.into_iter();
+            for (param, arg) in procedure.params.iter().zip(&mut args) {
+                scope.define(param, arg);
+            }
+            if let Some(rest) = &procedure.rest {
+                scope.define(rest, Value::list(args));
+            }
+            Ok(Step::Body(procedure.clone(), scope))
+        }
+        Value::Builtin(builtin) => (builtin.func)(&args).map(Step::Done),
+        _ => unreachable!("only procedures have an arity"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::parser::parse_program;
+
+    fn run(source: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        let forms = parse_program(source)?;
+        Ok(eval_program(&forms, &global_env())?)
+    }
+
+    fn run_err(source: &str) -> EvalError {
+        let forms = parse_program(source).expect("source parses");
+        eval_program(&forms, &global_env()).expect_err("source fails")
+    }
+
+    #[test]
+    fn atoms_evaluate_to_themselves() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("42")?, Value::Integer(42));
+        assert_eq!(run("\"hi\"")?, Value::Str("hi".into()));
+        assert_eq!(run("#f")?, Value::Bool(false));
+        assert_eq!(run("")?, Value::Void);
+
+        Ok(())
+    }
+
+    #[test]
+    fn quote_returns_the_datum() -> Result<(), Box<dyn std::error::Error>> {
+        let expected = Value::list([
+            Value::symbol("a"),
+            Value::list([Value::Integer(1), Value::Str("b".into())]),
+        ]);
+
+        assert_eq!(run("(quote (a (1 \"b\")))")?, expected);
+        assert_eq!(run("(quote ())")?, Value::Nil);
+
+        Ok(())
+    }
+
+    #[test]
+    fn quasiquote_fills_in_unquoted_parts() -> Result<(), Box<dyn std::error::Error>> {
+        let numbers = |ns: &[i64]| Value::list(ns.iter().copied().map(Value::Integer));
+
+        assert_eq!(run("'(a b)")?, run("(list (quote a) (quote b))")?);
+        assert_eq!(run("(define x 2) `(1 ,x ,(+ x 1))")?, numbers(&[1, 2, 3]));
+        assert_eq!(run("`(1 ,@(list 2 3) 4 ,@'())")?, numbers(&[1, 2, 3, 4]));
+        assert_eq!(
+            run("(define x 2) `(a `(b ,(c ,x)))")?.to_string(),
+            "(a (quasiquote (b (unquote (c 2)))))"
+        );
+        assert_eq!(
+            run_err("`(1 ,@2)").to_string(),
+            "Eval error at 1:5: expected a list, got 2"
+        );
+        assert_eq!(
+            run_err(",x").to_string(),
+            "Eval error at 1:1: bad unquote form, expected ,expr inside a quasiquote"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn if_treats_only_false_as_false() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(run("(if 0 1 2)")?, Value::Integer(1));
+        assert_eq!(run("(if (quote ()) 1 2)")?, Value::Integer(1));
+        assert_eq!(run("(if #f 1 2)")?, Value::Integer(2));
+        assert_eq!(run("(if #f 1)")?, Value::Void);
+        // The branch not taken is never evaluated.
+        assert_eq!(run("(if #t 1 (undefined))")?, Value::Integer(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn define_and_call_procedures() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            (define (square x) (* x x))
+            (define sum-of-squares
+              (lambda (a b) (+ (square a) (square b))))
+            (sum-of-squares 3 4)";
+
+        assert_eq!(run(source)?, Value::Integer(25));
+        assert_eq!(
+            run("(define (f x) (if (< x 2) x (+ (f (- x 1)) (f (- x 2))))) (f 15)")?,
+            Value::Integer(610)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn procedures_share_the_body_they_were_made_from() -> Result<(), Box<dyn std::error::Error>> {
+        let forms = parse_program("(x) (+ x 1)")?;
+        let lambda = Spanned::new(lambda(&forms)?, forms[0].span);
+        let env = global_env();
+
+        let (Value::Procedure(a), Value::Procedure(b)) =
+            (eval(&lambda, &env)?, eval(&lambda, &env)?)
+        else {
+            return Err("lambda did not make a procedure".into());
+        };
+        assert!(Rc::ptr_eq(&a.body, &b.body));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rest_parameters_collect_extra_arguments() -> Result<(), Box<dyn std::error::Error>> {
+        let expected = Value::list([Value::Integer(2), Value::Integer(3)]);
+
+        assert_eq!(run("((lambda (a . rest) rest) 1 2 3)")?, expected);
+        assert_eq!(run("((lambda args args) 2 3)")?, expected);
+        assert_eq!(run("(define (f . args) args) (f)")?, Value::Nil);
+
+        Ok(())
+    }
+
+    #[test]
+    fn procedures_close_over_their_scope() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            (define (make-counter)
+              (let ((n 0))
+                (lambda () (set! n (+ n 1)) n)))
+            (define a (make-counter))
+            (define b (make-counter))
+            (a) (a) (b)
+            (+ (* 10 (a)) (b))";
+
+        assert_eq!(run(source)?, Value::Integer(32));
+
+        Ok(())
+    }
+
+    #[test]
+    fn scopes_are_lexical() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            (define x 1)
+            (define (get-x) x)
+            (define (shadow x) (get-x))
+            (shadow 2)";
+
+        assert_eq!(run(source)?, Value::Integer(1));
+        // `let` values are evaluated in the enclosing scope.
+        assert_eq!(
+            run("(define x 1) (let ((x 2) (y x)) (+ x y))")?,
+            Value::Integer(3)
+        );
+        assert_eq!(run("(begin (define y 5) (set! y 6) y)")?, Value::Integer(6));
+
+        Ok(())
+    }
+
+    #[test]
+    fn cond_runs_the_first_true_clause() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            (define (sign n)
+              (cond ((< n 0) (quote negative))
+                    ((= n 0) (quote zero))
+                    (else (quote positive))))
+            (list (sign -2) (sign 0) (sign 5))";
+        let expected = Value::list(["negative", "zero", "positive"].map(Value::symbol));
+
+        assert_eq!(run(source)?, expected);
+        assert_eq!(run("(cond (#f 1) (2))")?, Value::Integer(2));
+        assert_eq!(run("(cond (#f 1))")?, Value::Void);
+        assert_eq!(run("(cond (#t (define x 1) (+ x 1)))")?, Value::Integer(2));
+
+        let usage = "(cond (test body...)... (else body...))";
+        assert_eq!(
+            run_err("(cond (else 1) (#t 2))").kind,
+            bad_syntax("cond", usage).kind
+        );
+        assert_eq!(run_err("(cond 1)").kind, bad_syntax("cond", usage).kind);
+
+        Ok(())
+    }
+
+    // A million calls deep would overflow the stack if tail calls nested.
+    #[test]
+    fn tail_calls_run_in_constant_stack() -> Result<(), Box<dyn std::error::Error>> {
+        let count = "
+            (define (count n acc)
+              (if (= n 0) acc (count (- n 1) (+ acc 1))))
+            (count 1000000 0)";
+        assert_eq!(run(count)?, Value::Integer(1_000_000));
+
+        let mutual = "
+            (define (my-even? n) (cond ((= n 0) #t) (else (my-odd? (- n 1)))))
+            (define (my-odd? n) (cond ((= n 0) #f) (else (my-even? (- n 1)))))
+            (my-even? 1000001)";
+        assert_eq!(run(mutual)?, Value::Bool(false));
+
+        let nested = "
+            (define total 0)
+            (define (loop n)
+              (begin
+                (set! total (+ total n))
+                (let ((next (- n 1)))
+                  (if (> next 0) ((lambda () (loop next))) total))))
+            (loop 100000)";
+        assert_eq!(run(nested)?, Value::Integer(5_000_050_000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn deep_recursion_is_an_error() -> Result<(), Box<dyn std::error::Error>> {
+        let sum = |n: usize| {
+            let source = format!(
+                "(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1))))) (sum {})",
+                n
+            );
+            let forms = parse_program(&source).expect("source parses");
+            eval_program(&forms, &global_env()).map(|value| value.to_string())
+        };
+
+        // Forms nested through their arguments, with no calls in between.
+        // They are evaluated unexpanded, as the expander has a limit of its own.
+        let nested = |n: usize| {
+            let source = format!("{}1{}", "(list ".repeat(n), ")".repeat(n));
+            let forms = parse_program(&source).expect("source parses");
+            eval(&forms[0], &global_env()).map(|value| value.to_string())
+        };
+
+        let results = on_large_stack(|| {
+            [
+                sum(1_000),
+                sum(10_000),
+                sum(MAX_DEPTH),
+                sum(10),
+                nested(3),
+                nested(MAX_DEPTH + 1),
+            ]
+        })?;
+
+        assert_eq!(results[0], Ok("500500".to_string()));
+        assert_eq!(results[1], Ok("50005000".to_string()));
+        let err = results[2].clone().expect_err("too deep");
+        assert_eq!(err.kind, EvalErrorKind::TooDeep);
+        assert_eq!(err.to_string(), "Eval error at 1:42: recursion too deep");
+        // The depth is unwound along with the error.
+        assert_eq!(results[3], Ok("55".to_string()));
+        assert_eq!(results[4], Ok("(((1)))".to_string()));
+        let err = results[5].clone().expect_err("too deep");
+        assert_eq!(err.kind, EvalErrorKind::TooDeep);
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors_point_at_what_failed() {
+        let err = run_err("(define (f x) (+ x y))\n(f 1)");
+        assert_eq!(err.kind, EvalErrorKind::Unbound("y".to_string()));
+        assert_eq!(err.span.map(|s| s.to_string()), Some("1:20".to_string()));
+
+        let err = run_err("(define (f x) x)\n(f 1 2)");
+        assert_eq!(
+            err.kind,
+            EvalErrorKind::Arity {
+                expected: Arity::Exactly(1),
+                got: 2
+            }
+        );
+        assert_eq!(err.span.map(|s| s.to_string()), Some("2:1".to_string()));
+
+        assert_eq!(
+            run_err("(1 2)").kind,
+            EvalErrorKind::NotAProcedure("1".to_string())
+        );
+        assert_eq!(
+            run_err("(set! z 1)").kind,
+            EvalErrorKind::Unbound("z".to_string())
+        );
+        assert_eq!(
+            run_err("(let ((x)) x)").to_string(),
+            "Eval error at 1:1: bad let form, expected (let ((name value)...) body...)"
+        );
+        assert_eq!(
+            run_err("(lambda (x 1) x)").kind,
+            bad_syntax("lambda", "(lambda (params...) body...)").kind
+        );
+    }
+}
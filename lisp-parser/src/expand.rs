@@ -0,0 +1,1066 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::{self, EvalError, EvalErrorKind, Nested, SPECIAL_FORMS, bad_syntax};
+use crate::parser::AST;
+use crate::span::{Span, Spanned};
+use crate::value::Value;
+
+// The forms the expander handles itself, leaving nothing of them to evaluate
+// but their results.
+pub const MACRO_FORMS: [&str; 4] = ["defmacro", "define-syntax", "syntax-rules", "macroexpand"];
+
+// Joins a symbol a `syntax-rules` template brings in to the expansion it came
+// from, so `tmp|3` is the `tmp` of the third. Renamed locals are joined to a
+// count the same way, as in `tmp#4`. Neither mark can be written in source,
+// so these names never clash with the program's own.
+const INTRODUCED: char = '|';
+const RENAMED: char = '#';
+
+const ELLIPSIS: &str = "...";
+
+// A form defined by the program, rewritten into other forms before it is
+// evaluated.
+#[derive(Debug)]
+pub enum Macro {
+    // From `defmacro`: a procedure that is called with the unevaluated
+    // arguments and returns the form to run in their place.
+    Procedure(Value),
+    // From `define-syntax`.
+    Rules(SyntaxRules),
+}
+
+// The rules of a `syntax-rules` macro. The template of the first one whose
+// pattern matches a use is filled in with what the pattern variables matched.
+#[derive(Debug)]
+pub struct SyntaxRules {
+    // Symbols that match only themselves in patterns.
+    literals: Vec<String>,
+    // Each pattern, without the macro's name that starts it, and its template.
+    rules: Vec<(Vec<Spanned<AST>>, Spanned<AST>)>,
+}
+
+// What a pattern variable matched: one form, or one match of the subpattern
+// before a `...` for each form it took.
+#[derive(Clone, Debug)]
+enum Binding {
+    One(Spanned<AST>),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+// Expands every macro in a top-level form, and carries out any `defmacro` and
+// `define-syntax` in it, defining the macros in `env`.
+//
+// Every local is renamed apart: those a `syntax-rules` template brings in, so
+// they cannot capture the program's variables, and the program's own, so a
+// template's references to globals cannot be captured either.
+pub fn expand(form: &Spanned<AST>, env: &Env) -> Result<Spanned<AST>, EvalError> {
+    Expander {
+        env,
+        scope: vec![],
+        count: 0,
+    }
+    .top_level(form)
+}
+
+struct Expander<'a> {
+    env: &'a Env,
+    // The locals in scope, innermost last, as written and as renamed.
+    scope: Vec<(String, String)>,
+    // Expansions and renamings so far, to number the next one.
+    count: usize,
+}
+
+impl Expander<'_> {
+    fn top_level(&mut self, form: &Spanned<AST>) -> Result<Spanned<AST>, EvalError> {
+        let form = self.expand_head(form)?;
+        let result = match self.form_keyword(&form) {
+            Some(("defmacro", args)) => self.defmacro(args),
+            Some(("define-syntax", args)) => self.define_syntax(args),
+            // The forms of a top-level `begin` are at the top level too.
+            Some(("begin", args)) => {
+                let mut items = vec![symbol("begin", form.span)];
+                for arg in args {
+                    items.push(self.top_level(arg)?);
+                }
+                Ok(Spanned::new(AST::List(items), form.span))
+            }
+            _ => return self.expand(&form),
+        };
+        result.map_err(|e| e.at(form.span))
+    }
+
+    // Expansion runs before `eval`, so it keeps to MAX_DEPTH itself.
+    fn expand(&mut self, form: &Spanned<AST>) -> Result<Spanned<AST>, EvalError> {
+        let _nested = Nested::enter().map_err(|e| e.at(form.span))?;
+        self.expand_node(form).map_err(|e| e.at(form.span))
+    }
+
+    fn expand_node(&mut self, form: &Spanned<AST>) -> Result<Spanned<AST>, EvalError> {
+        let AST::List(items) = &form.node else {
+            return Ok(match &form.node {
+                AST::Symbol(id) => symbol(&self.resolve(id), form.span),
+                node => Spanned::new(node.clone(), form.span),
+            });
+        };
+        let Some((
+            Spanned {
+                node: AST::Symbol(id),
+                ..
+            },
+            args,
+        )) = items.split_first()
+        else {
+            return self.expand_list(items, form.span);
+        };
+
+        let expanded = match self.keyword(id) {
+            Some("quote") => Some(strip(form)),
+            Some("quasiquote") => match args {
+                [template] => {
+                    let template = self.quasi(template, 1)?;
+                    Some(list([symbol("quasiquote", form.span), template], form.span))
+                }
+                _ => None,
+            },
+            Some("lambda") => self.lambda(args, form.span)?,
+            Some("define") => self.define(args, form.span)?,
+            Some("let") => self.let_form(args, form.span)?,
+            Some("defmacro") => {
+                return Err(bad_syntax("defmacro", "defmacro only at the top level"));
+            }
+            Some("define-syntax") => {
+                return Err(bad_syntax(
+                    "define-syntax",
+                    "define-syntax only at the top level",
+                ));
+            }
+            Some("macroexpand") => Some(self.macroexpand(args, form.span)?),
+            _ => match self.macro_named(id) {
+                Some(found) => {
+                    let expansion = self.apply(&found, form)?;
+                    Some(self.expand(&expansion)?)
+                }
+                None => None,
+            },
+        };
+        // Anything else, including a special form the wrong shape for `eval`
+        // to report on, is a list of expressions.
+        match expanded {
+            Some(expanded) => Ok(expanded),
+            None => self.expand_list(items, form.span),
+        }
+    }
+
+    fn expand_list(
+        &mut self,
+        items: &[Spanned<AST>],
+        span: Span,
+    ) -> Result<Spanned<AST>, EvalError> {
+        let items = items
+            .iter()
+            .map(|item| self.expand(item))
+            .collect::<Result<_, _>>()?;
+        Ok(Spanned::new(AST::List(items), span))
+    }
+
+    // Expands `form` for as long as it is a use of a macro.
+    fn expand_head<'b>(
+        &mut self,
+        form: &'b Spanned<AST>,
+    ) -> Result<Cow<'b, Spanned<AST>>, EvalError> {
+        let mut form = Cow::Borrowed(form);
+        while let Some((id, _)) = head(&form)
+            && let Some(found) = self.macro_named(id)
+        {
+            let expansion = self.apply(&found, &form).map_err(|e| e.at(form.span))?;
+            form = Cow::Owned(expansion);
+        }
+        Ok(form)
+    }
+
+    // The name `id` refers to if it is not a local variable. Special forms
+    // cannot be hidden by locals, as `eval` looks for them first.
+    fn keyword<'b>(&self, id: &'b str) -> Option<&'b str> {
+        let name = base(id);
+        let special = SPECIAL_FORMS.contains(&name) || MACRO_FORMS.contains(&name);
+        (special || self.local(id).is_none()).then_some(name)
+    }
+
+    // The keyword `form` starts with, if it starts with one.
+    fn form_keyword<'b>(&self, form: &'b Spanned<AST>) -> Option<(&'b str, &'b [Spanned<AST>])> {
+        let (id, args) = head(form)?;
+        Some((self.keyword(id)?, args))
+    }
+
+    fn macro_named(&self, id: &str) -> Option<Rc<Macro>> {
+        let name = self.keyword(id)?;
+        if SPECIAL_FORMS.contains(&name) || MACRO_FORMS.contains(&name) {
+            return None;
+        }
+        match self.env.get(name) {
+            Some(Value::Macro(found)) => Some(found),
+            _ => None,
+        }
+    }
+
+    fn local(&self, id: &str) -> Option<&str> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(from, _)| from == id)
+            .map(|(_, to)| to.as_str())
+    }
+
+    fn resolve(&self, id: &str) -> String {
+        self.local(id).unwrap_or(base(id)).to_string()
+    }
+
+    // Brings a local named `id` into scope, returning what it is renamed to.
+    // Every local is renamed, as a global it could hide may be defined after
+    // this form has been expanded.
+    fn bind(&mut self, id: &str) -> String {
+        self.count += 1;
+        let renamed = format!("{}{}{}", base(id), RENAMED, self.count);
+        self.scope.push((id.to_string(), renamed.clone()));
+        renamed
+    }
+
+    // Binds the parameters of a lambda, or returns None if they are not a
+    // symbol or a list of them.
+    fn bind_params(&mut self, params: &Spanned<AST>) -> Option<Spanned<AST>> {
+        let node = match &params.node {
+            AST::Symbol(rest) => AST::Symbol(self.bind(rest)),
+            AST::List(names) => {
+                let mut renamed = vec![];
+                for param in names {
+                    let AST::Symbol(id) = &param.node else {
+                        return None;
+                    };
+                    let name = match id.as_str() {
+                        "." => id.clone(),
+                        id => self.bind(id),
+                    };
+                    renamed.push(symbol(&name, param.span));
+                }
+                AST::List(renamed)
+            }
+            _ => return None,
+        };
+        Some(Spanned::new(node, params.span))
+    }
+
+    // Expands a body, whose definitions are locals of the scope around it.
+    fn body(&mut self, body: &[Spanned<AST>]) -> Result<Vec<Spanned<AST>>, EvalError> {
+        let body = body
+            .iter()
+            .map(|form| self.expand_head(form))
+            .collect::<Result<Vec<_>, _>>()?;
+        for form in &body {
+            self.bind_definitions(form);
+        }
+        body.iter().map(|form| self.expand(form)).collect()
+    }
+
+    fn bind_definitions(&mut self, form: &Spanned<AST>) {
+        match self.form_keyword(form) {
+            Some(("define", [name, ..])) => match &name.node {
+                AST::Symbol(id) => {
+                    self.bind(id);
+                }
+                AST::List(signature) => {
+                    if let Some(Spanned {
+                        node: AST::Symbol(id),
+                        ..
+                    }) = signature.first()
+                    {
+                        self.bind(id);
+                    }
+                }
+                _ => {}
+            },
+            Some(("begin", forms)) => {
+                for form in forms {
+                    self.bind_definitions(form);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Runs `expand` with the parameters bound by `bind` in scope, returning
+    // None if they cannot be bound.
+    fn scoped<T>(
+        &mut self,
+        bind: impl FnOnce(&mut Self) -> Option<T>,
+        expand: impl FnOnce(&mut Self, T) -> Result<Spanned<AST>, EvalError>,
+    ) -> Result<Option<Spanned<AST>>, EvalError> {
+        let outer = self.scope.len();
+        let result = match bind(self) {
+            Some(bound) => expand(self, bound).map(Some),
+            None => Ok(None),
+        };
+        self.scope.truncate(outer);
+        result
+    }
+
+    fn lambda(
+        &mut self,
+        args: &[Spanned<AST>],
+        span: Span,
+    ) -> Result<Option<Spanned<AST>>, EvalError> {
+        let [params, body @ ..] = args else {
+            return Ok(None);
+        };
+        self.scoped(
+            |this| this.bind_params(params),
+            |this, params| {
+                let mut items = vec![symbol("lambda", span), params];
+                items.extend(this.body(body)?);
+                Ok(Spanned::new(AST::List(items), span))
+            },
+        )
+    }
+
+    fn define(
+        &mut self,
+        args: &[Spanned<AST>],
+        span: Span,
+    ) -> Result<Option<Spanned<AST>>, EvalError> {
+        // Only (define (name params...) body...) has a scope of its own.
+        let [
+            Spanned {
+                node: AST::List(signature),
+                span: signature_span,
+            },
+            body @ ..,
+        ] = args
+        else {
+            return Ok(None);
+        };
+        let Some((
+            Spanned {
+                node: AST::Symbol(id),
+                span: name_span,
+            },
+            params,
+        )) = signature.split_first()
+        else {
+            return Ok(None);
+        };
+
+        let name = symbol(&self.resolve(id), *name_span);
+        let params = Spanned::new(AST::List(params.to_vec()), *signature_span);
+        self.scoped(
+            |this| this.bind_params(&params),
+            |this, params| {
+                let AST::List(params) = params.node else {
+                    unreachable!("a list of parameters stays a list");
+                };
+                let mut signature = vec![name];
+                signature.extend(params);
+                let mut items = vec![
+                    symbol("define", span),
+                    Spanned::new(AST::List(signature), *signature_span),
+                ];
+                items.extend(this.body(body)?);
+                Ok(Spanned::new(AST::List(items), span))
+            },
+        )
+    }
+
+    fn let_form(
+        &mut self,
+        args: &[Spanned<AST>],
+        span: Span,
+    ) -> Result<Option<Spanned<AST>>, EvalError> {
+        let [
+            Spanned {
+                node: AST::List(bindings),
+                span: bindings_span,
+            },
+            body @ ..,
+        ] = args
+        else {
+            return Ok(None);
+        };
+        // The values are outside the scope of the names.
+        let mut pairs = vec![];
+        for binding in bindings {
+            let AST::List(binding) = &binding.node else {
+                return Ok(None);
+            };
+            let [
+                Spanned {
+                    node: AST::Symbol(id),
+                    span,
+                },
+                value,
+            ] = binding.as_slice()
+            else {
+                return Ok(None);
+            };
+            pairs.push((id, *span, self.expand(value)?));
+        }
+
+        self.scoped(
+            |this| {
+                let bindings = pairs
+                    .into_iter()
+                    .map(|(id, name_span, value)| {
+                        let name = symbol(&this.bind(id), name_span);
+                        let span = name_span.to(value.span);
+                        list([name, value], span)
+                    })
+                    .collect();
+                Some(Spanned::new(AST::List(bindings), *bindings_span))
+            },
+            |this, bindings| {
+                let mut items = vec![symbol("let", span), bindings];
+                items.extend(this.body(body)?);
+                Ok(Spanned::new(AST::List(items), span))
+            },
+        )
+    }
+
+    // Expands the parts of a quasiquote template that are evaluated.
+    fn quasi(&mut self, template: &Spanned<AST>, depth: usize) -> Result<Spanned<AST>, EvalError> {
+        let _nested = Nested::enter().map_err(|e| e.at(template.span))?;
+        let AST::List(items) = &template.node else {
+            return Ok(strip(template));
+        };
+        if let [
+            Spanned {
+                node: AST::Symbol(id),
+                span,
+            },
+            form,
+        ] = items.as_slice()
+        {
+            let name = base(id);
+            let form = match name {
+                "unquote" | "unquote-splicing" if depth == 1 => Some(self.expand(form)?),
+                "unquote" | "unquote-splicing" => Some(self.quasi(form, depth - 1)?),
+                "quasiquote" => Some(self.quasi(form, depth + 1)?),
+                _ => None,
+            };
+            if let Some(form) = form {
+                return Ok(list([symbol(name, *span), form], template.span));
+            }
+        }
+        let items = items
+            .iter()
+            .map(|item| self.quasi(item, depth))
+            .collect::<Result<_, _>>()?;
+        Ok(Spanned::new(AST::List(items), template.span))
+    }
+
+    // (macroexpand 'form) gives the form with every macro in it expanded,
+    // as it would be at the top level.
+    fn macroexpand(
+        &mut self,
+        args: &[Spanned<AST>],
+        span: Span,
+    ) -> Result<Spanned<AST>, EvalError> {
+        let usage = "(macroexpand 'form)";
+        let [quoted] = args else {
+            return Err(bad_syntax("macroexpand", usage));
+        };
+        let form = match self.form_keyword(quoted) {
+            Some(("quote", [form])) => form,
+            _ => return Err(bad_syntax("macroexpand", usage)),
+        };
+
+        let scope = mem::take(&mut self.scope);
+        let expanded = self.expand(form);
+        self.scope = scope;
+        Ok(list([symbol("quote", span), expanded?], span))
+    }
+
+    // (defmacro name (params...) body...) defines a macro that runs the body
+    // on the unevaluated arguments of each use.
+    fn defmacro(&mut self, args: &[Spanned<AST>]) -> Result<Spanned<AST>, EvalError> {
+        let usage = "(defmacro name (params...) body...)";
+        let [
+            Spanned {
+                node: AST::Symbol(name),
+                span,
+            },
+            params,
+            body @ ..,
+        ] = args
+        else {
+            return Err(bad_syntax("defmacro", usage));
+        };
+
+        let mut lambda = vec![symbol("lambda", *span), params.clone()];
+        lambda.extend_from_slice(body);
+        let lambda = self.expand(&Spanned::new(AST::List(lambda), *span))?;
+        let procedure = eval::eval(&lambda, self.env)?;
+        self.define_macro(name, Macro::Procedure(procedure), *span)
+    }
+
+    // (define-syntax name (syntax-rules (literals...) ((_ pattern...) template)...))
+    fn define_syntax(&mut self, args: &[Spanned<AST>]) -> Result<Spanned<AST>, EvalError> {
+        let usage =
+            "(define-syntax name (syntax-rules (literals...) ((_ pattern...) template)...))";
+        let [
+            Spanned {
+                node: AST::Symbol(name),
+                span,
+            },
+            rules,
+        ] = args
+        else {
+            return Err(bad_syntax("define-syntax", usage));
+        };
+        let rules = SyntaxRules::new(rules).ok_or_else(|| bad_syntax("define-syntax", usage))?;
+        self.define_macro(name, Macro::Rules(rules), *span)
+    }
+
+    // Defines the macro now, so the forms after it can use it, and leaves
+    // `(begin)` to evaluate to nothing.
+    fn define_macro(
+        &mut self,
+        name: &str,
+        found: Macro,
+        span: Span,
+    ) -> Result<Spanned<AST>, EvalError> {
+        self.env.define(base(name), Value::Macro(Rc::new(found)));
+        Ok(list([symbol("begin", span)], span))
+    }
+
+    // Rewrites one use of a macro, leaving the result to be expanded.
+    fn apply(&mut self, found: &Macro, form: &Spanned<AST>) -> Result<Spanned<AST>, EvalError> {
+        let Some((name, args)) = head(form) else {
+            unreachable!("only lists use macros");
+        };
+        match found {
+            Macro::Procedure(procedure) => {
+                let args = args.iter().map(|arg| Value::from_ast(&arg.node)).collect();
+                let expansion = eval::apply(procedure, args)?;
+                expansion
+                    .to_ast(form.span)
+                    .ok_or_else(|| EvalError::type_error("a form", &expansion))
+            }
+            Macro::Rules(rules) => {
+                self.count += 1;
+                rules.expand(name, args, form.span, self.count)
+            }
+        }
+    }
+}
+
+impl SyntaxRules {
+    // Reads (syntax-rules (literals...) ((_ pattern...) template)...), or
+    // returns None if it is not that shape.
+    fn new(spec: &Spanned<AST>) -> Option<SyntaxRules> {
+        let AST::List(items) = &spec.node else {
+            return None;
+        };
+        let [
+            Spanned {
+                node: AST::Symbol(keyword),
+                ..
+            },
+            Spanned {
+                node: AST::List(literals),
+                ..
+            },
+            rules @ ..,
+        ] = items.as_slice()
+        else {
+            return None;
+        };
+        if base(keyword) != "syntax-rules" {
+            return None;
+        }
+
+        let literals = literals
+            .iter()
+            .map(|literal| match &literal.node {
+                AST::Symbol(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        let rules = rules
+            .iter()
+            .map(|rule| match &rule.node {
+                AST::List(rule) => match rule.as_slice() {
+                    [
+                        Spanned {
+                            node: AST::List(pattern),
+                            ..
+                        },
+                        template,
+                    ] if valid_pattern(pattern) => {
+                        let (_, pattern) = pattern.split_first()?;
+                        Some((pattern.to_vec(), template.clone()))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        Some(SyntaxRules { literals, rules })
+    }
+
+    fn expand(
+        &self,
+        name: &str,
+        args: &[Spanned<AST>],
+        span: Span,
+        mark: usize,
+    ) -> Result<Spanned<AST>, EvalError> {
+        for (pattern, template) in &self.rules {
+            let mut bindings = Bindings::new();
+            if self.match_list(pattern, args, span, &mut bindings) {
+                return fill(template, &bindings, mark, span);
+            }
+        }
+        Err(EvalError::new(EvalErrorKind::NoMatchingRule(
+            base(name).to_string(),
+        )))
+    }
+
+    fn match_form(
+        &self,
+        pattern: &Spanned<AST>,
+        form: &Spanned<AST>,
+        bindings: &mut Bindings,
+    ) -> bool {
+        match &pattern.node {
+            AST::Symbol(p) if p == "_" => true,
+            AST::Symbol(p) if self.literals.contains(p) => {
+                matches!(&form.node, AST::Symbol(s) if base(s) == base(p))
+            }
+            AST::Symbol(p) => {
+                bindings.insert(p.clone(), Binding::One(form.clone()));
+                true
+            }
+            AST::List(patterns) => match &form.node {
+                AST::List(items) => self.match_list(patterns, items, form.span, bindings),
+                _ => false,
+            },
+            node => *node == form.node,
+        }
+    }
+
+    // Matches `items`, which span `span`, against a list of patterns that may
+    // have one pattern followed by `...` and may end in `. rest`.
+    fn match_list(
+        &self,
+        patterns: &[Spanned<AST>],
+        items: &[Spanned<AST>],
+        span: Span,
+        bindings: &mut Bindings,
+    ) -> bool {
+        let (patterns, rest) = match patterns {
+            [patterns @ .., dot, rest] if is_symbol(dot, ".") => (patterns, Some(rest)),
+            patterns => (patterns, None),
+        };
+        let ellipsis = patterns.iter().position(|p| is_symbol(p, ELLIPSIS));
+        let (before, repeated, after) = match ellipsis {
+            Some(at) => (
+                &patterns[..at - 1],
+                Some(&patterns[at - 1]),
+                &patterns[at + 1..],
+            ),
+            None => (patterns, None, &[][..]),
+        };
+
+        // Without a `...` or `. rest`, the lengths must be the same.
+        let fixed = before.len() + after.len();
+        if items.len() < fixed || (repeated.is_none() && rest.is_none() && items.len() > fixed) {
+            return false;
+        }
+        let count = match repeated {
+            Some(_) => items.len() - fixed,
+            None => 0,
+        };
+        let (first, items) = items.split_at(before.len());
+        let (middle, items) = items.split_at(count);
+        let (last, items) = items.split_at(after.len());
+
+        let mut pairs = before.iter().zip(first).chain(after.iter().zip(last));
+        if !pairs.all(|(pattern, form)| self.match_form(pattern, form, bindings)) {
+            return false;
+        }
+        if let Some(repeated) = repeated {
+            let mut matches = vec![];
+            for form in middle {
+                let mut matched = Bindings::new();
+                if !self.match_form(repeated, form, &mut matched) {
+                    return false;
+                }
+                matches.push(matched);
+            }
+            for var in self.variables(repeated) {
+                let each = matches.iter_mut().filter_map(|m| m.remove(&var)).collect();
+                bindings.insert(var, Binding::Many(each));
+            }
+        }
+        match rest {
+            Some(rest) => {
+                let rest_form = Spanned::new(AST::List(items.to_vec()), span);
+                self.match_form(rest, &rest_form, bindings)
+            }
+            None => true,
+        }
+    }
+
+    // The pattern variables in `pattern`.
+    fn variables(&self, pattern: &Spanned<AST>) -> Vec<String> {
+        match &pattern.node {
+            AST::Symbol(p) if ["_", ".", ELLIPSIS].contains(&p.as_str()) => vec![],
+            AST::Symbol(p) if self.literals.contains(p) => vec![],
+            AST::Symbol(p) => vec![p.clone()],
+            AST::List(items) => items.iter().flat_map(|item| self.variables(item)).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+// A pattern is a list starting with a symbol, in which each list has at most
+// one `...`, coming after a pattern.
+fn valid_pattern(pattern: &[Spanned<AST>]) -> bool {
+    fn valid(items: &[Spanned<AST>]) -> bool {
+        let ellipses = items
+            .iter()
+            .filter(|item| is_symbol(item, ELLIPSIS))
+            .count();
+        let starts = items
+            .first()
+            .is_some_and(|first| is_symbol(first, ELLIPSIS));
+        ellipses <= 1
+            && !starts
+            && items.iter().all(|item| match &item.node {
+                AST::List(items) => valid(items),
+                _ => true,
+            })
+    }
+    matches!(
+        pattern.first(),
+        Some(Spanned {
+            node: AST::Symbol(_),
+            ..
+        })
+    ) && valid(&pattern[1..])
+}
+
+const TEMPLATE_ELLIPSIS: &str =
+    "... after each part of a template with a variable matched by ..., and only there";
+
+// Fills in `template` with what the pattern variables matched. Everything
+// else in it is marked as brought in by expansion `mark`, and put at `span`.
+fn fill(
+    template: &Spanned<AST>,
+    bindings: &Bindings,
+    mark: usize,
+    span: Span,
+) -> Result<Spanned<AST>, EvalError> {
+    match &template.node {
+        AST::Symbol(s) => match bindings.get(s) {
+            Some(Binding::One(form)) => Ok(form.clone()),
+            Some(Binding::Many(_)) => Err(bad_syntax("syntax-rules", TEMPLATE_ELLIPSIS)),
+            None if s == "." => Ok(symbol(s, span)),
+            None => Ok(symbol(&format!("{}{}{}", s, INTRODUCED, mark), span)),
+        },
+        AST::List(items) => {
+            let mut filled = vec![];
+            let mut items = items.iter().peekable();
+            while let Some(item) = items.next() {
+                if items.next_if(|next| is_symbol(next, ELLIPSIS)).is_some() {
+                    filled.extend(fill_each(item, bindings, mark, span)?);
+                } else {
+                    filled.push(fill(item, bindings, mark, span)?);
+                }
+            }
+            Ok(Spanned::new(AST::List(filled), span))
+        }
+        node => Ok(Spanned::new(node.clone(), span)),
+    }
+}
+
+// Fills in the part of a template before a `...` once for each match of the
+// variables in it that were matched by a `...`.
+fn fill_each(
+    template: &Spanned<AST>,
+    bindings: &Bindings,
+    mark: usize,
+    span: Span,
+) -> Result<Vec<Spanned<AST>>, EvalError> {
+    let repeated: Vec<(&String, &Vec<Binding>)> = symbols(template)
+        .into_iter()
+        .filter_map(|s| match bindings.get_key_value(s) {
+            Some((s, Binding::Many(each))) => Some((s, each)),
+            _ => None,
+        })
+        .collect();
+    let Some((_, first)) = repeated.first() else {
+        return Err(bad_syntax("syntax-rules", TEMPLATE_ELLIPSIS));
+    };
+    let count = first.len();
+    if repeated.iter().any(|(_, each)| each.len() != count) {
+        return Err(bad_syntax(
+            "syntax-rules",
+            "variables under the same ... to have matched as many forms",
+        ));
+    }
+
+    (0..count)
+        .map(|i| {
+            let mut bindings = bindings.clone();
+            for (s, each) in &repeated {
+                bindings.insert(s.to_string(), each[i].clone());
+            }
+            fill(template, &bindings, mark, span)
+        })
+        .collect()
+}
+
+fn symbols(template: &Spanned<AST>) -> Vec<&str> {
+    match &template.node {
+        AST::Symbol(s) => vec![s],
+        AST::List(items) => items.iter().flat_map(symbols).collect(),
+        _ => vec![],
+    }
+}
+
+// The head symbol and arguments of a form that is a list starting with one.
+fn head(form: &Spanned<AST>) -> Option<(&str, &[Spanned<AST>])> {
+    match &form.node {
+        AST::List(items) => match items.split_first() {
+            Some((
+                Spanned {
+                    node: AST::Symbol(id),
+                    ..
+                },
+                args,
+            )) => Some((id, args)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The symbol as written in the program, without the mark of the expansion
+// that brought it in.
+fn base(id: &str) -> &str {
+    id.split(INTRODUCED).next().unwrap_or(id)
+}
+
+fn is_symbol(form: &Spanned<AST>, name: &str) -> bool {
+    matches!(&form.node, AST::Symbol(s) if s == name)
+}
+
+fn symbol(name: &str, span: Span) -> Spanned<AST> {
+    Spanned::new(AST::Symbol(name.to_string()), span)
+}
+
+fn list<const N: usize>(items: [Spanned<AST>; N], span: Span) -> Spanned<AST> {
+    Spanned::new(AST::List(items.into()), span)
+}
+
+// The form with the marks taken off its symbols, for quoting.
+fn strip(form: &Spanned<AST>) -> Spanned<AST> {
+    let node = match &form.node {
+        AST::Symbol(id) => AST::Symbol(base(id).to_string()),
+        AST::List(items) => AST::List(items.iter().map(strip).collect()),
+        node => node.clone(),
+    };
+    Spanned::new(node, form.span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::global_env;
+    use crate::eval::{MAX_DEPTH, eval_program, on_large_stack};
+    use crate::parser::parse_program;
+
+    fn run(source: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        let forms = parse_program(source)?;
+        Ok(eval_program(&forms, &global_env())?)
+    }
+
+    fn run_err(source: &str) -> String {
+        let forms = parse_program(source).expect("source parses");
+        let err = eval_program(&forms, &global_env()).expect_err("source fails");
+        err.to_string()
+    }
+
+    #[test]
+    fn defmacro_rewrites_uses_before_they_run() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            (defmacro unless (test . body)
+              `(if ,test #f (begin ,@body)))
+            (define n 0)
+            (unless (> n 0) (set! n 10) (+ n 1))";
+        assert_eq!(run(source)?, Value::Integer(11));
+
+        // Macros can use procedures defined before them.
+        let source = "
+            (define (swap-args form) (list (car form) (car (cdr (cdr form))) (car (cdr form))))
+            (defmacro backwards (form) (swap-args form))
+            (backwards (- 1 10))";
+        assert_eq!(run(source)?, Value::Integer(9));
+
+        Ok(())
+    }
+
+    #[test]
+    fn syntax_rules_match_patterns_with_ellipses() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            (define-syntax my-or
+              (syntax-rules ()
+                ((_) #f)
+                ((_ e) e)
+                ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))
+            (list (my-or) (my-or #f) (my-or #f 2 (undefined)))";
+        assert_eq!(
+            run(source)?,
+            Value::list([Value::Bool(false), Value::Bool(false), Value::Integer(2)])
+        );
+
+        let source = "
+            (define-syntax my-let
+              (syntax-rules ()
+                ((_ ((name value) ...) body ...) ((lambda (name ...) body ...) value ...))))
+            (my-let ((a 1) (b 2)) (define c 3) (+ a b c))";
+        assert_eq!(run(source)?, Value::Integer(6));
+
+        let source = "
+            (define-syntax for
+              (syntax-rules (in)
+                ((_ x in items body ...) (map (lambda (x) body ...) items))))
+            (for n in (list 1 2 3) (* n n))";
+        assert_eq!(run(source)?, Value::list([1, 4, 9].map(Value::Integer)));
+        assert_eq!(
+            run_err(&format!("{} (for n on (list 1))", source)),
+            "Eval error at 5:45: no rule of for matches this form"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn syntax_rules_are_hygienic() -> Result<(), Box<dyn std::error::Error>> {
+        let swap = "
+            (define-syntax swap!
+              (syntax-rules () ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))";
+
+        // The macro's `tmp` is not the program's.
+        let source = format!(
+            "{} (define tmp 1) (define y 2) (swap! tmp y) (list tmp y)",
+            swap
+        );
+        assert_eq!(run(&source)?, Value::list([2, 1].map(Value::Integer)));
+        let source = format!("{} (let ((tmp 1) (y 2)) (swap! tmp y) (list tmp y))", swap);
+        assert_eq!(run(&source)?, Value::list([2, 1].map(Value::Integer)));
+
+        // Nor is the program's `car` the one the macro means.
+        let source = "
+            (define-syntax first (syntax-rules () ((_ l) (car l))))
+            (let ((car cdr)) (first (list 1 2)))";
+        assert_eq!(run(source)?, Value::Integer(1));
+
+        // Even when the global is defined after the macro is used.
+        let source = "
+            (define-syntax call-helper (syntax-rules () ((_ x) (helper x))))
+            (define (f helper) (call-helper 1))
+            (define (helper x) (* x 10))
+            (f list)";
+        assert_eq!(run(source)?, Value::Integer(10));
+
+        // A local of the same name hides a macro.
+        let source = format!("{} (let ((swap! list)) (swap! 1 2))", swap);
+        assert_eq!(run(&source)?, Value::list([1, 2].map(Value::Integer)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn macroexpand_shows_the_expansion() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            (defmacro unless (test . body) `(if ,test #f (begin ,@body)))
+            (define-syntax swap!
+              (syntax-rules () ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))";
+
+        assert_eq!(
+            run(&format!("{} (macroexpand '(unless (f) (g 1)))", source))?.to_string(),
+            "(if (f) #f (begin (g 1)))"
+        );
+        assert_eq!(
+            run(&format!("{} (macroexpand '(swap! x (car y)))", source))?.to_string(),
+            "(let ((tmp#2 x)) (set! x (car y)) (set! (car y) tmp#2))"
+        );
+        assert_eq!(
+            run(&format!(
+                "{} (macroexpand '(lambda (x list) (unless x list)))",
+                source
+            ))?
+            .to_string(),
+            "(lambda (x#1 list#2) (if x#1 #f (begin list#2)))"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn deep_forms_are_an_error() -> Result<(), Box<dyn std::error::Error>> {
+        let nested = |n: usize| format!("{}1{}", "(list ".repeat(n), ")".repeat(n));
+        let expand_all = |source: String| {
+            let forms = parse_program(&source).expect("source parses");
+            eval_program(&forms, &global_env()).map(|value| value.to_string())
+        };
+
+        // The bodies are never run, so only the expander sees how deep they go.
+        let results = on_large_stack(|| {
+            [
+                expand_all(format!("(lambda () {}) 1", nested(MAX_DEPTH - 10))),
+                expand_all(format!("(lambda () {})", nested(MAX_DEPTH + 1))),
+                expand_all(format!("`{}", nested(MAX_DEPTH + 1))),
+            ]
+        })?;
+
+        assert_eq!(results[0], Ok("1".to_string()));
+        for result in &results[1..] {
+            let err = result.clone().expect_err("too deep");
+            assert_eq!(err.kind, EvalErrorKind::TooDeep);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn misused_macros_are_reported() {
+        assert_eq!(
+            run_err("(define (f) (defmacro m () 1))"),
+            "Eval error at 1:13: bad defmacro form, expected defmacro only at the top level"
+        );
+        assert_eq!(
+            run_err("(defmacro m (1) 2)"),
+            "Eval error at 1:11: bad lambda form, expected (lambda (params...) body...)"
+        );
+        assert_eq!(
+            run_err("(defmacro m (x) (lambda () x)) (m 1)"),
+            "Eval error at 1:32: expected a form, got #<procedure>"
+        );
+        assert_eq!(
+            run_err("(define-syntax m (syntax-rules () ((_ ... x) x)))"),
+            "Eval error at 1:1: bad define-syntax form, expected \
+             (define-syntax name (syntax-rules (literals...) ((_ pattern...) template)...))"
+        );
+        assert_eq!(
+            run_err("(define-syntax m (syntax-rules () ((_ x ...) x))) (m 1)"),
+            "Eval error at 1:51: bad syntax-rules form, expected \
+             ... after each part of a template with a variable matched by ..., and only there"
+        );
+    }
+}
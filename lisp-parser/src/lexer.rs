@@ -0,0 +1,422 @@
+use nom::{
+    IResult, Parser,
+    branch::alt,
+    bytes::complete::{escaped_transform, is_not, tag, take_while, take_while1},
+    character::complete::{char, digit1, hex_digit1, multispace1, one_of},
+    combinator::{all_consuming, map, map_opt, opt, recognize, value},
+    error::{Error as NomError, ErrorKind},
+    multi::many0,
+    number::complete::recognize_float,
+    sequence::{delimited, pair, preceded},
+};
+
+use std::ops::Range;
+
+use crate::parser::{ParseError, ParseErrorKind};
+use crate::span::{LineIndex, Spanned};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Integer(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    Symbol(String),
+    LParen,
+    RParen,
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
+}
+
+fn parse_l_paren(input: &str) -> IResult<&str, Token> {
+    map(tag("("), |_| Token::LParen).parse(input)
+}
+
+fn parse_r_paren(input: &str) -> IResult<&str, Token> {
+    map(tag(")"), |_| Token::RParen).parse(input)
+}
+
+// `'`, `` ` ``, `,` and `,@`, which the parser reads as `quote`, `quasiquote`,
+// `unquote` and `unquote-splicing` around the form that follows.
+fn parse_quote(input: &str) -> IResult<&str, Token> {
+    alt((
+        value(Token::Quote, char('\'')),
+        value(Token::Quasiquote, char('`')),
+        value(Token::UnquoteSplicing, tag(",@")),
+        value(Token::Unquote, char(',')),
+    ))
+    .parse(input)
+}
+
+// `\n`, `\t`, `\r`, `\0`, `\\`, `\"` and `\u{...}` with a hex code point.
+fn parse_escape(input: &str) -> IResult<&str, char> {
+    alt((
+        value('\n', char('n')),
+        value('\t', char('t')),
+        value('\r', char('r')),
+        value('\0', char('0')),
+        value('\\', char('\\')),
+        value('"', char('"')),
+        map_opt(delimited(tag("u{"), hex_digit1, char('}')), |hex| {
+            u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+        }),
+    ))
+    .parse(input)
+}
+
+fn parse_string(input: &str) -> IResult<&str, Token> {
+    let contents = escaped_transform(is_not("\\\""), '\\', parse_escape);
+    map(delimited(char('"'), opt(contents), char('"')), |s| {
+        Token::Str(s.unwrap_or_default())
+    })
+    .parse(input)
+}
+
+// Characters that end an atom.
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';')
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || "!$%&*/:<=>?^_~+-.@".contains(c)
+}
+
+fn integer_literal(input: &str) -> IResult<&str, &str> {
+    all_consuming(recognize(pair(opt(one_of("+-")), digit1))).parse(input)
+}
+
+// Rust's own float parsing also takes `inf` and `nan`, which are symbols here.
+fn float_literal(input: &str) -> IResult<&str, &str> {
+    all_consuming(recognize_float).parse(input)
+}
+
+// Reads an atom as everything up to the next delimiter, then decides what it
+// is, so `-7` is a number but `-` and `->x` are symbols and `1+` is a symbol
+// rather than a number followed by a `+`.
+pub fn classify_atom(atom: &str) -> Option<Token> {
+    match atom {
+        "#t" | "#true" => Some(Token::Bool(true)),
+        "#f" | "#false" => Some(Token::Bool(false)),
+        _ if integer_literal(atom).is_ok() => atom.parse().ok().map(Token::Integer),
+        _ if float_literal(atom).is_ok() => atom.parse().ok().map(Token::Float),
+        _ if atom.chars().all(is_symbol_char) => Some(Token::Symbol(atom.to_string())),
+        _ => None,
+    }
+}
+
+fn parse_atom(input: &str) -> IResult<&str, Token> {
+    let (rest, atom) = take_while1(|c| !is_delimiter(c)).parse(input)?;
+    match classify_atom(atom) {
+        Some(token) => Ok((rest, token)),
+        None => Err(nom::Err::Error(NomError::new(input, ErrorKind::Verify))),
+    }
+}
+
+fn parse_token(input: &str) -> IResult<&str, Token> {
+    alt((
+        parse_l_paren,
+        parse_r_paren,
+        parse_quote,
+        parse_string,
+        parse_atom,
+    ))
+    .parse(input)
+}
+
+// Whitespace and `;` comments running to the end of the line.
+fn skip_blank(input: &str) -> IResult<&str, ()> {
+    let comment = preceded(char(';'), take_while(|c| c != '\n'));
+    value((), many0(alt((multispace1, comment)))).parse(input)
+}
+
+// Why the token at the start of `input` could not be read, the range of
+// `input` to point at, and how much of it to skip before carrying on.
+fn lex_error(input: &str) -> (ParseErrorKind, Range<usize>, usize) {
+    if input.starts_with('"') {
+        return string_error(input);
+    }
+
+    let len = input.find(is_delimiter).unwrap_or(input.len());
+    let atom = &input[..len];
+    if integer_literal(atom).is_ok() {
+        return (
+            ParseErrorKind::NumberOutOfRange(atom.to_string()),
+            0..len,
+            len,
+        );
+    }
+    // Anything else made only of symbol characters reads as a symbol.
+    let first = (0, input.chars().next().unwrap_or_default());
+    let (at, c) = atom
+        .char_indices()
+        .find(|&(_, c)| !is_symbol_char(c))
+        .unwrap_or(first);
+    (
+        ParseErrorKind::UnexpectedChar(c),
+        at..at + c.len_utf8(),
+        len,
+    )
+}
+
+// A string that failed to read either has a bad escape in it or is never
+// closed. A bad escape is reported and the whole string skipped.
+fn string_error(input: &str) -> (ParseErrorKind, Range<usize>, usize) {
+    let mut bad_escape = None;
+    let mut pos = 1;
+
+    while let Some(c) = input[pos..].chars().next() {
+        match c {
+            '"' => {
+                let range = bad_escape.unwrap_or(0..pos + 1);
+                let escape = input[range.clone()].to_string();
+                return (ParseErrorKind::InvalidEscape(escape), range, pos + 1);
+            }
+            '\\' => {
+                let escaped = &input[pos + 1..];
+                let len = match parse_escape(escaped) {
+                    Ok((after, _)) => escaped.len() - after.len(),
+                    Err(_) => {
+                        let len = escaped.chars().next().map_or(0, char::len_utf8);
+                        bad_escape.get_or_insert(pos..pos + 1 + len);
+                        len
+                    }
+                };
+                pos += 1 + len;
+            }
+            c => pos += c.len_utf8(),
+        }
+    }
+
+    (ParseErrorKind::UnterminatedString, 0..1, input.len())
+}
+
+// Reads every token in `input`. Anything that is not a token is reported and
+// skipped, so one pass finds all of them.
+pub fn tokenize(input: &str) -> (Vec<Spanned<Token>>, Vec<ParseError>) {
+    let index = LineIndex::new(input);
+    let offset = |rest: &str| input.len() - rest.len();
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    let mut rest = input;
+
+    loop {
+        if let Ok((after, _)) = skip_blank(rest) {
+            rest = after;
+        }
+        if rest.is_empty() {
+            return (tokens, errors);
+        }
+
+        let start = offset(rest);
+        match parse_token(rest) {
+            Ok((after, token)) => {
+                tokens.push(Spanned::new(token, index.span(start, offset(after))));
+                rest = after;
+            }
+            Err(_) => {
+                let (kind, range, skip) = lex_error(rest);
+                let span = index.span(start + range.start, start + range.end);
+                errors.push(ParseError::new(kind, span));
+                // Always make progress, even on something unforeseen.
+                let skip = skip.max(rest.chars().next().map_or(1, char::len_utf8));
+                rest = &rest[skip..];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(tokens: Vec<Spanned<Token>>) -> Vec<Token> {
+        tokens.into_iter().map(|t| t.node).collect()
+    }
+
+    #[test]
+    fn tokenize_complex() {
+        let expected: Vec<Token> = vec![
+            Token::LParen,
+            Token::Symbol("first".to_string()),
+            Token::LParen,
+            Token::Symbol("list".to_string()),
+            Token::Integer(1),
+            Token::LParen,
+            Token::Symbol("+".to_string()),
+            Token::Integer(2),
+            Token::Integer(3),
+            Token::RParen,
+            Token::Integer(9),
+            Token::RParen,
+            Token::RParen,
+        ];
+
+        let (result, errors) = tokenize("(first (list 1 (+ 2 3) 9))");
+        assert_eq!(errors, vec![]);
+
+        assert_eq!(kinds(result), expected);
+    }
+
+    #[test]
+    fn tokenize_simple() {
+        let expected: Vec<Token> = vec![Token::LParen, Token::Integer(1), Token::RParen];
+
+        let (result, errors) = tokenize("(1)");
+        assert_eq!(errors, vec![]);
+
+        assert_eq!(kinds(result), expected);
+    }
+
+    #[test]
+    fn tokenize_atoms() {
+        let expected: Vec<Token> = vec![
+            Token::LParen,
+            Token::Symbol("-".to_string()),
+            Token::Symbol("*".to_string()),
+            Token::Symbol("<=".to_string()),
+            Token::Symbol("list->vector".to_string()),
+            Token::Symbol("1+".to_string()),
+            Token::Integer(-7),
+            Token::Integer(7),
+            Token::Float(2.75),
+            Token::Float(-0.5),
+            Token::Float(1e3),
+            Token::Bool(true),
+            Token::Bool(false),
+            Token::Symbol("nan".to_string()),
+            Token::RParen,
+        ];
+
+        let (result, errors) =
+            tokenize("(- * <= list->vector 1+ -7 +7 2.75 -.5 1e3 #t #false nan)");
+
+        assert_eq!(kinds(result), expected);
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn tokenize_quote_marks() {
+        let expected: Vec<Token> = vec![
+            Token::Quote,
+            Token::Symbol("a".to_string()),
+            Token::Quasiquote,
+            Token::LParen,
+            Token::Unquote,
+            Token::Symbol("b".to_string()),
+            Token::UnquoteSplicing,
+            Token::Symbol("c".to_string()),
+            Token::RParen,
+        ];
+
+        let (result, errors) = tokenize("'a `(,b ,@c)");
+
+        assert_eq!(kinds(result), expected);
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn tokenize_strings() {
+        let expected: Vec<Token> = vec![
+            Token::Str("".to_string()),
+            Token::Str("say \"hi\"\n\tA\\".to_string()),
+            Token::Str("(not a list) ; nor a comment".to_string()),
+        ];
+
+        let input = r#""" "say \"hi\"\n\t\u{41}\\" "(not a list) ; nor a comment""#;
+        let (result, errors) = tokenize(input);
+
+        assert_eq!(kinds(result), expected);
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn tokenize_skips_comments() {
+        let expected: Vec<Token> = vec![Token::LParen, Token::Integer(1), Token::RParen];
+
+        let (result, errors) = tokenize("; a comment\n(1 ; another\n)");
+        assert_eq!(errors, vec![]);
+
+        assert_eq!(kinds(result), expected);
+    }
+
+    #[test]
+    fn tokens_carry_spans() {
+        let (result, errors) = tokenize("(+ 1\n   \"two\")");
+        assert_eq!(errors, vec![]);
+
+        let spans: Vec<(usize, usize, String)> = result
+            .iter()
+            .map(|t| (t.span.start, t.span.end, t.span.to_string()))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 1, "1:1".to_string()),
+                (1, 2, "1:2".to_string()),
+                (3, 4, "1:4".to_string()),
+                (8, 13, "2:4".to_string()),
+                (13, 14, "2:9".to_string()),
+            ]
+        );
+    }
+
+    // The kind and position of each error.
+    fn errors(input: &str) -> Vec<(ParseErrorKind, String)> {
+        let (_, errors) = tokenize(input);
+        errors
+            .into_iter()
+            .map(|e| (e.kind, e.span.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_reports_and_skips_bad_atoms() {
+        let (result, _) = tokenize("(a b|c [d] e)");
+
+        let expected: Vec<Token> = vec![
+            Token::LParen,
+            Token::Symbol("a".to_string()),
+            Token::Symbol("e".to_string()),
+            Token::RParen,
+        ];
+        assert_eq!(kinds(result), expected);
+        assert_eq!(
+            errors("(a b|c [d] e)"),
+            vec![
+                (ParseErrorKind::UnexpectedChar('|'), "1:5".to_string()),
+                (ParseErrorKind::UnexpectedChar('['), "1:8".to_string()),
+            ]
+        );
+        assert_eq!(
+            errors("#x 99999999999999999999"),
+            vec![
+                (ParseErrorKind::UnexpectedChar('#'), "1:1".to_string()),
+                (
+                    ParseErrorKind::NumberOutOfRange("99999999999999999999".to_string()),
+                    "1:4".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_reports_bad_strings() {
+        let (result, _) = tokenize(r#"("a\qb\z" "ok")"#);
+
+        let expected: Vec<Token> = vec![Token::LParen, Token::Str("ok".to_string()), Token::RParen];
+        assert_eq!(kinds(result), expected);
+        assert_eq!(
+            errors(r#"("a\qb\z" "ok")"#),
+            vec![(
+                ParseErrorKind::InvalidEscape(r"\q".to_string()),
+                "1:4".to_string()
+            )]
+        );
+        assert_eq!(
+            errors("(print \"oops)\n"),
+            vec![(ParseErrorKind::UnterminatedString, "1:8".to_string())]
+        );
+    }
+}
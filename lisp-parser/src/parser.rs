@@ -0,0 +1,486 @@
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Token, tokenize};
+use crate::span::{LineIndex, Span, Spanned};
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    NumberOutOfRange(String),
+    UnterminatedString,
+    InvalidEscape(String),
+    // Input ran out with the list opened at this span still open.
+    UnclosedParen(Span),
+    UnexpectedCloseParen,
+    // A quote mark, such as `'` or `,@`, with no form after it.
+    NothingQuoted(&'static str),
+}
+
+impl ParseErrorKind {
+    fn hint(&self) -> Option<String> {
+        match self {
+            ParseErrorKind::InvalidEscape(_) => {
+                Some(r#"the escapes are \n \t \r \0 \\ \" and \u{...}"#.to_string())
+            }
+            ParseErrorKind::UnclosedParen(open) => Some(format!("unclosed '(' opened at {}", open)),
+            ParseErrorKind::UnexpectedCloseParen => {
+                Some("there is no '(' for it to close".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            ParseErrorKind::NumberOutOfRange(n) => write!(f, "number {} is out of range", n),
+            ParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            ParseErrorKind::InvalidEscape(e) => write!(f, "invalid escape {}", e),
+            ParseErrorKind::UnclosedParen(_) => write!(f, "unexpected end of input"),
+            ParseErrorKind::UnexpectedCloseParen => write!(f, "unexpected ')'"),
+            ParseErrorKind::NothingQuoted(mark) => write!(f, "nothing to quote after {}", mark),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> Self {
+        ParseError { kind, span }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            message: self.kind.to_string(),
+            span: self.span,
+            hint: self.kind.hint(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Parse error at {}: {}", self.span, self.kind)
+    }
+}
+
+impl Error for ParseError {}
+
+// Every error found in one read of the input, in source order.
+#[derive(Debug)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl ParseErrors {
+    pub fn render(&self, source: &str, origin: &str) -> String {
+        self.0
+            .iter()
+            .map(|e| e.diagnostic().render(source, origin))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let errors: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        write!(f, "{}", errors.join("\n"))
+    }
+}
+
+impl Error for ParseErrors {}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AST {
+    Integer(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    Symbol(String),
+    // Made from `lambda` forms by the evaluator rather than the reader. A
+    // parameter list ending in ".", name binds the remaining arguments to name.
+    // The body is shared with every procedure made from it.
+    Lambda(Vec<String>, Rc<[Spanned<AST>]>),
+    List(Vec<Spanned<AST>>),
+}
+
+// Like `parse_program`, with every span marked as read from `source`, so
+// errors can be shown against the right one of several.
+pub fn parse_source(input: &str, source: usize) -> Result<Vec<Spanned<AST>>, ParseErrors> {
+    fn mark(form: &mut Spanned<AST>, source: usize) {
+        form.span.source = source;
+        if let AST::List(items) = &mut form.node {
+            items.iter_mut().for_each(|item| mark(item, source));
+        }
+    }
+
+    let mut forms = parse_program(input)?;
+    forms.iter_mut().for_each(|form| mark(form, source));
+    Ok(forms)
+}
+
+// Reads every top-level form in `input`, e.g. a file of definitions. Mistakes
+// are recovered from where possible so that all of them are reported together.
+pub fn parse_program(input: &str) -> Result<Vec<Spanned<AST>>, ParseErrors> {
+    let (tokens, mut errors) = tokenize(input);
+
+    // Running out of input is reported just after the last thing read.
+    let end = input.trim_end().len();
+    let mut parser = Parser {
+        tokens: tokens.into_iter().rev().collect(),
+        errors: vec![],
+        eof: LineIndex::new(input).span(end, end),
+    };
+    let forms = parser.parse_forms();
+    errors.append(&mut parser.errors);
+
+    if errors.is_empty() {
+        Ok(forms)
+    } else {
+        errors.sort_by_key(|e| e.span.start);
+        Err(ParseErrors(errors))
+    }
+}
+
+struct Parser {
+    // In reverse, so the next token is popped off the end.
+    tokens: Vec<Spanned<Token>>,
+    errors: Vec<ParseError>,
+    eof: Span,
+}
+
+impl Parser {
+    // Reads forms until the tokens run out. A `)` with nothing open is
+    // reported and skipped.
+    fn parse_forms(&mut self) -> Vec<Spanned<AST>> {
+        let mut forms = vec![];
+        while let Some(token) = self.tokens.pop() {
+            match token.node {
+                Token::RParen => self.errors.push(ParseError::new(
+                    ParseErrorKind::UnexpectedCloseParen,
+                    token.span,
+                )),
+                node => forms.push(self.parse_form(Spanned::new(node, token.span))),
+            }
+        }
+        forms
+    }
+
+    fn parse_form(&mut self, token: Spanned<Token>) -> Spanned<AST> {
+        let ast = match token.node {
+            Token::Symbol(s) => AST::Symbol(s),
+            Token::Integer(i) => AST::Integer(i),
+            Token::Float(f) => AST::Float(f),
+            Token::Str(s) => AST::Str(s),
+            Token::Bool(b) => AST::Bool(b),
+            Token::LParen => return self.parse_list(token.span),
+            Token::RParen => unreachable!("callers handle ')'"),
+            Token::Quote => return self.parse_quoted("'", "quote", token.span),
+            Token::Quasiquote => return self.parse_quoted("`", "quasiquote", token.span),
+            Token::Unquote => return self.parse_quoted(",", "unquote", token.span),
+            Token::UnquoteSplicing => {
+                return self.parse_quoted(",@", "unquote-splicing", token.span);
+            }
+        };
+        Spanned::new(ast, token.span)
+    }
+
+    // Reads the form after the quote mark at `span` as (name form).
+    fn parse_quoted(&mut self, mark: &'static str, name: &str, span: Span) -> Spanned<AST> {
+        let name = Spanned::new(AST::Symbol(name.to_string()), span);
+        match self.tokens.pop() {
+            Some(token) if token.node != Token::RParen => {
+                let form = self.parse_form(token);
+                let span = span.to(form.span);
+                Spanned::new(AST::List(vec![name, form]), span)
+            }
+            token => {
+                // A `)` is left for the list it closes.
+                self.tokens.extend(token);
+                self.errors
+                    .push(ParseError::new(ParseErrorKind::NothingQuoted(mark), span));
+                Spanned::new(AST::List(vec![name]), span)
+            }
+        }
+    }
+
+    // Reads the rest of a list opened at `open`. A list left open at the end
+    // of the input is reported and closed there.
+    fn parse_list(&mut self, open: Span) -> Spanned<AST> {
+        let mut items = vec![];
+        loop {
+            match self.tokens.pop() {
+                Some(Spanned {
+                    node: Token::RParen,
+                    span,
+                }) => return Spanned::new(AST::List(items), open.to(span)),
+                Some(token) => items.push(self.parse_form(token)),
+                None => {
+                    self.errors.push(ParseError::new(
+                        ParseErrorKind::UnclosedParen(open),
+                        self.eof,
+                    ));
+                    return Spanned::new(AST::List(items), open.to(self.eof));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: Vec<AST>) -> AST {
+        AST::List(items.into_iter().map(Spanned::from).collect())
+    }
+
+    #[test]
+    fn parse_nested() -> Result<(), Box<dyn std::error::Error>> {
+        let expected: AST = list(vec![
+            AST::Symbol("first".to_string()),
+            list(vec![
+                AST::Symbol("list".to_string()),
+                AST::Integer(1),
+                list(vec![
+                    AST::Symbol("+".to_string()),
+                    AST::Integer(2),
+                    AST::Integer(3),
+                ]),
+                AST::Integer(9),
+            ]),
+        ]);
+
+        let input = "(first (list 1 (+ 2 3) 9))";
+
+        let result = parse_program(input)?;
+
+        assert_eq!(result, vec![expected.into()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_simple_add() -> Result<(), Box<dyn std::error::Error>> {
+        let expected: AST = list(vec![
+            AST::Symbol("+".to_string()),
+            AST::Integer(1),
+            AST::Integer(2),
+        ]);
+
+        let input = "(+ 1 2)";
+
+        let result = parse_program(input)?;
+
+        assert_eq!(result, vec![expected.into()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_simple() -> Result<(), Box<dyn std::error::Error>> {
+        let expected: AST = list(vec![]);
+
+        let input = "()";
+
+        let result = parse_program(input)?;
+
+        assert_eq!(result, vec![expected.into()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_atoms() -> Result<(), Box<dyn std::error::Error>> {
+        let expected: AST = list(vec![
+            AST::Symbol("if".to_string()),
+            AST::Bool(true),
+            AST::Str("yes".to_string()),
+            AST::Float(2.5),
+        ]);
+
+        let result = parse_program("(if #t \"yes\" 2.5)")?;
+
+        assert_eq!(result, vec![expected.into()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn nodes_carry_spans() -> Result<(), Box<dyn std::error::Error>> {
+        let result = &parse_program("(a\n  (b 12))")?[0];
+
+        assert_eq!((result.span.start, result.span.end), (0, 12));
+        let AST::List(items) = &result.node else {
+            return Err("expected a list".into());
+        };
+        assert_eq!(items[1].span.to_string(), "2:3");
+        assert_eq!((items[1].span.start, items[1].span.end), (5, 11));
+        let AST::List(inner) = &items[1].node else {
+            return Err("expected a list".into());
+        };
+        assert_eq!(inner[1].span.to_string(), "2:6");
+        assert_eq!(inner[1].span.source, 0);
+
+        let result = &parse_source("(a\n  (b 12))", 3)?[0];
+        let AST::List(items) = &result.node else {
+            return Err("expected a list".into());
+        };
+        let AST::List(inner) = &items[1].node else {
+            return Err("expected a list".into());
+        };
+        assert_eq!((result.span.source, inner[1].span.source), (3, 3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unclosed_list_points_at_its_paren() {
+        let input = "(define (f x)\n  (+ x 1)\n";
+
+        let err = parse_program(input).expect_err("input is unclosed");
+
+        let expected = "\
+error: unexpected end of input
+ --> f.lisp:2:10
+  |
+2 |   (+ x 1)
+  |          ^
+  = hint: unclosed '(' opened at 1:1";
+        assert_eq!(err.render(input, "f.lisp"), expected);
+    }
+
+    // The message and position of each error.
+    fn errors(input: &str) -> Vec<(String, String)> {
+        let err = parse_program(input).expect_err("input is invalid");
+        err.0
+            .iter()
+            .map(|e| (e.kind.to_string(), e.span.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn extra_close_parens_are_rejected() {
+        assert_eq!(
+            errors("(a (b)))"),
+            vec![("unexpected ')'".to_string(), "1:8".to_string())]
+        );
+        assert_eq!(
+            errors(")(a)"),
+            vec![("unexpected ')'".to_string(), "1:1".to_string())]
+        );
+    }
+
+    #[test]
+    fn quote_marks_wrap_the_next_form() -> Result<(), Box<dyn std::error::Error>> {
+        let symbol = |s: &str| AST::Symbol(s.to_string());
+        let expected = list(vec![
+            symbol("quasiquote"),
+            list(vec![
+                list(vec![symbol("quote"), symbol("a")]),
+                list(vec![symbol("unquote"), symbol("b")]),
+                list(vec![
+                    symbol("unquote-splicing"),
+                    list(vec![symbol("f"), AST::Integer(1)]),
+                ]),
+            ]),
+        ]);
+
+        let result = parse_program("`('a ,b ,@(f 1))")?;
+
+        assert_eq!(result, vec![expected.into()]);
+        assert_eq!((result[0].span.start, result[0].span.end), (0, 16));
+        assert_eq!(
+            errors("(a ') '"),
+            vec![
+                ("nothing to quote after '".to_string(), "1:4".to_string()),
+                ("nothing to quote after '".to_string(), "1:7".to_string()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_program_reads_every_form() -> Result<(), Box<dyn std::error::Error>> {
+        let input = "(define x 1)\n(define (f) x) ; done\n42 f ()";
+
+        let result = parse_program(input)?;
+
+        let expected: Vec<Spanned<AST>> = vec![
+            list(vec![
+                AST::Symbol("define".to_string()),
+                AST::Symbol("x".to_string()),
+                AST::Integer(1),
+            ])
+            .into(),
+            list(vec![
+                AST::Symbol("define".to_string()),
+                list(vec![AST::Symbol("f".to_string())]),
+                AST::Symbol("x".to_string()),
+            ])
+            .into(),
+            AST::Integer(42).into(),
+            AST::Symbol("f".to_string()).into(),
+            list(vec![]).into(),
+        ];
+        assert_eq!(result, expected);
+        assert_eq!(result[4].span.to_string(), "3:6");
+        assert_eq!((result[4].span.start, result[4].span.end), (40, 42));
+        assert_eq!(parse_program(" ; nothing\n")?, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_every_error_in_one_pass() {
+        let input = "(define (f x)\n  (g x|y \"\\q\"))\n  (h x)))\n(";
+
+        let err = parse_program(input).expect_err("input is invalid");
+
+        let expected = "\
+error: unexpected character '|'
+ --> f.lisp:2:7
+  |
+2 |   (g x|y \"\\q\"))
+  |       ^
+
+error: invalid escape \\q
+ --> f.lisp:2:11
+  |
+2 |   (g x|y \"\\q\"))
+  |           ^^
+  = hint: the escapes are \\n \\t \\r \\0 \\\\ \\\" and \\u{...}
+
+error: unexpected ')'
+ --> f.lisp:3:8
+  |
+3 |   (h x)))
+  |        ^
+  = hint: there is no '(' for it to close
+
+error: unexpected ')'
+ --> f.lisp:3:9
+  |
+3 |   (h x)))
+  |         ^
+  = hint: there is no '(' for it to close
+
+error: unexpected end of input
+ --> f.lisp:4:2
+  |
+4 | (
+  |  ^
+  = hint: unclosed '(' opened at 4:1";
+        assert_eq!(err.render(input, "f.lisp"), expected);
+    }
+}
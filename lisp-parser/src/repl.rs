@@ -0,0 +1,399 @@
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::builtins::global_env;
+use crate::env::Env;
+use crate::eval::{EvalError, EvalErrorKind, SPECIAL_FORMS, eval_program};
+use crate::expand::MACRO_FORMS;
+use crate::parser::{ParseErrorKind, parse_program, parse_source};
+use crate::value::Value;
+
+const HISTORY_FILE: &str = ".lisp_history";
+const PROMPT: &str = "lisp> ";
+// Results wider than this are broken over several lines.
+const WIDTH: usize = 80;
+
+const META_COMMANDS: [&str; 5] = [":load", ":env", ":time", ":help", ":quit"];
+
+const HELP: &str = "\
+:load <file>   run a file in this session
+:env           list what has been defined
+:time <expr>   evaluate an expression and report how long it took
+:help          show this list
+:quit          leave (as does Ctrl-D)";
+
+enum Command<'a> {
+    Eval(&'a str),
+    Load(&'a str),
+    Env,
+    Time(&'a str),
+    Help,
+    Quit,
+    Unknown(&'a str),
+}
+
+fn parse_command(line: &str) -> Command<'_> {
+    let line = line.trim();
+    if !line.starts_with(':') {
+        return Command::Eval(line);
+    }
+    let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let arg = arg.trim();
+    match command {
+        ":load" => Command::Load(arg),
+        ":env" => Command::Env,
+        ":time" => Command::Time(arg),
+        ":help" => Command::Help,
+        ":quit" | ":q" => Command::Quit,
+        _ => Command::Unknown(command),
+    }
+}
+
+enum Reply {
+    Print(String),
+    Fail(String),
+    // Leave with this exit status.
+    Quit(i32),
+}
+
+// What a session has built up: its definitions, and every source it has
+// read. A procedure can fail long after the source it came from was read, so
+// errors are shown against the source their span is marked with.
+struct Session {
+    env: Env,
+    // The origin and text of each source, numbered from 1.
+    sources: Vec<(String, String)>,
+}
+
+impl Session {
+    fn new(env: Env) -> Self {
+        Session {
+            env,
+            sources: vec![],
+        }
+    }
+
+    // Parses and runs `source`, rendering any error against the source it
+    // points into. A call to `exit` ends the session.
+    fn run_source(&mut self, source: &str, origin: &str) -> Result<Value, Reply> {
+        let number = self.sources.len() + 1;
+        let forms =
+            parse_source(source, number).map_err(|e| Reply::Fail(e.render(source, origin)))?;
+        self.sources.push((origin.to_string(), source.to_string()));
+
+        eval_program(&forms, &self.env).map_err(|e| match e.kind {
+            EvalErrorKind::Exit(status) => Reply::Quit(status),
+            _ => Reply::Fail(self.render(&e)),
+        })
+    }
+
+    fn render(&self, error: &EvalError) -> String {
+        let source = error
+            .span
+            .and_then(|span| self.sources.get(span.source.checked_sub(1)?));
+        match source {
+            Some((origin, text)) => error.diagnostic().render(text, origin),
+            None => error.diagnostic().render("", "<repl>"),
+        }
+    }
+}
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::Void => String::new(),
+        value => pretty(value, 0, WIDTH),
+    }
+}
+
+fn respond(line: &str, session: &mut Session) -> Reply {
+    match parse_command(line) {
+        Command::Eval(source) => match session.run_source(source, "<repl>") {
+            Ok(value) => Reply::Print(show(&value)),
+            Err(reply) => reply,
+        },
+        Command::Load("") => Reply::Fail("usage: :load <file>".to_string()),
+        Command::Load(path) => match fs::read_to_string(path) {
+            Ok(source) => match session.run_source(&source, path) {
+                Ok(_) => Reply::Print(format!("loaded {}", path)),
+                Err(reply) => reply,
+            },
+            Err(e) => Reply::Fail(format!("could not read {}: {}", path, e)),
+        },
+        Command::Env => {
+            let bindings: Vec<String> = session
+                .env
+                .bindings()
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect();
+            Reply::Print(bindings.join("\n"))
+        }
+        Command::Time(source) => {
+            let started = Instant::now();
+            let result = session.run_source(source, "<repl>");
+            let elapsed = started.elapsed();
+            match result {
+                Ok(value) => {
+                    let value = show(&value);
+                    let took = format!("; took {:?}", elapsed);
+                    match value.is_empty() {
+                        true => Reply::Print(took),
+                        false => Reply::Print(format!("{}\n{}", value, took)),
+                    }
+                }
+                Err(reply) => reply,
+            }
+        }
+        Command::Help => Reply::Print(HELP.to_string()),
+        Command::Quit => Reply::Quit(0),
+        Command::Unknown(command) => Reply::Fail(format!("unknown command {}, try :help", command)),
+    }
+}
+
+// Prints `value` on one line if it fits in `width` columns from `indent`,
+// and otherwise puts each element of a list on its own line, lined up under
+// the first.
+fn pretty(value: &Value, indent: usize, width: usize) -> String {
+    let flat = value.to_string();
+    if indent + flat.chars().count() <= width {
+        return flat;
+    }
+    let Some(items) = value.to_vec() else {
+        return flat;
+    };
+
+    let separator = format!("\n{}", " ".repeat(indent + 1));
+    let items: Vec<String> = items
+        .iter()
+        .map(|item| pretty(item, indent + 1, width))
+        .collect();
+    format!("({})", items.join(&separator))
+}
+
+// Whether `input` is the start of something longer, i.e. it would read but
+// for an unclosed list or string.
+fn is_incomplete(input: &str) -> bool {
+    let source = match parse_command(input) {
+        Command::Eval(source) | Command::Time(source) => source,
+        _ => return false,
+    };
+    match parse_program(source) {
+        Ok(_) => false,
+        Err(errors) => errors.0.iter().all(|e| {
+            matches!(
+                e.kind,
+                ParseErrorKind::UnclosedParen(_) | ParseErrorKind::UnterminatedString
+            )
+        }),
+    }
+}
+
+struct ReplHelper {
+    env: Env,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line
+            .rfind(|c: char| c.is_whitespace() || "()\"".contains(c))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..];
+        if word.is_empty() {
+            return Ok((start, vec![]));
+        }
+
+        let mut names: Vec<String> = if start == 0 && word.starts_with(':') {
+            META_COMMANDS.iter().map(|c| c.to_string()).collect()
+        } else {
+            let forms = SPECIAL_FORMS
+                .iter()
+                .chain(&MACRO_FORMS)
+                .map(|f| f.to_string());
+            self.env.names().into_iter().chain(forms).collect()
+        };
+        names.retain(|name| name.starts_with(word));
+        names.sort();
+        names.dedup();
+        Ok((start, names))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+// Enter inserts a line break rather than submitting while a list or string
+// is still open.
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        match is_incomplete(ctx.input()) {
+            true => Ok(ValidationResult::Incomplete),
+            false => Ok(ValidationResult::Valid(None)),
+        }
+    }
+}
+
+impl Helper for ReplHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+// Runs the REPL until it is left, returning the status to exit with.
+pub fn run() -> rustyline::Result<i32> {
+    // Definitions go in a scope of their own, so :env lists only them.
+    let mut session = Session::new(global_env().child());
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper {
+        env: session.env.clone(),
+    }));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means this is the first session.
+        let _ = editor.load_history(path);
+    }
+
+    let mut status = 0;
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+
+        match respond(&line, &mut session) {
+            Reply::Print(output) if output.is_empty() => {}
+            Reply::Print(output) => println!("{}", output),
+            Reply::Fail(error) => eprintln!("{}", error),
+            Reply::Quit(code) => {
+                status = code;
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(line: &str, session: &mut Session) -> Result<String, String> {
+        match respond(line, session) {
+            Reply::Print(output) => Ok(output),
+            Reply::Fail(error) => Err(error),
+            Reply::Quit(status) => Ok(format!(":quit {}", status)),
+        }
+    }
+
+    #[test]
+    fn keeps_reading_while_input_is_open() {
+        assert!(is_incomplete("(define (f x)"));
+        assert!(is_incomplete("(display \"a\nb"));
+        assert!(is_incomplete(":time (f"));
+        assert!(!is_incomplete("(f x)"));
+        assert!(!is_incomplete("(f x))"));
+        assert!(!is_incomplete("(f |"));
+        assert!(!is_incomplete(":load (f"));
+    }
+
+    #[test]
+    fn long_results_are_broken_over_lines() {
+        let value = Value::list([
+            Value::symbol("define"),
+            Value::list([Value::symbol("f"), Value::symbol("x")]),
+            Value::list((1..=12).map(Value::Integer)),
+        ]);
+
+        assert_eq!(pretty(&value, 0, 80), value.to_string());
+        assert_eq!(
+            pretty(&value, 0, 20),
+            "(define\n (f x)\n (1\n  2\n  3\n  4\n  5\n  6\n  7\n  8\n  9\n  10\n  11\n  12))"
+        );
+    }
+
+    #[test]
+    fn meta_commands() -> Result<(), Box<dyn std::error::Error>> {
+        let session = &mut Session::new(global_env().child());
+        let path = std::env::temp_dir().join(format!("repl-test-{}.lisp", std::process::id()));
+        fs::write(&path, "(define x 2)\n(define (double n) (* n x))")?;
+        let path = path.to_str().ok_or("temp path is not utf-8")?;
+
+        assert_eq!(
+            reply(&format!(":load {}", path), session)?,
+            format!("loaded {}", path)
+        );
+        assert_eq!(reply("(double 21)", session)?, "42");
+        assert_eq!(reply("(define y 1)", session)?, "");
+        assert_eq!(
+            reply(":env", session)?,
+            "double = #<procedure>\nx = 2\ny = 1"
+        );
+        assert!(reply(":time (double 1)", session)?.starts_with("2\n; took "));
+        assert_eq!(reply(":q", session)?, ":quit 0");
+        assert_eq!(reply("(exit 3)", session)?, ":quit 3");
+        assert_eq!(
+            reply(":nope", session),
+            Err("unknown command :nope, try :help".to_string())
+        );
+        assert_eq!(
+            reply("(+ 1 z)", session),
+            Err(
+                "error: unbound variable z\n --> <repl>:1:6\n  |\n1 | (+ 1 z)\n  |      ^"
+                    .to_string()
+            )
+        );
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    // The error is in the loaded file, not the line that called into it.
+    #[test]
+    fn errors_show_the_source_they_happened_in() -> Result<(), Box<dyn std::error::Error>> {
+        let session = &mut Session::new(global_env().child());
+        let path = std::env::temp_dir().join(format!("repl-source-{}.lisp", std::process::id()));
+        fs::write(&path, "; helpers\n(define (f x)\n  (+ x y))")?;
+        let path = path.to_str().ok_or("temp path is not utf-8")?;
+
+        reply(&format!(":load {}", path), session)?;
+        assert_eq!(
+            reply("(f 1)", session),
+            Err(format!(
+                "error: unbound variable y\n --> {}:3:8\n  |\n3 |   (+ x y))\n  |        ^",
+                path
+            ))
+        );
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
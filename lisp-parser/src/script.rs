@@ -0,0 +1,46 @@
+use std::fs;
+
+use crate::builtins::{global_env, set_command_line};
+use crate::eval::{EvalError, EvalErrorKind, eval_program};
+use crate::parser::parse_program;
+use crate::value::Value;
+
+// Runs a whole program, printing any error as a diagnostic. Returns the value
+// of its last form, or the status to exit with if it stopped before the end.
+pub fn run(source: &str, origin: &str) -> Result<Value, i32> {
+    let forms = parse_program(source).map_err(|e| {
+        eprintln!("{}", e.render(source, origin));
+        1
+    })?;
+
+    eval_program(&forms, &global_env()).map_err(|e| match e {
+        EvalError {
+            kind: EvalErrorKind::Exit(status),
+            ..
+        } => status,
+        e => {
+            eprintln!("{}", e.diagnostic().render(source, origin));
+            1
+        }
+    })
+}
+
+// Runs the file at `path`, which sees itself and `args` through
+// `command-line`, and returns the status to exit with.
+pub fn run_file(path: &str, args: &[String]) -> i32 {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("could not read {}: {}", path, e);
+            return 1;
+        }
+    };
+
+    let mut command_line = vec![path.to_string()];
+    command_line.extend_from_slice(args);
+    set_command_line(command_line);
+    match run(&source, path) {
+        Ok(_) => 0,
+        Err(status) => status,
+    }
+}
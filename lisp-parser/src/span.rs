@@ -0,0 +1,115 @@
+use std::fmt;
+
+// Where something was read from: the byte range in the source, and the line
+// and column it starts at, both counted from 1. Columns count characters.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    // Which source, when there are several to tell apart, e.g. everything
+    // read in a REPL session. See `parse_source`.
+    pub source: usize,
+}
+
+impl Span {
+    // The span from the start of this one to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// A token or tree node with the span it was read from. Spans are left out of
+// comparisons, so the same tree read from different places compares equal.
+#[derive(Clone, Debug)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T> From<T> for Spanned<T> {
+    fn from(node: T) -> Self {
+        Spanned::new(node, Span::default())
+    }
+}
+
+// The offsets at which the lines of a source start, for turning byte offsets
+// into lines and columns.
+pub struct LineIndex<'a> {
+    source: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { source, starts }
+    }
+
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let line = self.starts.partition_point(|&s| s <= start);
+        let line_start = self.starts[line - 1];
+        let column = self.source[line_start..start].chars().count() + 1;
+        Span {
+            start,
+            end,
+            line,
+            column,
+            source: 0,
+        }
+    }
+
+    // The text of line `line`, without its line break, or None if the source
+    // has no such line.
+    pub fn line(&self, line: usize) -> Option<&'a str> {
+        let start = *self.starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .starts
+            .get(line)
+            .map_or(self.source.len(), |next| next - 1);
+        Some(self.source[start..end].trim_end_matches('\r'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_and_columns() {
+        let index = LineIndex::new("(a\n  bé c)\n");
+
+        assert_eq!(index.span(0, 1).to_string(), "1:1");
+        assert_eq!(index.span(5, 6).to_string(), "2:3");
+        // `é` is two bytes but one column.
+        assert_eq!(index.span(9, 10).to_string(), "2:6");
+        assert_eq!(index.line(2), Some("  bé c)"));
+        assert_eq!(index.line(3), Some(""));
+        assert_eq!(index.line(4), None);
+        assert_eq!(index.line(0), None);
+    }
+}
@@ -0,0 +1,331 @@
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+
+use crate::env::Env;
+use crate::eval::EvalError;
+use crate::expand::Macro;
+use crate::parser::AST;
+use crate::span::{Span, Spanned};
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    // The result of forms evaluated only for their effect, such as `define`.
+    Void,
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Str(Rc<str>),
+    Symbol(Rc<str>),
+    Pair(Rc<Pair>),
+    Builtin(&'static Builtin),
+    Procedure(Rc<Procedure>),
+    // Only the expander uses these, on the forms that name them.
+    Macro(Rc<Macro>),
+}
+
+#[derive(Debug)]
+pub struct Pair {
+    pub car: Value,
+    pub cdr: Value,
+}
+
+// Unlinks the rest of a list one pair at a time, as dropping it recursively
+// would overflow the stack on long lists.
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut next = mem::replace(&mut self.cdr, Value::Nil);
+        while let Value::Pair(pair) = next {
+            match Rc::try_unwrap(pair) {
+                Ok(mut pair) => next = mem::replace(&mut pair.cdr, Value::Nil),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            Arity::Exactly(m) => n == m,
+            Arity::AtLeast(min) => n >= min,
+            Arity::Between(min, max) => (min..=max).contains(&n),
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Exactly(1) => write!(f, "1 argument"),
+            Arity::Exactly(n) => write!(f, "{} arguments", n),
+            Arity::AtLeast(1) => write!(f, "at least 1 argument"),
+            Arity::AtLeast(n) => write!(f, "at least {} arguments", n),
+            Arity::Between(min, max) => write!(f, "{} to {} arguments", min, max),
+        }
+    }
+}
+
+// A procedure written in Rust. Arguments are counted before `func` is called.
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub func: fn(&[Value]) -> Result<Value, EvalError>,
+}
+
+// A procedure made by `lambda`, closing over the environment it was made in.
+pub struct Procedure {
+    pub params: Vec<String>,
+    // Bound to a list of any arguments after `params`.
+    pub rest: Option<String>,
+    pub body: Rc<[Spanned<AST>]>,
+    pub env: Env,
+}
+
+impl Procedure {
+    pub fn arity(&self) -> Arity {
+        match self.rest {
+            Some(_) => Arity::AtLeast(self.params.len()),
+            None => Arity::Exactly(self.params.len()),
+        }
+    }
+}
+
+impl fmt::Debug for Procedure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Procedure")
+            .field("params", &self.params)
+            .field("rest", &self.rest)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Value {
+    pub fn cons(car: Value, cdr: Value) -> Value {
+        Value::Pair(Rc::new(Pair { car, cdr }))
+    }
+
+    pub fn list(items: impl IntoIterator<Item = Value>) -> Value {
+        let items: Vec<Value> = items.into_iter().collect();
+        items
+            .into_iter()
+            .rev()
+            .fold(Value::Nil, |list, item| Value::cons(item, list))
+    }
+
+    pub fn symbol(name: &str) -> Value {
+        Value::Symbol(name.into())
+    }
+
+    // The datum a piece of source stands for, as `quote` returns it.
+    pub fn from_ast(ast: &AST) -> Value {
+        match ast {
+            AST::Integer(i) => Value::Integer(*i),
+            AST::Float(f) => Value::Float(*f),
+            AST::Str(s) => Value::Str(s.as_str().into()),
+            AST::Bool(b) => Value::Bool(*b),
+            AST::Symbol(s) => Value::symbol(s),
+            AST::List(items) => Value::list(items.iter().map(|item| Value::from_ast(&item.node))),
+            AST::Lambda(params, body) => {
+                let params = Value::list(params.iter().map(|p| Value::symbol(p)));
+                let body = body.iter().map(|form| Value::from_ast(&form.node));
+                Value::list([Value::symbol("lambda"), params].into_iter().chain(body))
+            }
+        }
+    }
+
+    // The form `quote` would give this datum for, with every node at `span`.
+    // An improper list ends in ".", tail as a lambda's rest parameter does.
+    // Values that cannot be written, such as procedures, give None.
+    pub fn to_ast(&self, span: Span) -> Option<Spanned<AST>> {
+        let node = match self {
+            Value::Nil => AST::List(vec![]),
+            Value::Bool(b) => AST::Bool(*b),
+            Value::Integer(i) => AST::Integer(*i),
+            Value::Float(f) => AST::Float(*f),
+            Value::Str(s) => AST::Str(s.to_string()),
+            Value::Symbol(s) => AST::Symbol(s.to_string()),
+            Value::Pair(_) => {
+                let mut items = vec![];
+                let mut rest = self;
+                while let Value::Pair(pair) = rest {
+                    items.push(pair.car.to_ast(span)?);
+                    rest = &pair.cdr;
+                }
+                if *rest != Value::Nil {
+                    items.push(Spanned::new(AST::Symbol(".".to_string()), span));
+                    items.push(rest.to_ast(span)?);
+                }
+                AST::List(items)
+            }
+            Value::Void | Value::Builtin(_) | Value::Procedure(_) | Value::Macro(_) => return None,
+        };
+        Some(Spanned::new(node, span))
+    }
+
+    // The elements of a proper list, or None for anything else.
+    pub fn to_vec(&self) -> Option<Vec<Value>> {
+        let mut items = vec![];
+        let mut rest = self;
+        loop {
+            match rest {
+                Value::Nil => return Some(items),
+                Value::Pair(pair) => {
+                    items.push(pair.car.clone());
+                    rest = &pair.cdr;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    // Everything but #f counts as true.
+    pub fn is_true(&self) -> bool {
+        !matches!(self, Value::Bool(false))
+    }
+}
+
+impl PartialEq for Pair {
+    fn eq(&self, other: &Self) -> bool {
+        self.car == other.car && self.cdr == other.cdr
+    }
+}
+
+// Numbers compare by value, strings, symbols and lists by contents, and
+// procedures and macros by identity. Lists are walked along their cdrs in a
+// loop, so only nesting in the cars uses the stack.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut a, mut b) = (self, other);
+        loop {
+            return match (a, b) {
+                (Value::Void, Value::Void) | (Value::Nil, Value::Nil) => true,
+                (Value::Bool(a), Value::Bool(b)) => a == b,
+                (Value::Integer(a), Value::Integer(b)) => a == b,
+                (Value::Float(a), Value::Float(b)) => a == b,
+                (Value::Str(a), Value::Str(b)) | (Value::Symbol(a), Value::Symbol(b)) => a == b,
+                (Value::Pair(x), Value::Pair(y)) => {
+                    if x.car != y.car {
+                        return false;
+                    }
+                    (a, b) = (&x.cdr, &y.cdr);
+                    continue;
+                }
+                (Value::Builtin(a), Value::Builtin(b)) => ptr::eq(*a, *b),
+                (Value::Procedure(a), Value::Procedure(b)) => Rc::ptr_eq(a, b),
+                (Value::Macro(a), Value::Macro(b)) => Rc::ptr_eq(a, b),
+                _ => false,
+            };
+        }
+    }
+}
+
+impl Value {
+    // Writes the value as it would appear in source where it can, or with
+    // strings as their bare text when `quote` is false.
+    fn write(&self, f: &mut fmt::Formatter, quote: bool) -> fmt::Result {
+        match self {
+            Value::Void => write!(f, "#<void>"),
+            Value::Nil => write!(f, "()"),
+            Value::Bool(true) => write!(f, "#t"),
+            Value::Bool(false) => write!(f, "#f"),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Str(s) if quote => write!(f, "{:?}", s),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Symbol(s) => write!(f, "{}", s),
+            Value::Pair(pair) => {
+                write!(f, "(")?;
+                pair.car.write(f, quote)?;
+                let mut rest = &pair.cdr;
+                loop {
+                    match rest {
+                        Value::Nil => break,
+                        Value::Pair(pair) => {
+                            write!(f, " ")?;
+                            pair.car.write(f, quote)?;
+                            rest = &pair.cdr;
+                        }
+                        value => {
+                            write!(f, " . ")?;
+                            value.write(f, quote)?;
+                            break;
+                        }
+                    }
+                }
+                write!(f, ")")
+            }
+            Value::Builtin(builtin) => write!(f, "#<builtin {}>", builtin.name),
+            Value::Procedure(_) => write!(f, "#<procedure>"),
+            Value::Macro(_) => write!(f, "#<macro>"),
+        }
+    }
+
+    // The value as `display` prints it, with strings unquoted.
+    pub fn display(&self) -> Unquoted<'_> {
+        Unquoted(self)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, true)
+    }
+}
+
+pub struct Unquoted<'a>(&'a Value);
+
+impl fmt::Display for Unquoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write(f, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_print_as_source() {
+        let list = Value::list([
+            Value::Integer(1),
+            Value::Float(2.0),
+            Value::Str("a \"b\"\n".into()),
+            Value::list([Value::symbol("x"), Value::Bool(false)]),
+            Value::Nil,
+        ]);
+
+        assert_eq!(list.to_string(), r#"(1 2.0 "a \"b\"\n" (x #f) ())"#);
+        assert_eq!(
+            Value::cons(Value::Integer(1), Value::Integer(2)).to_string(),
+            "(1 . 2)"
+        );
+        assert_eq!(list.display().to_string(), "(1 2.0 a \"b\"\n (x #f) ())");
+    }
+
+    #[test]
+    fn long_lists_drop_without_overflowing() {
+        let list = Value::list((0..1_000_000).map(Value::Integer));
+
+        drop(list);
+    }
+
+    #[test]
+    fn long_lists_compare_without_overflowing() {
+        let list = || Value::list((0..100_000).map(Value::Integer));
+        let longer = Value::list((0..100_001).map(Value::Integer));
+
+        assert!(list() == list());
+        assert!(list() != longer);
+    }
+}
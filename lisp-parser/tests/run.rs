@@ -0,0 +1,90 @@
+// Runs scripts through the binary, as `lisp-parser run file.lisp [args...]`.
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// Writes `source` to a file of its own and runs it with `args`.
+fn run_script(
+    name: &str,
+    source: &str,
+    args: &[&str],
+) -> Result<(PathBuf, Output), Box<dyn Error>> {
+    let path = std::env::temp_dir().join(format!("{}-{}.lisp", name, std::process::id()));
+    fs::write(&path, source)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_lisp-parser"))
+        .arg("run")
+        .arg(&path)
+        .args(args)
+        .output()?;
+    fs::remove_file(&path)?;
+    Ok((path, output))
+}
+
+#[test]
+fn scripts_see_their_arguments_and_set_the_exit_status() -> Result<(), Box<dyn Error>> {
+    let source = "
+        (display (command-line))
+        (newline)
+        (display \"done\")
+        (exit 3)
+        (display \"not reached\")";
+
+    let (path, output) = run_script("arguments", source, &["-v", "two words"])?;
+
+    let expected = format!("({} -v two words)\ndone", path.display());
+    assert_eq!(String::from_utf8(output.stdout)?, expected);
+    assert_eq!(output.status.code(), Some(3));
+
+    Ok(())
+}
+
+#[test]
+fn scripts_that_finish_succeed() -> Result<(), Box<dyn Error>> {
+    let (_, output) = run_script("finish", "(define x 1)\n(+ x 1)", &[])?;
+
+    assert_eq!(String::from_utf8(output.stdout)?, "");
+    assert_eq!(output.status.code(), Some(0));
+
+    Ok(())
+}
+
+#[test]
+fn errors_print_a_diagnostic_and_fail() -> Result<(), Box<dyn Error>> {
+    let (path, output) = run_script("error", "(define (f) (g))\n(f)", &[])?;
+
+    let expected = format!(
+        "error: unbound variable g\n --> {}:1:14\n  |\n1 | (define (f) (g))\n  |              ^\n",
+        path.display()
+    );
+    assert_eq!(String::from_utf8(output.stderr)?, expected);
+    assert_eq!(output.status.code(), Some(1));
+
+    let (_, output) = run_script("parse-error", "(display 1))", &[])?;
+
+    assert!(String::from_utf8(output.stderr)?.starts_with("error: unexpected ')'"));
+    assert_eq!(output.status.code(), Some(1));
+
+    Ok(())
+}
+
+#[test]
+fn missing_files_and_bad_arguments_fail() -> Result<(), Box<dyn Error>> {
+    let binary = env!("CARGO_BIN_EXE_lisp-parser");
+
+    let output = Command::new(binary)
+        .args(["run", "/no/such/file.lisp"])
+        .output()?;
+    assert!(String::from_utf8(output.stderr)?.starts_with("could not read /no/such/file.lisp"));
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = Command::new(binary).arg("walk").output()?;
+    assert_eq!(
+        String::from_utf8(output.stderr)?,
+        "usage: lisp-parser [run <file> [args...]]\n"
+    );
+    assert_eq!(output.status.code(), Some(2));
+
+    Ok(())
+}